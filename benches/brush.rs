@@ -0,0 +1,44 @@
+//! Compares stamping a large ellipse brush by solving its rows on every stamp, as
+//! `Brush::pixels` does, against compiling it once and reusing the `CompiledBrush`, as the
+//! editor does during a stroke.
+//!
+//! Run with `cargo bench --bench brush`.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use egui_pixel_editor::{Brush, CompiledBrush};
+
+const STAMPS: isize = 1000;
+
+fn time(name: &str, stamp: impl Fn(isize, &mut usize)) -> Duration {
+    let mut covered = 0;
+    let start = Instant::now();
+    for i in 0..STAMPS {
+        stamp(i, &mut covered);
+    }
+    let elapsed = start.elapsed() / STAMPS as u32;
+    black_box(covered);
+    println!("{name:>10}: {elapsed:?} per stamp");
+    elapsed
+}
+
+fn main() {
+    let brush = Brush::Ellipse(50, 50);
+
+    let per_stamp = time("per stamp", |x, covered| {
+        black_box(&brush).pixels(x, 0, |_, _| *covered += 1);
+    });
+
+    let compiled = CompiledBrush::new(brush);
+    let cached = time("cached", |x, covered| {
+        black_box(&compiled).pixels(x, 0, |_, _| *covered += 1);
+    });
+
+    println!(
+        "speedup: {:.2}x",
+        per_stamp.as_secs_f64() / cached.as_secs_f64()
+    );
+}
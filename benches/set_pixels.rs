@@ -0,0 +1,56 @@
+//! Compares writing a large area pixel by pixel against one batched `set_pixels` call and
+//! against `ImageExt::fill_rect`, which passes whole rows down with `Image::fill_row`, all
+//! routed through the editor's undo and tile trackers. `set_pixels` comes out slower here:
+//! each adapter in the stack adds a dynamic iterator call per pixel, which costs more than
+//! the bookkeeping it saves, so it pays off for scattered writes rather than solid fills.
+//!
+//! Run with `cargo bench --bench set_pixels`.
+
+use std::time::{Duration, Instant};
+
+use egui::{Color32, ColorImage};
+use egui_pixel_editor::{
+    image::{Image, ImageExt},
+    ImageEditor,
+};
+
+const SIZE: usize = 2048;
+
+fn time(name: &str, f: impl Fn(&mut dyn Image<Pixel = Color32>, Color32)) -> Duration {
+    let mut image = ColorImage::new([SIZE, SIZE], Color32::BLACK);
+    let mut editor = ImageEditor::<Color32>::from_tile_size(512);
+
+    let start = Instant::now();
+    for color in [Color32::RED, Color32::GREEN, Color32::BLUE] {
+        editor.apply(&mut image, |image| f(image, color));
+    }
+    let elapsed = start.elapsed() / 3;
+    println!("{name:>10}: {elapsed:?} per {SIZE}x{SIZE} fill");
+    elapsed
+}
+
+fn main() {
+    let per_pixel = time("set_pixel", |image, color| {
+        for y in 0..SIZE as isize {
+            for x in 0..SIZE as isize {
+                image.set_pixel(x, y, color);
+            }
+        }
+    });
+
+    let batched = time("set_pixels", |image, color| {
+        let size = SIZE as isize;
+        let mut pixels = (0..size).flat_map(|y| (0..size).map(move |x| (x, y, color)));
+        image.set_pixels(&mut pixels);
+    });
+
+    let fill_rect = time("fill_rect", |image, color| {
+        let last = SIZE as isize - 1;
+        image.fill_rect(0..=last, 0..=last, color);
+    });
+
+    for (name, elapsed) in [("set_pixels", batched), ("fill_rect", fill_rect)] {
+        let speedup = per_pixel.as_secs_f64() / elapsed.as_secs_f64();
+        println!("{name:>10} speedup: {speedup:.2}x");
+    }
+}
@@ -1,5 +1,23 @@
-use egui::{CentralPanel, Color32, ColorImage, DragValue, Rect, Scene};
-use egui_pixel_editor::{Brush, ImageEditor};
+use egui::{
+    CentralPanel, Color32, ColorImage, DragValue, Rect, Scene, TextureFilter, TextureOptions,
+};
+use egui_pixel_editor::{image::Buffer2D, Brush, Dither, ImageEditor, Tool};
+
+#[derive(PartialEq, Copy, Clone)]
+enum Mode {
+    Ellipse,
+    Rectangle,
+    Diamond,
+    Triangle,
+    RectOutline,
+    EllipseOutline,
+    Line,
+    Gradient,
+    Smudge,
+    Stamp,
+    Fill,
+    Eyedropper,
+}
 
 fn main() {
     let mut image = ColorImage::new([1000, 1000], Color32::BLACK);
@@ -8,13 +26,19 @@ fn main() {
     let mut scene_rect = Rect::ZERO;
 
     let mut color = Color32::WHITE;
+    let mut right_color = Color32::BLACK;
+    let mut gradient_end = Color32::BLACK;
+    let mut dither = Dither::None;
+    let mut tolerance = 0_u8;
 
     let mut editor = None;
 
-    let mut mode = false;
+    let mut mode = Mode::Ellipse;
     let mut brush_width = 1_isize;
     let mut brush_height = 1_isize;
     let mut square_brush = false;
+    let mut brush_degrees = 0.0_f32;
+    let mut smooth_zoom_out = false;
 
     eframe::run_simple_native("image editor", Default::default(), move |ctx, _frame| {
         let editor = editor.get_or_insert_with(|| ImageEditor::new(ctx));
@@ -24,10 +48,22 @@ fn main() {
             ui.horizontal(|ui| {
                 ui.label("Draw color: ");
                 ui.color_edit_button_srgba(&mut color);
+                ui.label("Right click: ");
+                ui.color_edit_button_srgba(&mut right_color);
 
                 ui.label("Brush mode");
-                ui.selectable_value(&mut mode, false, "Ellipse");
-                ui.selectable_value(&mut mode, true, "Rectangle");
+                ui.selectable_value(&mut mode, Mode::Ellipse, "Ellipse");
+                ui.selectable_value(&mut mode, Mode::Rectangle, "Rectangle");
+                ui.selectable_value(&mut mode, Mode::Diamond, "Diamond");
+                ui.selectable_value(&mut mode, Mode::Triangle, "Triangle");
+                ui.selectable_value(&mut mode, Mode::RectOutline, "Rect outline");
+                ui.selectable_value(&mut mode, Mode::EllipseOutline, "Ellipse outline");
+                ui.selectable_value(&mut mode, Mode::Line, "Line");
+                ui.selectable_value(&mut mode, Mode::Gradient, "Gradient");
+                ui.selectable_value(&mut mode, Mode::Smudge, "Smudge");
+                ui.selectable_value(&mut mode, Mode::Stamp, "Stamp");
+                ui.selectable_value(&mut mode, Mode::Fill, "Fill");
+                ui.selectable_value(&mut mode, Mode::Eyedropper, "Eyedropper");
 
                 ui.label("Brush size");
                 ui.add(DragValue::new(&mut brush_width).range(0..=isize::MAX));
@@ -35,23 +71,108 @@ fn main() {
                     ui.label("x");
                     ui.add(DragValue::new(&mut brush_height).range(0..=isize::MAX));
                 });
-                ui.checkbox(&mut square_brush, "Square brush")
+                ui.checkbox(&mut square_brush, "Square brush");
+                ui.label("Rotation");
+                ui.add(DragValue::new(&mut brush_degrees).range(0.0..=360.0));
+                ui.label("Spacing");
+                ui.add(DragValue::new(&mut editor.brush_spacing).range(1.0..=100.0));
+
+                ui.checkbox(&mut smooth_zoom_out, "Smooth when zoomed out");
+                ui.checkbox(&mut editor.read_only, "View only");
+
+                if mode == Mode::Gradient || mode == Mode::Stamp {
+                    ui.label("Second color: ");
+                    ui.color_edit_button_srgba(&mut gradient_end);
+                    ui.label("Dither");
+                    ui.selectable_value(&mut dither, Dither::None, "None");
+                    ui.selectable_value(&mut dither, Dither::Bayer2x2, "2x2");
+                    ui.selectable_value(&mut dither, Dither::Bayer4x4, "4x4");
+                    ui.selectable_value(&mut dither, Dither::Bayer8x8, "8x8");
+                }
+                if mode == Mode::Fill {
+                    ui.label("Tolerance");
+                    ui.add(DragValue::new(&mut tolerance));
+                }
             });
 
             if square_brush {
                 brush_height = brush_width;
             }
 
-            let brush = match mode {
-                false => Brush::Ellipse(brush_width, brush_height),
-                true => Brush::Rectangle(brush_width, brush_height),
+            // Nearest when zoomed in so pixels stay crisp, mipmapped when zoomed out to avoid
+            // aliasing
+            let texture_options = if smooth_zoom_out {
+                TextureOptions {
+                    minification: TextureFilter::Linear,
+                    mipmap_mode: Some(TextureFilter::Linear),
+                    ..TextureOptions::NEAREST
+                }
+            } else {
+                TextureOptions::NEAREST
+            };
+            editor.set_texture_options(texture_options);
+
+            // A checkered diamond in both colors, which tiles with a spacing of 7
+            editor.stamp = Buffer2D {
+                data: (0..49)
+                    .map(|i| {
+                        let (x, y) = (i % 7 - 3_i32, i / 7 - 3);
+                        let checker = if (x + y) % 2 == 0 {
+                            color
+                        } else {
+                            gradient_end
+                        };
+                        (x.abs() + y.abs() <= 3).then_some(checker)
+                    })
+                    .collect(),
+                width: 7,
+                height: 7,
+            };
+
+            let tool = match mode {
+                Mode::Ellipse => Tool::Brush(Brush::RotatedEllipse(
+                    brush_width,
+                    brush_height,
+                    brush_degrees.to_radians(),
+                )),
+                Mode::Rectangle => Tool::Brush(Brush::RotatedRectangle(
+                    brush_width,
+                    brush_height,
+                    brush_degrees.to_radians(),
+                )),
+                Mode::Diamond => Tool::Brush(Brush::Diamond(brush_width, brush_height)),
+                Mode::Triangle => Tool::Brush(Brush::Triangle(brush_width, brush_height)),
+                Mode::RectOutline => Tool::RectOutline,
+                Mode::EllipseOutline => Tool::EllipseOutline,
+                Mode::Line => Tool::Line(Brush::Ellipse(brush_width, brush_height)),
+                Mode::Gradient => Tool::Gradient {
+                    end: gradient_end,
+                    dither,
+                },
+                Mode::Smudge => Tool::Smudge {
+                    brush: Brush::Ellipse(brush_width, brush_height),
+                    strength: 0.5,
+                },
+                Mode::Stamp => Tool::Stamp,
+                Mode::Fill => Tool::Fill { tolerance },
+                Mode::Eyedropper => Tool::Eyedropper,
             };
 
             egui::Frame::canvas(ui.style()).show(ui, |ui| {
                 Scene::new()
                     .zoom_range(0.1..=100.0)
                     .show(ui, &mut scene_rect, |ui| {
-                        editor.edit(ui, &mut image, color, brush);
+                        let resp =
+                            editor.edit_with_secondary(ui, &mut image, color, right_color, tool);
+                        if mode == Mode::Eyedropper
+                            && let Some(picked) = resp.hover.and_then(|hover| hover.color)
+                        {
+                            if resp.egui_response.clicked() {
+                                color = picked;
+                            } else if resp.egui_response.secondary_clicked() {
+                                right_color = picked;
+                            }
+                        }
                     });
             });
         });
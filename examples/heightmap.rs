@@ -0,0 +1,63 @@
+//! Editing a heightmap, where each pixel is an `f32` rather than a color.
+//!
+//! Any type which is `PartialEq + Clone` can be a pixel; `PixelInterface` decides how it is
+//! displayed, and optionally how gradients blend it.
+use egui::{CentralPanel, Color32, Rect, Scene, Slider};
+use egui_pixel_editor::{
+    image::{Buffer2D, PixelInterface},
+    Brush, Dither, ImageEditor, Tool,
+};
+
+#[derive(Copy, Clone, PartialEq)]
+struct HeightPixel(f32);
+
+impl PixelInterface for HeightPixel {
+    /// Heights from 0 to 1 are shown from black to white
+    fn as_rgba(&self) -> Color32 {
+        Color32::from_gray((self.0.clamp(0.0, 1.0) * 255.0).round() as u8)
+    }
+
+    fn lerp(&self, other: &Self, t: f32, _threshold: f32) -> Self {
+        HeightPixel(self.0 + (other.0 - self.0) * t)
+    }
+}
+
+fn main() {
+    let mut image = Buffer2D::new(256, 256, HeightPixel(0.0));
+
+    let mut scene_rect = Rect::ZERO;
+    let mut editor = None;
+    let mut height = 0.5;
+    let mut gradient = false;
+
+    eframe::run_simple_native("heightmap", Default::default(), move |ctx, _frame| {
+        let editor = editor.get_or_insert_with(|| ImageEditor::new(ctx));
+
+        CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Heightmap Editor");
+            ui.horizontal(|ui| {
+                ui.label("Height: ");
+                ui.add(Slider::new(&mut height, 0.0..=1.0));
+                ui.checkbox(&mut gradient, "Gradient to zero");
+            });
+
+            let tool = if gradient {
+                Tool::Gradient {
+                    end: HeightPixel(0.0),
+                    dither: Dither::None,
+                }
+            } else {
+                Tool::Brush(Brush::Ellipse(5, 5))
+            };
+
+            egui::Frame::canvas(ui.style()).show(ui, |ui| {
+                Scene::new()
+                    .zoom_range(0.1..=100.0)
+                    .show(ui, &mut scene_rect, |ui| {
+                        editor.edit(ui, &mut image, HeightPixel(height), tool);
+                    });
+            });
+        });
+    })
+    .unwrap();
+}
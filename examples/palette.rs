@@ -0,0 +1,94 @@
+//! Editing an indexed-color image, where each pixel is an index into a shared palette.
+//!
+//! `PixelInterface::as_rgba` only receives the pixel, so the palette has to live somewhere
+//! the pixel can reach it. Here it is a thread local, which works because egui draws on a
+//! single thread; an `Arc<RwLock<..>>` cloned into a static would work just as well.
+use std::cell::RefCell;
+
+use egui::{CentralPanel, Color32, Rect, Scene};
+use egui_pixel_editor::{
+    image::{Buffer2D, PixelInterface},
+    Brush, ImageEditor,
+};
+
+thread_local! {
+    static PALETTE: RefCell<Vec<Color32>> = RefCell::new(vec![
+        Color32::from_rgb(0x1a, 0x1c, 0x2c),
+        Color32::from_rgb(0x5d, 0x27, 0x5d),
+        Color32::from_rgb(0xb1, 0x3e, 0x53),
+        Color32::from_rgb(0xef, 0x7d, 0x57),
+        Color32::from_rgb(0xff, 0xcd, 0x75),
+        Color32::from_rgb(0xa7, 0xf0, 0x70),
+        Color32::from_rgb(0x38, 0xb7, 0x64),
+        Color32::from_rgb(0x25, 0x71, 0x79),
+    ]);
+}
+
+#[derive(Copy, Clone, PartialEq)]
+struct PaletteIndex(u8);
+
+impl PixelInterface for PaletteIndex {
+    fn as_rgba(&self) -> Color32 {
+        PALETTE.with_borrow(|palette| {
+            palette
+                .get(self.0 as usize)
+                .copied()
+                .unwrap_or(Color32::TRANSPARENT)
+        })
+    }
+
+    /// Borrows the palette once per row of a tile rather than once per pixel
+    fn as_rgba_row(pixels: &[Self], out: &mut [Color32]) {
+        PALETTE.with_borrow(|palette| {
+            for (px, out) in pixels.iter().zip(out) {
+                *out = palette
+                    .get(px.0 as usize)
+                    .copied()
+                    .unwrap_or(Color32::TRANSPARENT);
+            }
+        })
+    }
+}
+
+fn main() {
+    let mut image = Buffer2D::new(256, 256, PaletteIndex(0));
+
+    let mut scene_rect = Rect::ZERO;
+    let mut editor = None;
+    let mut selected = PaletteIndex(4);
+
+    eframe::run_simple_native("palette editor", Default::default(), move |ctx, _frame| {
+        let editor = editor.get_or_insert_with(|| ImageEditor::new_for_image(ctx, (256, 256)));
+
+        CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Indexed Image Editor");
+
+            let mut palette_changed = false;
+            ui.horizontal(|ui| {
+                ui.label("Palette: ");
+                PALETTE.with_borrow_mut(|palette| {
+                    for (idx, color) in palette.iter_mut().enumerate() {
+                        let idx = PaletteIndex(idx as u8);
+                        ui.radio_value(&mut selected, idx, "");
+                        palette_changed |= ui.color_edit_button_srgba(color).changed();
+                    }
+                });
+            });
+
+            // The pixels themselves didn't change, so the editor can't know the textures
+            // are stale; tell it to sample every tile again
+            if palette_changed {
+                editor.invalidate_all();
+            }
+
+            egui::Frame::canvas(ui.style()).show(ui, |ui| {
+                Scene::new()
+                    .zoom_range(0.1..=100.0)
+                    .show(ui, &mut scene_rect, |ui| {
+                        editor.edit(ui, &mut image, selected, Brush::Ellipse(3, 3));
+                    });
+            });
+        });
+    })
+    .unwrap();
+}
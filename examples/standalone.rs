@@ -0,0 +1,45 @@
+//! Editing without an `egui::Scene`, letting the editor pan and zoom the canvas itself.
+//!
+//! Drag with the middle button or with space held to pan, scroll to pan, and pinch or
+//! Ctrl+scroll to zoom. The offset and zoom are plain fields, so the controls at the top
+//! can show and change them too.
+use egui::{CentralPanel, Color32, ColorImage, DragValue};
+use egui_pixel_editor::{Brush, ImageEditor};
+
+fn main() {
+    let mut image = ColorImage::new([128, 128], Color32::BLACK);
+
+    let mut editor = None;
+    let mut fit = true;
+
+    eframe::run_simple_native("standalone", Default::default(), move |ctx, _frame| {
+        let editor = editor.get_or_insert_with(|| {
+            let mut editor = ImageEditor::new(ctx);
+            editor.pan_and_zoom = true;
+            editor
+        });
+
+        CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Standalone Image Editor");
+            ui.horizontal(|ui| {
+                ui.label("Offset");
+                ui.add(DragValue::new(&mut editor.pan.x));
+                ui.add(DragValue::new(&mut editor.pan.y));
+                ui.label("Zoom");
+                let zoom = DragValue::new(&mut editor.zoom).speed(0.1);
+                ui.add(zoom.range(0.1..=100.0));
+                fit |= ui.button("Fit").clicked();
+            });
+
+            egui::Frame::canvas(ui.style()).show(ui, |ui| {
+                if fit {
+                    let dims = (image.size[0], image.size[1]);
+                    editor.fit(ui.available_rect_before_wrap(), dims);
+                    fit = false;
+                }
+                editor.edit(ui, &mut image, Color32::WHITE, Brush::Ellipse(3, 3));
+            });
+        });
+    })
+    .unwrap();
+}
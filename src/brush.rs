@@ -8,46 +8,130 @@ use egui::{
     Painter, Pos2, Rect, Sense, Stroke, StrokeKind, TextureId, TextureOptions, Ui, Vec2, Widget,
 };
 
-use crate::ellipse;
+use crate::{
+    ellipse,
+    image::{Buffer2D, Image, ImageExt, PixelInterface},
+};
 
-#[derive(Copy, Clone)]
+/// The footprint painted at each point of a stroke. Sizes larger than [`Brush::MAX_SIZE`]
+/// are drawn at that size.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Brush {
     /// Width, Height
     Ellipse(isize, isize),
     /// Width, Height
     Rectangle(isize, isize),
+    /// Width, Height. Fills where `|dx| / width + |dy| / height <= 1`.
+    Diamond(isize, isize),
+    /// Width, Height. An isosceles triangle pointing up, with its base along the bottom of
+    /// the bounding box.
+    Triangle(isize, isize),
+    /// Width, Height, rotation in radians (clockwise on screen)
+    RotatedEllipse(isize, isize, f32),
+    /// Width, Height, rotation in radians (clockwise on screen)
+    RotatedRectangle(isize, isize, f32),
+    /// An arbitrary footprint, whose center (rounding down) lands on the pointer. To paint
+    /// the stamp's own colors instead of the draw color, see [`crate::Tool::Stamp`].
+    Stamp(StampMask),
+}
+
+/// The footprint of a [`Brush::Stamp`], up to [`StampMask::MAX_SIZE`] cells on a side.
+/// It is stored inline so that brushes stay `Copy`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StampMask {
+    /// One bit per cell, the lowest for the leftmost column
+    rows: [u32; StampMask::MAX_SIZE],
+    width: u8,
+    height: u8,
+}
+
+impl StampMask {
+    pub const MAX_SIZE: usize = 32;
+
+    /// The footprint `width` by `height` cells in row-major order, which covers the cells
+    /// where `mask` is `true`. `None` if `mask` isn't `width * height` long, or either
+    /// side is larger than [`Self::MAX_SIZE`].
+    pub fn new(mask: &[bool], width: usize, height: usize) -> Option<Self> {
+        if mask.len() != width * height || width.max(height) > Self::MAX_SIZE {
+            return None;
+        }
+        let mut rows = [0; Self::MAX_SIZE];
+        for (i, _) in mask.iter().enumerate().filter(|(_, set)| **set) {
+            rows[i / width] |= 1 << (i % width);
+        }
+        Some(Self {
+            rows,
+            width: width as u8,
+            height: height as u8,
+        })
+    }
+
+    // Limited again here, in case a deserialized mask claims to be larger
+    pub fn width(&self) -> usize {
+        (self.width as usize).min(Self::MAX_SIZE)
+    }
+
+    pub fn height(&self) -> usize {
+        (self.height as usize).min(Self::MAX_SIZE)
+    }
+
+    /// Whether the cell in column `x` of row `y` is covered. Cells outside the mask aren't.
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        x < self.width() && y < self.height() && (self.rows[y] >> x) & 1 != 0
+    }
 }
 
 impl Brush {
-    pub fn pixels(&self, x: isize, y: isize, mut f: impl FnMut(isize, isize)) {
-        match *self {
-            Brush::Ellipse(wx, wy) => {
-                for dy in -wy..=wy {
-                    // Note: the ellipse is on its side here ...
-                    let mx = ellipse::solve_ellipse(wy, wx, dy);
+    /// The largest width or height a brush is drawn with, beyond which solving its rows
+    /// would overflow
+    pub const MAX_SIZE: isize = ellipse::MAX_RADIUS;
 
-                    for dx in -mx..=mx {
-                        f(x + dx, y + dy);
-                    }
-                    
-                }
-            }
-            Brush::Rectangle(wx, wy) => {
-                for dy in -wy..=wy {
-                    for dx in -wx..=wx {
-                        f(x + dx, y + dy);
-                    }
-                }
-            }
+    /// Calls `f` on each pixel of the brush stamped at `(x, y)`. To stamp the same brush
+    /// many times, use a [`CompiledBrush`] instead of solving its rows on every call.
+    pub fn pixels(&self, x: isize, y: isize, f: impl FnMut(isize, isize)) {
+        CompiledBrush::new(*self).pixels(x, y, f)
+    }
+
+    /// The same brush with its width and height multiplied by `factor` and rounded, e.g.
+    /// for pen pressure. Stamps have a fixed size, so they are returned unchanged.
+    pub fn scaled(&self, factor: f32) -> Brush {
+        self.map_sizes(|size| (size as f32 * factor).round().max(0.0) as isize)
+    }
+
+    /// The same brush with its full width and height, both sides of the center included,
+    /// limited to `max` pixels, so that a huge size can't make each stamp visit billions
+    /// of pixels. Stamps are returned unchanged.
+    pub fn clamped(&self, max: isize) -> Brush {
+        // A half-width of `size` covers `2 * size + 1` pixels
+        self.map_sizes(|size| size.min((max - 1).max(0) / 2))
+    }
+
+    /// The same brush with `f` applied to its width and height. Stamps are unchanged.
+    fn map_sizes(&self, f: impl Fn(isize) -> isize) -> Brush {
+        match *self {
+            Brush::Ellipse(wx, wy) => Brush::Ellipse(f(wx), f(wy)),
+            Brush::Rectangle(wx, wy) => Brush::Rectangle(f(wx), f(wy)),
+            Brush::Diamond(wx, wy) => Brush::Diamond(f(wx), f(wy)),
+            Brush::Triangle(wx, wy) => Brush::Triangle(f(wx), f(wy)),
+            Brush::RotatedEllipse(wx, wy, angle) => Brush::RotatedEllipse(f(wx), f(wy), angle),
+            Brush::RotatedRectangle(wx, wy, angle) => Brush::RotatedRectangle(f(wx), f(wy), angle),
+            Brush::Stamp(_) => *self,
         }
     }
 
     pub fn draw(&self, paint: &Painter, pos: Pos2) {
-        let stroke = Stroke::new(0.1, Color32::LIGHT_GRAY);
+        self.draw_zoomed(paint, pos, 1.0)
+    }
+
+    /// Like [`Self::draw`], for a canvas drawn with each pixel `zoom` points wide
+    pub fn draw_zoomed(&self, paint: &Painter, pos: Pos2, zoom: f32) {
+        let stroke = outline_stroke(zoom);
         match *self {
             Brush::Rectangle(wx, wy) => {
-                let v = Vec2::new(wx as f32, wy as f32);
-                let rect = Rect::from_min_max(pos - v, pos + v + Vec2::splat(1.0));
+                let v = Vec2::new(wx as f32, wy as f32) * zoom;
+                let rect = Rect::from_min_max(pos - v, pos + v + Vec2::splat(zoom));
                 paint.rect_stroke(
                     rect,
                     0.,
@@ -61,8 +145,8 @@ impl Brush {
                 let mirror = |v: Vec2| Vec2::new(v.x, -v.y+1.);
 
                 let smart_line = |a: Vec2, b: Vec2| {
-                    paint.line_segment([pos + a, pos + b], stroke);
-                    paint.line_segment([pos + mirror(a), pos + mirror(b)], stroke);
+                    paint.line_segment([pos + a * zoom, pos + b * zoom], stroke);
+                    paint.line_segment([pos + mirror(a) * zoom, pos + mirror(b) * zoom], stroke);
                 };
 
                 for dx in -wx..=wx {
@@ -82,12 +166,450 @@ impl Brush {
                 let b = Vec2::new((wx + 1) as f32, 0.0);
                 smart_line(a, b);
             },
+            Brush::Diamond(..)
+            | Brush::Triangle(..)
+            | Brush::RotatedEllipse(..)
+            | Brush::RotatedRectangle(..)
+            | Brush::Stamp(_) => CompiledBrush::new(*self).draw(paint, pos, zoom),
         }
     }
 }
 
+/// A brush with the spans of each of its rows worked out ahead of time, so that each stamp
+/// is a plain loop. [`crate::ImageEditor`] keeps the last one it used, and only compiles
+/// it again when the brush changes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompiledBrush {
+    brush: Brush,
+    /// Offset of the first row from the center
+    top: isize,
+    /// First and last offsets from the center of each run of pixels, for each row from
+    /// the top to the bottom. Runs within a row are sorted and never touch.
+    rows: Vec<Vec<(isize, isize)>>,
+}
+
+impl CompiledBrush {
+    /// Works out the rows of `brush`. Stamping still has to visit every pixel, which costs
+    /// more than solving the rows, so caching saves around a third of each stamp
+    pub fn new(brush: Brush) -> Self {
+        let brush = brush.map_sizes(|size| size.min(Brush::MAX_SIZE));
+        let symmetric = |wy: isize, half_width: &dyn Fn(isize) -> isize| Self {
+            brush,
+            top: -wy,
+            rows: (-wy..=wy)
+                .map(|dy| {
+                    let mx = half_width(dy);
+                    if mx < 0 {
+                        vec![]
+                    } else {
+                        vec![(-mx, mx)]
+                    }
+                })
+                .collect(),
+        };
+        match brush {
+            // Note: the ellipse is on its side here ...
+            Brush::Ellipse(wx, wy) => symmetric(wy, &|dy| ellipse::solve_ellipse(wy, wx, dy)),
+            Brush::Rectangle(wx, wy) => symmetric(wy, &|_| wx),
+            Brush::Diamond(wx, wy) => symmetric(wy, &|dy| match wy {
+                0 => wx,
+                _ => wx * (wy - dy.abs()) / wy,
+            }),
+            // Widens from nothing at the top row to the full width at the bottom, rounding
+            Brush::Triangle(wx, wy) => symmetric(wy, &|dy| match wy {
+                0 => wx,
+                _ => (wx * (dy + wy) + wy) / (2 * wy),
+            }),
+            Brush::RotatedEllipse(wx, wy, rotation) => {
+                // The same inequality as `ellipse::ellipse`, on its side like above
+                let (wx2, wy2) = ((wx * wx) as f64, (wy * wy) as f64);
+                Self::rotated(brush, wx, wy, rotation, |u, v| {
+                    u.abs() <= wx as f64
+                        && v.abs() <= wy as f64
+                        && u * u * wy2 + v * v * wx2 <= wx2 * wy2
+                })
+            }
+            Brush::RotatedRectangle(wx, wy, rotation) => {
+                Self::rotated(brush, wx, wy, rotation, |u, v| {
+                    u.abs() <= wx as f64 && v.abs() <= wy as f64
+                })
+            }
+            Brush::Stamp(mask) => Self {
+                brush,
+                top: -(mask.height() as isize / 2),
+                rows: mask_rows(mask.width(), mask.height(), |x, y| mask.get(x, y)),
+            },
+        }
+    }
+
+    /// Collects the pixels whose centers, rotated back by `rotation`, land inside the
+    /// unrotated shape of size `wx` by `wy`
+    fn rotated(
+        brush: Brush,
+        wx: isize,
+        wy: isize,
+        rotation: f32,
+        inside: impl Fn(f64, f64) -> bool,
+    ) -> Self {
+        let (sin, cos) = (rotation as f64).sin_cos();
+        // Rotations by multiples of 90 degrees land within rounding error of other pixel
+        // centers; snap them there so they match the unrotated shape exactly
+        let snap = |t: f64| match (t - t.round()).abs() < 1e-6 {
+            true => t.round(),
+            false => t,
+        };
+        let radius = (wx as f64).hypot(wy as f64).ceil() as isize;
+
+        let (mut top, mut rows) = (None, vec![]);
+        for dy in -radius..=radius {
+            let row = (-radius..=radius).filter(|&dx| {
+                let (dx, dy) = (dx as f64, dy as f64);
+                inside(snap(dx * cos + dy * sin), snap(dy * cos - dx * sin))
+            });
+            let (mut first, mut last) = (None, None);
+            for dx in row {
+                first.get_or_insert(dx);
+                last = Some(dx);
+            }
+            if let (Some(first), Some(last)) = (first, last) {
+                top.get_or_insert(dy);
+                rows.push(vec![(first, last)]);
+            }
+        }
+
+        Self {
+            brush,
+            top: top.unwrap_or(0),
+            rows,
+        }
+    }
+
+    /// Reuses `cache` if it was compiled from `brush`, and recompiles it otherwise
+    pub fn cached(cache: &mut Option<Self>, brush: Brush) -> &Self {
+        if cache.as_ref().map(|compiled| compiled.brush) != Some(brush) {
+            *cache = Some(Self::new(brush));
+        }
+        cache.as_ref().unwrap()
+    }
+
+    /// Calls `f` on each pixel of the brush stamped at `(x, y)`
+    pub fn pixels(&self, x: isize, y: isize, mut f: impl FnMut(isize, isize)) {
+        for (dy, runs) in (self.top..).zip(&self.rows) {
+            for &(first, last) in runs {
+                for dx in first..=last {
+                    f(x + dx, y + dy);
+                }
+            }
+        }
+    }
+
+    /// Calls `f` with the row and the columns of each run of pixels of the brush stamped at
+    /// `(x, y)`, e.g. to fill them with [`Image::fill_row`]
+    pub fn runs(&self, x: isize, y: isize, mut f: impl FnMut(isize, RangeInclusive<isize>)) {
+        for (dy, runs) in (self.top..).zip(&self.rows) {
+            for &(first, last) in runs {
+                f(y + dy, x + first..=x + last);
+            }
+        }
+    }
+
+    /// Outlines the pixels of the brush stamped at the pixel whose corner is at `pos`,
+    /// following the steps between rows, with each pixel `zoom` points wide
+    pub fn draw(&self, paint: &Painter, pos: Pos2, zoom: f32) {
+        draw_rows(paint, pos, zoom, self.top, &self.rows)
+    }
+}
+
+/// Outlines runs of pixels laid out like the rows of a [`CompiledBrush`], starting `top`
+/// rows from the pixel whose corner is at `pos`
+fn draw_rows(paint: &Painter, pos: Pos2, zoom: f32, top: isize, rows: &[Vec<(isize, isize)>]) {
+    let stroke = outline_stroke(zoom);
+    let line = |(ax, ay): (isize, isize), (bx, by): (isize, isize)| {
+        let a = pos + Vec2::new(ax as f32, ay as f32) * zoom;
+        let b = pos + Vec2::new(bx as f32, by as f32) * zoom;
+        paint.line_segment([a, b], stroke);
+    };
+
+    let mut above: &[(isize, isize)] = &[];
+    for (dy, runs) in (top..).zip(rows) {
+        // Between two rows, edges run wherever exactly one of them is covered
+        edges_between(above, runs, |left, right| line((left, dy), (right, dy)));
+        for &(first, last) in runs {
+            line((first, dy), (first, dy + 1));
+            line((last + 1, dy), (last + 1, dy + 1));
+        }
+        above = runs;
+    }
+    let bottom = top + rows.len() as isize;
+    edges_between(above, &[], |left, right| {
+        line((left, bottom), (right, bottom))
+    });
+}
+
+/// The runs of each row of a `width` by `height` footprint covering the cells where
+/// `covered` is true, as offsets from its center (rounding down)
+fn mask_rows(
+    width: usize,
+    height: usize,
+    covered: impl Fn(usize, usize) -> bool,
+) -> Vec<Vec<(isize, isize)>> {
+    (0..height)
+        .map(|y| {
+            let mut runs = vec![];
+            let mut run_start = None;
+            // One past the end, so a run reaching the edge is closed too
+            for x in 0..=width {
+                let dx = x as isize - width as isize / 2;
+                match (x < width && covered(x, y), run_start) {
+                    (true, None) => run_start = Some(dx),
+                    (false, Some(first)) => {
+                        runs.push((first, dx - 1));
+                        run_start = None;
+                    }
+                    _ => (),
+                }
+            }
+            runs
+        })
+        .collect()
+}
+
+/// Calls `f` with the start and end of each stretch covered by exactly one of the two
+/// rows of runs
+fn edges_between(a: &[(isize, isize)], b: &[(isize, isize)], mut f: impl FnMut(isize, isize)) {
+    // Runs in a row never touch, so each boundary toggles whether we're in exactly one
+    // row, and a boundary shared by both rows toggles it twice
+    let mut bounds: Vec<isize> = a
+        .iter()
+        .chain(b)
+        .flat_map(|&(first, last)| [first, last + 1])
+        .collect();
+    bounds.sort_unstable();
+    for pair in bounds.chunks(2) {
+        if pair[0] != pair[1] {
+            f(pair[0], pair[1]);
+        }
+    }
+}
+
+/// Outlines the `Some` pixels of `stamp` centered on the pixel whose corner is at `pos`,
+/// like [`Brush::draw_zoomed`] does for a [`Brush::Stamp`]
+pub(crate) fn draw_stamp<Pixel>(
+    paint: &Painter,
+    pos: Pos2,
+    zoom: f32,
+    stamp: &Buffer2D<Option<Pixel>>,
+) {
+    let covered = |x, y| {
+        stamp
+            .data
+            .get(x + y * stamp.width)
+            .is_some_and(Option::is_some)
+    };
+    let rows = mask_rows(stamp.width, stamp.height, covered);
+    draw_rows(paint, pos, zoom, -(stamp.height as isize / 2), &rows)
+}
+
+/// Writes the `Some` pixels of `stamp` with its center (rounding down) at `(x, y)`
+pub(crate) fn stamp<Pixel: Clone>(
+    image: &mut dyn Image<Pixel = Pixel>,
+    stamp: &Buffer2D<Option<Pixel>>,
+    (x, y): (isize, isize),
+) {
+    let (x0, y0) = (x - stamp.width as isize / 2, y - stamp.height as isize / 2);
+    let (x_range, y_range) = stamp.image_boundaries();
+    for sy in y_range {
+        for sx in x_range.clone() {
+            if let Some(px) = stamp.get_pixel(sx, sy) {
+                image.set_pixel_checked(x0 + sx, y0 + sy, px);
+            }
+        }
+    }
+}
+
+/// Blends the pixels under `brush` at `from` into those under it at `to`, by `strength`.
+/// The pixels at `from` are read before anything is written, so overlapping footprints
+/// don't feed back into themselves.
+pub(crate) fn smudge<Pixel: PixelInterface + Clone>(
+    image: &mut dyn Image<Pixel = Pixel>,
+    brush: &CompiledBrush,
+    from: (isize, isize),
+    to: (isize, isize),
+    strength: f32,
+) {
+    let mut carried = vec![];
+    brush.pixels(from.0, from.1, |x, y| {
+        if let Some(px) = image.get_pixel_checked(x, y) {
+            carried.push((x - from.0, y - from.1, px));
+        }
+    });
+
+    let mut writes = vec![];
+    for (dx, dy, src) in carried {
+        let (x, y) = (to.0 + dx, to.1 + dy);
+        if let Some(dst) = image.get_pixel_checked(x, y) {
+            writes.push((x, y, dst.lerp(&src, strength, 0.5)));
+        }
+    }
+    image.set_pixels(&mut writes.into_iter());
+}
+
+/// The stroke used for brush outlines and other tool previews, on a canvas drawn with each
+/// pixel `zoom` points wide
+pub(crate) fn outline_stroke(zoom: f32) -> Stroke {
+    Stroke::new(0.1 * zoom, Color32::LIGHT_GRAY)
+}
+
 impl Default for Brush {
     fn default() -> Self {
         Self::Rectangle(0, 0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compiled_brush() {
+        for wx in 0..8 {
+            for wy in 0..8 {
+                let mut pixels = HashSet::new();
+                CompiledBrush::new(Brush::Ellipse(wx, wy)).pixels(10, 20, |x, y| {
+                    assert!(pixels.insert((x - 10, y - 20)));
+                });
+                for dy in -wy..=wy {
+                    for dx in -wx..=wx {
+                        let inside = ellipse::ellipse(wy, wx, dy, dx);
+                        assert_eq!(pixels.contains(&(dx, dy)), inside, "{wx}x{wy}");
+                    }
+                }
+
+                let mut count = 0;
+                CompiledBrush::new(Brush::Rectangle(wx, wy)).pixels(0, 0, |_, _| count += 1);
+                assert_eq!(count, (2 * wx + 1) * (2 * wy + 1));
+            }
+        }
+        assert_eq!(CompiledBrush::new(Brush::Rectangle(-1, 2)).rows, [[]; 5]);
+    }
+
+    #[test]
+    fn test_diamond_and_triangle() {
+        let widths = |brush| -> Vec<isize> {
+            let compiled = CompiledBrush::new(brush);
+            compiled.rows.iter().map(|runs| runs[0].1).collect()
+        };
+        assert_eq!(widths(Brush::Diamond(2, 2)), [0, 1, 2, 1, 0]);
+        assert_eq!(widths(Brush::Diamond(4, 2)), [0, 2, 4, 2, 0]);
+        assert_eq!(widths(Brush::Diamond(3, 0)), [3]);
+        assert_eq!(widths(Brush::Triangle(2, 2)), [0, 1, 1, 2, 2]);
+        assert_eq!(widths(Brush::Triangle(4, 1)), [0, 2, 4]);
+        assert_eq!(widths(Brush::Triangle(3, 0)), [3]);
+
+        // Every pixel satisfies the diamond inequality, and no others do
+        for (wx, wy) in [(3, 5), (5, 3), (1, 1)] {
+            let mut count = 0;
+            CompiledBrush::new(Brush::Diamond(wx, wy)).pixels(0, 0, |dx, dy| {
+                assert!(dx.abs() * wy + dy.abs() * wx <= wx * wy);
+                count += 1;
+            });
+            let inside = (-wy..=wy)
+                .flat_map(|dy| (-wx..=wx).map(move |dx| (dx, dy)))
+                .filter(|(dx, dy)| dx.abs() * wy + dy.abs() * wx <= wx * wy)
+                .count();
+            assert_eq!(count, inside);
+        }
+    }
+
+    fn footprint(brush: Brush) -> HashSet<(isize, isize)> {
+        let mut pixels = HashSet::new();
+        CompiledBrush::new(brush).pixels(0, 0, |x, y| {
+            assert!(pixels.insert((x, y)));
+        });
+        pixels
+    }
+
+    #[test]
+    fn test_rotated_brushes() {
+        use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+        for (wx, wy) in [(3, 1), (5, 2), (0, 4), (2, 2)] {
+            for (rotation, turned) in [
+                (0.0, false),
+                (FRAC_PI_2, true),
+                (PI, false),
+                (-FRAC_PI_2, true),
+            ] {
+                let (ex, ey) = if turned { (wy, wx) } else { (wx, wy) };
+                let rotated = footprint(Brush::RotatedEllipse(wx, wy, rotation));
+                assert_eq!(
+                    rotated,
+                    footprint(Brush::Ellipse(ex, ey)),
+                    "{wx}x{wy} by {rotation}"
+                );
+                let rotated = footprint(Brush::RotatedRectangle(wx, wy, rotation));
+                assert_eq!(
+                    rotated,
+                    footprint(Brush::Rectangle(ex, ey)),
+                    "{wx}x{wy} by {rotation}"
+                );
+            }
+        }
+
+        // A thin line turned on its diagonal
+        let diagonal = footprint(Brush::RotatedRectangle(2, 0, FRAC_PI_4));
+        let expected = HashSet::from([(-1, -1), (0, 0), (1, 1)]);
+        assert_eq!(diagonal, expected);
+    }
+
+    #[test]
+    fn test_scaled() {
+        assert_eq!(Brush::Ellipse(4, 3).scaled(0.5), Brush::Ellipse(2, 2));
+        assert_eq!(Brush::Rectangle(4, 0).scaled(2.0), Brush::Rectangle(8, 0));
+        let rotated = Brush::RotatedRectangle(1, 2, 0.5).scaled(-1.0);
+        assert_eq!(rotated, Brush::RotatedRectangle(0, 0, 0.5));
+    }
+
+    #[test]
+    fn test_clamped() {
+        let brush = Brush::RotatedEllipse(100_000, 3, 1.0);
+        assert_eq!(brush.clamped(1024), Brush::RotatedEllipse(511, 3, 1.0));
+        assert_eq!(Brush::Rectangle(5, 0).clamped(1), Brush::Rectangle(0, 0));
+        assert_eq!(Brush::Diamond(2, 2).clamped(1024), Brush::Diamond(2, 2));
+
+        // Compiling limits the size too, rather than overflowing
+        let rows = CompiledBrush::new(Brush::Ellipse(isize::MAX, 0)).rows;
+        assert_eq!(rows, [vec![(-Brush::MAX_SIZE, Brush::MAX_SIZE)]]);
+    }
+
+    #[test]
+    fn test_stamp_brush() {
+        let mask = StampMask::new(&[true, false, true, true, true, false], 3, 2).unwrap();
+        let brush = Brush::Stamp(mask);
+        let expected = HashSet::from([(-1, -1), (1, -1), (-1, 0), (0, 0)]);
+        assert_eq!(footprint(brush), expected);
+        assert_eq!(
+            CompiledBrush::new(brush).rows,
+            [vec![(-1, -1), (1, 1)], vec![(-1, 0)]]
+        );
+
+        // The mask has to match its size, and fit
+        assert_eq!(StampMask::new(&[true; 5], 3, 2), None);
+        assert_eq!(StampMask::new(&[true; 33], 33, 1), None);
+        let full = StampMask::new(&[true; 32 * 32], 32, 32).unwrap();
+        assert!(full.get(31, 31) && !full.get(32, 0));
+    }
+
+    #[test]
+    fn test_edges_between() {
+        let edges = |a: &[(isize, isize)], b: &[(isize, isize)]| {
+            let mut edges = vec![];
+            edges_between(a, b, |left, right| edges.push((left, right)));
+            edges
+        };
+        assert_eq!(edges(&[(0, 4)], &[(2, 6)]), [(0, 2), (5, 7)]);
+        assert_eq!(edges(&[(0, 4)], &[(0, 4)]), []);
+        // Meeting end to end, so the whole stretch has an edge
+        assert_eq!(edges(&[(0, 1)], &[(2, 3)]), [(0, 2), (2, 4)]);
+        assert_eq!(edges(&[(0, 0), (2, 2)], &[]), [(0, 1), (2, 3)]);
+    }
+}
@@ -1,42 +1,39 @@
+/// The largest half-width or half-height [`ellipse`] and [`solve_ellipse`] handle. Products
+/// of four of them still fit in an `i128`.
+pub const MAX_RADIUS: isize = i32::MAX as isize;
+
+/// Whether `(x, y)` lies inside the ellipse with half-width `wx` and half-height `wy`, up
+/// to [`MAX_RADIUS`]. The math is done in `i128`.
 pub fn ellipse(wx: isize, wy: isize, x: isize, y: isize) -> bool {
-    let x2 = x * x;
-    let y2 = y * y;
-    let wx2 = wx * wx;
-    let wy2 = wy * wy;
+    let x2 = x as i128 * x as i128;
+    let y2 = y as i128 * y as i128;
+    let wx2 = wx as i128 * wx as i128;
+    let wy2 = wy as i128 * wy as i128;
 
     y2 * wx2 <= wy2 * wx2 - wy2 * x2
 }
 
-
-
+/// The largest `y` such that `(x, y)` is inside the ellipse, found with a binary search
+/// over `0..=wy` in `O(log wy)` steps. Both radii must be at most [`MAX_RADIUS`].
 pub fn solve_ellipse(wx: isize, wy: isize, x: isize) -> isize {
-    assert!(wx >= 0);
-    assert!(wy >= 0);
+    assert!((0..=MAX_RADIUS).contains(&wx));
+    assert!((0..=MAX_RADIUS).contains(&wy));
     assert!(x <= wx);
     assert!(x >= -wx);
-    
-    let mut min = 0;
-    let mut max = wy;
 
-    // NOTE: Should be logarithmic!
-    for _ in 0..=isize::BITS {
-        if ellipse(wx, wy, x, max) {
-            return max;
-        }
-        
-        if max - min == 1 && ellipse(wx, wy, x, min) {
-            return min;
-        }
-        
-        let mid = (max + min) / 2;
+    // Invariant: `lo` is inside the ellipse, everything above `hi` is outside.
+    // The center row is always inside, because `x` is within the width.
+    let (mut lo, mut hi) = (0, wy);
+    while lo < hi {
+        // Round up, so that `lo = mid` always makes progress
+        let mid = lo + (hi - lo + 1) / 2;
         if ellipse(wx, wy, x, mid) {
-            min = mid;
+            lo = mid;
         } else {
-            max = mid;
+            hi = mid - 1;
         }
     }
-
-    unreachable!("Ellipse went unsolved!")
+    lo
 }
 
 #[cfg(test)]
@@ -83,4 +80,43 @@ mod tests {
         check_solve_ellipse(solve_ellipse)
     }
 
+    #[test]
+    fn test_solve_large_ellipse() {
+        for (wx, wy) in [(1000, 3000), (3000, 1000), (2047, 2048), (1, 2999)] {
+            for x in -wx..=wx {
+                assert_eq!(
+                    solve_ellipse(wx, wy, x),
+                    solve_ellipse_naive(wx, wy, x),
+                    "x={x} wx={wx} wy={wy}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_huge_ellipse() {
+        let r = 100_000;
+        let mut prev = r;
+        for x in 0..=r {
+            let y = solve_ellipse(r, r, x);
+            assert_eq!(y, solve_ellipse(r, r, -x));
+            // Shrinks towards the sides, and stays within a pixel of the true circle
+            assert!(y <= prev, "x={x}");
+            let exact = ((r as f64).powi(2) - (x as f64).powi(2)).sqrt();
+            assert!((y as f64 - exact).abs() <= 1.0, "x={x} y={y}");
+            prev = y;
+        }
+        assert_eq!(solve_ellipse(r, r, 0), r);
+        assert_eq!(solve_ellipse(r, r, r), 0);
+        assert!(!ellipse(r, r, r, 1));
+    }
+
+    #[test]
+    fn test_max_radius() {
+        let r = MAX_RADIUS;
+        assert_eq!(solve_ellipse(r, r, 0), r);
+        assert_eq!(solve_ellipse(r, r, r), 0);
+        assert_eq!(solve_ellipse(r, 1, r / 2), 0);
+        assert!(ellipse(r, r, r, 0) && !ellipse(r, r, r, r));
+    }
 }
@@ -0,0 +1,282 @@
+use std::collections::{HashSet, VecDeque};
+
+use egui::Color32;
+
+use crate::image::{Image, PixelInterface};
+
+/// How to pick between two colors when a blend falls between them
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Dither {
+    /// Blend smoothly, rounding to the nearest representable color
+    #[default]
+    None,
+    /// Ordered dithering with a 2x2 Bayer matrix, for a coarse pattern with few steps
+    Bayer2x2,
+    /// Ordered dithering with a 4x4 Bayer matrix
+    Bayer4x4,
+    /// Ordered dithering with an 8x8 Bayer matrix, for finer steps of intensity
+    Bayer8x8,
+}
+
+impl Dither {
+    /// The fraction of the way to the next color at which `(x, y)` rounds up to it,
+    /// strictly between 0 and 1
+    pub fn threshold(&self, x: isize, y: isize) -> f32 {
+        let bits = match self {
+            Dither::None => return 0.5,
+            Dither::Bayer2x2 => 1,
+            Dither::Bayer4x4 => 2,
+            Dither::Bayer8x8 => 3,
+        };
+        (bayer_level(x, y, bits) as f32 + 0.5) / (1 << (2 * bits)) as f32
+    }
+
+    /// Whether the pixel at `(x, y)` gets the color when covered with `intensity`, from
+    /// 0 to 1. Over an area, the fraction of pixels which do approximates the intensity.
+    pub fn covers(&self, intensity: f32, x: isize, y: isize) -> bool {
+        intensity >= self.threshold(x, y)
+    }
+}
+
+/// Fills the whole image with a linear gradient from `start` at `a` to `end` at `b`.
+/// Pixels are projected onto the line from `a` to `b`, and clamped beyond either end.
+/// Without dithering, colors are blended with [`PixelInterface::lerp`], which for `Color32`
+/// works on the premultiplied channels. With dithering, each pixel is either `start` or
+/// `end`, so the result stays within a limited palette.
+pub fn linear_gradient<Pixel: PixelInterface + Clone>(
+    image: &mut dyn Image<Pixel = Pixel>,
+    a: (isize, isize),
+    b: (isize, isize),
+    start: &Pixel,
+    end: &Pixel,
+    dither: Dither,
+) {
+    let (x_range, y_range) = image.image_boundaries();
+    let mut pixels = y_range.flat_map(|y| {
+        x_range.clone().map(move |x| {
+            let t = project(a, b, (x, y));
+            let px = match dither {
+                Dither::None => start.lerp(end, t, 0.5),
+                _ if dither.covers(t, x, y) => end.clone(),
+                _ => start.clone(),
+            };
+            (x, y, px)
+        })
+    });
+    image.set_pixels(&mut pixels);
+}
+
+/// Finds the pixels connected to `start` through their edges whose displayed color is
+/// within `tolerance` of that of `start` in every channel, nearest first. Stops after
+/// `limit` pixels; the second value is whether the whole region was found by then. Empty
+/// if `start` is outside the image boundaries.
+pub fn flood_region<Pixel: PixelInterface>(
+    image: &dyn Image<Pixel = Pixel>,
+    start: (isize, isize),
+    tolerance: u8,
+    limit: usize,
+) -> (Vec<(isize, isize)>, bool) {
+    let (x_range, y_range) = image.image_boundaries();
+    let in_bounds = |(x, y): (isize, isize)| x_range.contains(&x) && y_range.contains(&y);
+    if !in_bounds(start) {
+        return (vec![], true);
+    }
+
+    let target = image.get_pixel(start.0, start.1).as_rgba();
+    let similar = |(x, y): (isize, isize)| {
+        let color = image.get_pixel(x, y).as_rgba();
+        (0..4).all(|i| color[i].abs_diff(target[i]) <= tolerance)
+    };
+
+    let mut region = vec![];
+    let mut seen = HashSet::from([start]);
+    let mut queue = VecDeque::from([start]);
+    while let Some((x, y)) = queue.pop_front() {
+        if region.len() == limit {
+            return (region, false);
+        }
+        region.push((x, y));
+        for next in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+            if in_bounds(next) && seen.insert(next) && similar(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+    (region, true)
+}
+
+/// Sets the region found by [`flood_region`] around `start` to `color`, like the bucket
+/// tool of other editors
+pub fn flood_fill<Pixel: PixelInterface + Clone>(
+    image: &mut dyn Image<Pixel = Pixel>,
+    start: (isize, isize),
+    color: &Pixel,
+    tolerance: u8,
+) {
+    let (region, _) = flood_region(image, start, tolerance, usize::MAX);
+    let mut pixels = region.into_iter().map(|(x, y)| (x, y, color.clone()));
+    image.set_pixels(&mut pixels);
+}
+
+/// The entry of `palette` displayed most like `color`, by the squared distance between
+/// their RGBA channels, or `None` if `palette` is empty. Of equally near entries, the first
+/// wins.
+pub fn nearest_in_palette<Pixel: PixelInterface>(
+    palette: &[Pixel],
+    color: Color32,
+) -> Option<&Pixel> {
+    let distance = |px: &&Pixel| {
+        let entry = px.as_rgba();
+        (0..4)
+            .map(|i| (entry[i] as i32 - color[i] as i32).pow(2))
+            .sum::<i32>()
+    };
+    palette.iter().min_by_key(distance)
+}
+
+/// How far along the line from `a` to `b` the point `p` is, from 0 to 1
+fn project(a: (isize, isize), b: (isize, isize), p: (isize, isize)) -> f32 {
+    let (dx, dy) = ((b.0 - a.0) as f32, (b.1 - a.1) as f32);
+    let len_sq = dx * dx + dy * dy;
+    if len_sq == 0.0 {
+        return 0.0;
+    }
+    let (px, py) = ((p.0 - a.0) as f32, (p.1 - a.1) as f32);
+    ((px * dx + py * dy) / len_sq).clamp(0.0, 1.0)
+}
+
+/// The entry at `(x, y)` in the Bayer matrix with sides of `2^bits`, which orders the
+/// pixels so that each prefix is spread as evenly as possible
+fn bayer_level(x: isize, y: isize, bits: u32) -> u32 {
+    let size = 1 << bits;
+    let (x, y) = (x.rem_euclid(size) as u32, y.rem_euclid(size) as u32);
+    // The lowest bits of the position choose the quadrant, which matters most
+    (0..bits).fold(0, |level, i| {
+        let (xi, yi) = ((x >> i) & 1, (y >> i) & 1);
+        (level << 2) | ((xi ^ yi) << 1) | yi
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::ColorImage;
+
+    #[test]
+    fn test_project() {
+        assert_eq!(project((0, 0), (4, 0), (2, 7)), 0.5);
+        assert_eq!(project((0, 0), (4, 0), (-3, 0)), 0.0);
+        assert_eq!(project((0, 0), (4, 0), (9, 0)), 1.0);
+        assert_eq!(project((1, 1), (1, 1), (5, 5)), 0.0);
+    }
+
+    #[test]
+    fn test_linear_gradient() {
+        let mut image = ColorImage::new([5, 2], Color32::TRANSPARENT);
+        let (black, white) = (Color32::BLACK, Color32::WHITE);
+        linear_gradient(&mut image, (0, 0), (4, 0), &black, &white, Dither::None);
+
+        let row: Vec<u8> = image.pixels[..5].iter().map(|px| px.r()).collect();
+        assert_eq!(row, [0, 64, 128, 191, 255]);
+        assert_eq!(image.pixels[..5], image.pixels[5..]);
+    }
+
+    #[test]
+    fn test_gradient_to_transparent_is_premultiplied() {
+        let mut image = ColorImage::new([3, 1], Color32::BLACK);
+        let (red, clear) = (Color32::from_rgb(255, 0, 0), Color32::TRANSPARENT);
+        linear_gradient(&mut image, (0, 0), (2, 0), &red, &clear, Dither::None);
+        // Half as opaque, but just as red rather than a darker red at full opacity
+        let half_red = Color32::from_rgba_premultiplied(128, 0, 0, 128);
+        assert_eq!(image.pixels, [red, half_red, clear]);
+    }
+
+    #[test]
+    fn test_flood_fill() {
+        let (black, white) = (Color32::BLACK, Color32::WHITE);
+        let mut image = ColorImage::new([4, 3], black);
+        // A wall down the middle, with a near-black pixel in the left half
+        for y in 0..3 {
+            image.pixels[2 + y * 4] = white;
+        }
+        image.pixels[4] = Color32::from_gray(3);
+
+        let red = Color32::from_rgb(255, 0, 0);
+        flood_fill(&mut image, (0, 0), &red, 0);
+        let reds = |image: &ColorImage| image.pixels.iter().filter(|&&px| px == red).count();
+        assert_eq!(reds(&image), 5);
+        assert_eq!(image.pixels[4], Color32::from_gray(3));
+        assert_eq!(image.pixels[3], black);
+
+        let mut image = ColorImage::new([4, 3], black);
+        image.pixels[4] = Color32::from_gray(3);
+        flood_fill(&mut image, (1, 1), &red, 3);
+        assert_eq!(reds(&image), 12);
+    }
+
+    #[test]
+    fn test_flood_region_limit() {
+        let image = ColorImage::new([10, 10], Color32::BLACK);
+        let (mut region, complete) = flood_region(&image, (5, 5), 0, 5);
+        assert!(!complete);
+        // Nearest first: the start and its four neighbours
+        region.sort_unstable();
+        assert_eq!(region, [(4, 5), (5, 4), (5, 5), (5, 6), (6, 5)]);
+
+        let (region, complete) = flood_region(&image, (5, 5), 0, 100);
+        assert_eq!((region.len(), complete), (100, true));
+        assert_eq!(flood_region(&image, (10, 0), 0, 100), (vec![], true));
+    }
+
+    #[test]
+    fn test_nearest_in_palette() {
+        let palette = [Color32::BLACK, Color32::WHITE];
+        let nearest = |color| nearest_in_palette(&palette, color).copied();
+        assert_eq!(nearest(Color32::from_gray(100)), Some(Color32::BLACK));
+        assert_eq!(nearest(Color32::from_gray(160)), Some(Color32::WHITE));
+        // Alpha counts too: transparent is nearer to black than to white
+        assert_eq!(nearest(Color32::TRANSPARENT), Some(Color32::BLACK));
+        assert_eq!(nearest_in_palette::<Color32>(&[], Color32::RED), None);
+    }
+
+    #[test]
+    fn test_bayer_4x4() {
+        let levels: Vec<u32> = (0..16).map(|i| bayer_level(i % 4, i / 4, 2)).collect();
+        let expected = [0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5];
+        assert_eq!(levels, expected);
+    }
+
+    #[test]
+    fn test_half_intensity_is_checkered() {
+        let dithers = [
+            (Dither::Bayer2x2, 2_isize),
+            (Dither::Bayer4x4, 4),
+            (Dither::Bayer8x8, 8),
+        ];
+        for (dither, size) in dithers {
+            for y in -size..size {
+                for x in -size..size {
+                    let checker = (x + y).rem_euclid(2) == 0;
+                    assert_eq!(dither.covers(0.5, x, y), checker, "{dither:?} at {x}, {y}");
+                }
+            }
+        }
+        assert!(Dither::None.covers(0.5, 1, 0));
+        assert!(!Dither::None.covers(0.49, 0, 0));
+    }
+
+    #[test]
+    fn test_dithered_gradient_uses_two_colors() {
+        let mut image = ColorImage::new([9, 4], Color32::TRANSPARENT);
+        let (black, white) = (Color32::BLACK, Color32::WHITE);
+        linear_gradient(&mut image, (0, 0), (8, 0), &black, &white, Dither::Bayer4x4);
+
+        assert!(image.pixels.iter().all(|&px| px == black || px == white));
+        // The middle column has half of its 4x4 block, so half of its rows, set
+        let whites = |x: usize| (0..4).filter(|y| image.pixels[x + y * 9] == white).count();
+        assert_eq!(whites(0), 0);
+        assert_eq!(whites(4), 2);
+        assert_eq!(whites(8), 4);
+    }
+}
@@ -0,0 +1,50 @@
+use egui::Color32;
+
+/// Inverts the color channels, leaving alpha alone
+pub fn invert(px: Color32) -> Color32 {
+    let [r, g, b, a] = px.to_srgba_unmultiplied();
+    Color32::from_rgba_unmultiplied(255 - r, 255 - g, 255 - b, a)
+}
+
+/// Adds `delta` to each color channel, clamping to the valid range and leaving alpha alone
+pub fn adjust_brightness(px: Color32, delta: i16) -> Color32 {
+    let [r, g, b, a] = px.to_srgba_unmultiplied();
+    let adjust = |c: u8| (c as i16 + delta).clamp(0, 255) as u8;
+    Color32::from_rgba_unmultiplied(adjust(r), adjust(g), adjust(b), a)
+}
+
+/// Replaces the color with its (Rec. 601) luma, leaving alpha alone
+pub fn to_grayscale(px: Color32) -> Color32 {
+    let [r, g, b, a] = px.to_srgba_unmultiplied();
+    let luma = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+    let luma = luma.round().clamp(0.0, 255.0) as u8;
+    Color32::from_rgba_unmultiplied(luma, luma, luma, a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invert() {
+        assert_eq!(invert(Color32::BLACK), Color32::WHITE);
+        let px = Color32::from_rgb(10, 20, 30);
+        assert_eq!(invert(px), Color32::from_rgb(245, 235, 225));
+        assert_eq!(invert(Color32::TRANSPARENT).a(), 0);
+    }
+
+    #[test]
+    fn test_adjust_brightness_clamps() {
+        let px = Color32::from_rgb(10, 128, 250);
+        assert_eq!(adjust_brightness(px, 20), Color32::from_rgb(30, 148, 255));
+        assert_eq!(adjust_brightness(px, -20), Color32::from_rgb(0, 108, 230));
+        assert_eq!(adjust_brightness(px, 1000), Color32::WHITE);
+    }
+
+    #[test]
+    fn test_to_grayscale() {
+        assert_eq!(to_grayscale(Color32::WHITE), Color32::WHITE);
+        let red = Color32::from_rgb(255, 0, 0);
+        assert_eq!(to_grayscale(red), Color32::from_gray(76));
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
     collections::{HashMap, HashSet},
-    ops::RangeInclusive,
+    ops::{Range, RangeInclusive},
 };
 
 use egui::{
@@ -12,12 +12,75 @@ pub trait Image {
     type Pixel;
     /// Gets the pixel at `(x, y)`
     /// Allowed to panic outside of image_boundaries if `set_pixel_out_of_bounds` is `false`.
+    /// The editor and the operations in this crate never read outside of them unless
+    /// allowed to, going through [`ImageExt::get_pixel_checked`] wherever a coordinate
+    /// isn't known to be inside.
+    /// Returns the pixel by value, since the adapters below, the editor's trackers and
+    /// images like [`crate::Composite`] work their pixels out rather than store them, so
+    /// they have nothing to lend. Images which do store them implement [`ImageRef`] too.
     fn get_pixel(&self, x: isize, y: isize) -> Self::Pixel;
     /// Sets the pixel at `(x, y)` to `px`.
-    /// Allowed to panic outside of image_boundaries if `set_pixel_out_of_bounds` is `false`.
+    /// Allowed to panic outside of image_boundaries if `set_pixel_out_of_bounds` is `false`;
+    /// as with `get_pixel`, built-in writes go through [`ImageExt::set_pixel_checked`]
+    /// where needed.
     fn set_pixel(&mut self, x: isize, y: isize, px: Self::Pixel);
+    /// Sets many pixels, in order; later writes to the same coordinate win.
+    /// Wrappers such as the editor's change trackers override this to do their
+    /// bookkeeping once per batch instead of once per pixel, and pass the batch on to the
+    /// image they wrap; the tile tracker marks each tile dirty once.
+    fn set_pixels(&mut self, pixels: &mut dyn Iterator<Item = (isize, isize, Self::Pixel)>) {
+        for (x, y, px) in pixels {
+            self.set_pixel(x, y, px);
+        }
+    }
+    /// Sets the pixels of row `y` from `x_range.start()` to `x_range.end()` to `px`, like
+    /// `set_pixels` with the same pixels. Row-major images fill the run in place, and the
+    /// editor's trackers and the adapters in this module pass whole runs on, so that
+    /// [`ImageExt::fill_rect`] and the editor's brushes skip the per-pixel calls.
+    fn fill_row(&mut self, y: isize, x_range: RangeInclusive<isize>, px: Self::Pixel)
+    where
+        Self::Pixel: Clone,
+    {
+        self.set_pixels(&mut x_range.map(|x| (x, y, px.clone())));
+    }
     /// Returns the boundaries of the image; may grow over time (but not shrink!)
     fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>);
+    /// Whether pixels outside of `image_boundaries` may be accessed, e.g. because the image
+    /// grows to fit them. If so, [`ImageExt::set_pixel_checked`] and
+    /// [`ImageExt::get_pixel_checked`] skip their bounds checks.
+    fn set_pixel_out_of_bounds(&self) -> bool {
+        false
+    }
+}
+
+/// An image which stores its pixels, and so can lend them out instead of cloning them
+/// like [`Image::get_pixel`], e.g. to look into large pixels without copying them
+pub trait ImageRef: Image {
+    /// Borrows the pixel at `(x, y)`. Panics outside of `image_boundaries`, since there
+    /// is no stored pixel there to lend.
+    fn get_pixel_ref(&self, x: isize, y: isize) -> &Self::Pixel;
+}
+
+/// `Send + 'static` with the `parallel` feature, and implemented by every type otherwise.
+/// With the feature, the pixels of dirty tiles are copied out of the image and sampled on
+/// background threads, so drawing an image requires it of its pixels; the image itself
+/// never leaves the thread drawing it.
+pub trait MaybeSend: parallel::Bound {}
+
+impl<T: parallel::Bound + ?Sized> MaybeSend for T {}
+
+mod parallel {
+    #[cfg(feature = "parallel")]
+    pub trait Bound: Send + 'static {}
+
+    #[cfg(feature = "parallel")]
+    impl<T: Send + 'static + ?Sized> Bound for T {}
+
+    #[cfg(not(feature = "parallel"))]
+    pub trait Bound {}
+
+    #[cfg(not(feature = "parallel"))]
+    impl<T: ?Sized> Bound for T {}
 }
 
 pub trait PixelInterface {
@@ -25,6 +88,40 @@ pub trait PixelInterface {
     /// Allows transparency.
     /// This should be a pure function.
     fn as_rgba(&self) -> Color32;
+
+    /// Whether the pixel can be seen through entirely, e.g. to leave it out when pasting.
+    /// Defaults to checking for zero alpha; override it if that is cheaper to tell without
+    /// working out the color.
+    fn is_transparent(&self) -> bool {
+        self.as_rgba().a() == 0
+    }
+
+    /// Writes the color of each of `pixels` to the same place in `out`, which has the same
+    /// length. Tiles are sampled a row at a time through this, so override it when
+    /// resolving many colors at once is cheaper, e.g. to look up a palette only once.
+    fn as_rgba_row(pixels: &[Self], out: &mut [Color32])
+    where
+        Self: Sized,
+    {
+        for (px, out) in pixels.iter().zip(out) {
+            *out = px.as_rgba();
+        }
+    }
+
+    /// Blends from `self` (at `t = 0`) to `other` (at `t = 1`), for gradients.
+    /// Values which fall between two representable pixels are rounded up if their
+    /// fractional part is at least `threshold`; `0.5` rounds to nearest, and varying it
+    /// per pixel dithers. The default can't blend, so it just picks one of the two.
+    fn lerp(&self, other: &Self, t: f32, threshold: f32) -> Self
+    where
+        Self: Clone,
+    {
+        if t < threshold {
+            self.clone()
+        } else {
+            other.clone()
+        }
+    }
 }
 
 pub struct Crop<'image, I: Image + ?Sized> {
@@ -33,17 +130,110 @@ pub struct Crop<'image, I: Image + ?Sized> {
     image: &'image mut I,
 }
 
+/// A read-only [`Crop`], for sampling part of an image that is also borrowed elsewhere;
+/// see [`ImageExt::crop_ref`]
+pub struct CropRef<'image, I: Image + ?Sized> {
+    x_range: RangeInclusive<isize>,
+    y_range: RangeInclusive<isize>,
+    image: &'image I,
+}
+
+/// Shifts coordinates by a fixed offset before passing them on; see [`ImageExt::translate`]
+pub struct Translate<'image, I: Image + ?Sized> {
+    dx: isize,
+    dy: isize,
+    image: &'image mut I,
+}
+
+/// A row-major image of any pixel type, for when `ColorImage` is too specific.
+/// With the `serde` feature it can be saved whenever its pixels can.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Buffer2D<P> {
+    pub data: Vec<P>,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// A row-major `Color32` buffer borrowed from elsewhere, e.g. an existing framebuffer
+pub struct SliceImage<'a> {
+    data: &'a mut [Color32],
+    width: usize,
+    height: usize,
+}
+
+/// A `ColorImage` which can grow in any direction, keeping each pixel at the same
+/// coordinates. With `auto_grow`, writes outside of it grow it to fit them, filling the new
+/// area with `fill`; growing left or up moves `origin` to negative coordinates.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResizableColorImage {
+    pub image: ColorImage,
+    /// Coordinates of the top left pixel of `image`
+    pub origin: (isize, isize),
+    /// Color of the pixels added when growing automatically, and of reads outside
+    pub fill: Color32,
+    pub auto_grow: bool,
+}
+
+/// Drops writes to pixels for which `predicate` returns `false`; see [`ImageExt::mask`]
+pub struct Mask<'image, I: Image + ?Sized, F> {
+    predicate: F,
+    image: &'image mut I,
+}
+
+/// Replaces each written pixel with `f(px)`; see [`ImageExt::map_writes`]
+pub struct MapWrites<'image, I: Image + ?Sized, F> {
+    f: F,
+    image: &'image mut I,
+}
+
+/// What happens to writes past the edges of an image; see [`ImageExt::wrap`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WrapMode {
+    /// Pixels outside the image boundaries are dropped
+    #[default]
+    Clip,
+    /// Pixels outside the image boundaries wrap around to the opposite edge, horizontally
+    /// and vertically, e.g. for painting seamless textures
+    WrapXY,
+}
+
+/// Whether writes past the edges of an image are dropped or passed on to it; see
+/// [`ImageExt::out_of_bounds`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OutOfBounds {
+    /// Pixels outside the image boundaries are dropped, unless the image accepts them
+    /// itself (see [`Image::set_pixel_out_of_bounds`])
+    #[default]
+    Clip,
+    /// Pixels outside the image boundaries are written with [`Image::set_pixel`] all the
+    /// same, so that an image which grows to fit them can do so. Only for images whose
+    /// `get_pixel` and `set_pixel` accept any coordinates and keep what is written there;
+    /// the undo history expects to find it again.
+    Grow,
+}
+
+/// Passes reads and writes outside the image on to it; see [`ImageExt::out_of_bounds`]
+pub struct OutOfBoundsWrites<'image, I: Image + ?Sized> {
+    policy: OutOfBounds,
+    image: &'image mut I,
+}
+
+/// Wraps coordinates outside the image around to the other side; see [`ImageExt::wrap`]
+pub struct Wrap<'image, I: Image + ?Sized> {
+    mode: WrapMode,
+    image: &'image mut I,
+}
+
 pub trait ImageExt: Image {
     fn crop(
         &mut self,
         x_range: RangeInclusive<isize>,
         y_range: RangeInclusive<isize>,
-    ) -> Crop<Self> {
-        let (image_x_range, image_y_range) = self.image_boundaries();
-        let x_range = (*x_range.start()).max(*image_x_range.start())
-            ..=(*x_range.end()).min(*image_x_range.end());
-        let y_range = (*y_range.start()).max(*image_y_range.start())
-            ..=(*y_range.end()).min(*image_y_range.end());
+    ) -> Crop<'_, Self> {
+        let (x_range, y_range) = clip_to(self, x_range, y_range);
         Crop {
             x_range,
             y_range,
@@ -51,6 +241,64 @@ pub trait ImageExt: Image {
         }
     }
 
+    /// Like [`ImageExt::crop`], but only borrows the image for reading
+    fn crop_ref(
+        &self,
+        x_range: RangeInclusive<isize>,
+        y_range: RangeInclusive<isize>,
+    ) -> CropRef<'_, Self> {
+        let (x_range, y_range) = clip_to(self, x_range, y_range);
+        CropRef {
+            x_range,
+            y_range,
+            image: self,
+        }
+    }
+
+    /// Restricts writes to the pixels where `predicate(x, y)` is `true`. Reads are unaffected.
+    fn mask<F: Fn(isize, isize) -> bool>(&mut self, predicate: F) -> Mask<'_, Self, F> {
+        Mask {
+            predicate,
+            image: self,
+        }
+    }
+
+    /// Passes every written pixel through `f`, e.g. to snap it to a palette. Reads are
+    /// unaffected.
+    fn map_writes<F>(&mut self, f: F) -> MapWrites<'_, Self, F>
+    where
+        F: Fn(Self::Pixel) -> Self::Pixel,
+    {
+        MapWrites { f, image: self }
+    }
+
+    /// Maps every coordinate into the image boundaries according to `mode` before reading
+    /// or writing, so that e.g. a brush hanging off the right edge also paints on the left.
+    /// Wrapping accepts writes anywhere, so `set_pixel_checked` never rejects one.
+    fn wrap(&mut self, mode: WrapMode) -> Wrap<'_, Self> {
+        Wrap { mode, image: self }
+    }
+
+    /// With [`OutOfBounds::Grow`], lets `set_pixel_checked` and `get_pixel_checked` reach
+    /// past the image boundaries, as if the image allowed it itself
+    fn out_of_bounds(&mut self, policy: OutOfBounds) -> OutOfBoundsWrites<'_, Self> {
+        OutOfBoundsWrites {
+            policy,
+            image: self,
+        }
+    }
+
+    /// Views the image so that pixel `(x, y)` is `(x + dx, y + dy)` of the original.
+    /// Combined with [`ImageExt::crop`], this edits part of an image as if its corner were
+    /// at the origin.
+    fn translate(&mut self, dx: isize, dy: isize) -> Translate<'_, Self> {
+        Translate {
+            dx,
+            dy,
+            image: self,
+        }
+    }
+
     fn dimensions(&self) -> (usize, usize) {
         let (x_range, y_range) = self.image_boundaries();
         let width: usize = (x_range.end() - x_range.start() + 1)
@@ -63,17 +311,170 @@ pub trait ImageExt: Image {
     }
 
     fn get_pixel_checked(&self, x: isize, y: isize) -> Option<Self::Pixel> {
-        self.bounds_check(x, y).then(|| self.get_pixel(x, y))
+        (self.set_pixel_out_of_bounds() || self.bounds_check(x, y)).then(|| self.get_pixel(x, y))
     }
 
     fn set_pixel_checked(&mut self, x: isize, y: isize, px: Self::Pixel) -> bool {
-        let ret = self.bounds_check(x, y);
+        let ret = self.set_pixel_out_of_bounds() || self.bounds_check(x, y);
         if ret {
             self.set_pixel(x, y, px);
         } 
         ret
     }
 
+    /// Like [`Image::fill_row`], but drops the pixels outside of the image unless
+    /// `set_pixel_out_of_bounds` allows them, as `set_pixel_checked` does
+    fn fill_row_checked(&mut self, y: isize, x_range: RangeInclusive<isize>, px: Self::Pixel)
+    where
+        Self::Pixel: Clone,
+    {
+        if self.set_pixel_out_of_bounds() {
+            self.fill_row(y, x_range, px);
+            return;
+        }
+        let (x_range, y_range) = clip_to(self, x_range, y..=y);
+        if !x_range.is_empty() && !y_range.is_empty() {
+            self.fill_row(y, x_range, px);
+        }
+    }
+
+    /// Calls `f` for every pixel in the rectangle, row by row, with `None` for pixels
+    /// outside the image. Fetches [`Image::image_boundaries`] only once, unlike repeated
+    /// calls to [`Self::get_pixel_checked`].
+    fn for_each_in_rect(
+        &self,
+        x_range: RangeInclusive<isize>,
+        y_range: RangeInclusive<isize>,
+        mut f: impl FnMut(isize, isize, Option<Self::Pixel>),
+    ) {
+        let (image_x_range, image_y_range) = self.image_boundaries();
+        for y in y_range {
+            let row_in_bounds = image_y_range.contains(&y);
+            for x in x_range.clone() {
+                let in_bounds = row_in_bounds && image_x_range.contains(&x);
+                f(x, y, in_bounds.then(|| self.get_pixel(x, y)));
+            }
+        }
+    }
+
+    /// Sets the pixels in the rectangle to `px` a row at a time with [`Image::fill_row`],
+    /// ignoring any outside of the image
+    fn fill_rect(
+        &mut self,
+        x_range: RangeInclusive<isize>,
+        y_range: RangeInclusive<isize>,
+        px: Self::Pixel,
+    ) where
+        Self::Pixel: Clone,
+    {
+        let (image_x_range, image_y_range) = self.image_boundaries();
+        let x_range = (*x_range.start()).max(*image_x_range.start())
+            ..=(*x_range.end()).min(*image_x_range.end());
+        let y_range = (*y_range.start()).max(*image_y_range.start())
+            ..=(*y_range.end()).min(*image_y_range.end());
+        if x_range.is_empty() {
+            return;
+        }
+        for y in y_range {
+            self.fill_row(y, x_range.clone(), px.clone());
+        }
+    }
+
+    /// Sets every pixel in the image to `px`
+    fn fill_all(&mut self, px: Self::Pixel)
+    where
+        Self::Pixel: Clone,
+    {
+        let (x_range, y_range) = self.image_boundaries();
+        self.fill_rect(x_range, y_range, px);
+    }
+
+    /// Copies `src` onto this image with its top left corner at `at`. Pixels which land
+    /// outside of this image are dropped.
+    fn blit(&mut self, src: &(impl Image<Pixel = Self::Pixel> + ?Sized), at: (isize, isize)) {
+        self.blit_with(src, at, |_| false);
+    }
+
+    /// Like [`Self::blit`], but leaves the destination alone wherever `skip` returns `true`
+    /// for the source pixel, e.g. to omit transparent pixels.
+    fn blit_with(
+        &mut self,
+        src: &(impl Image<Pixel = Self::Pixel> + ?Sized),
+        (at_x, at_y): (isize, isize),
+        skip: impl Fn(&Self::Pixel) -> bool,
+    ) {
+        let (x_range, y_range) = src.image_boundaries();
+        let (x0, y0) = (*x_range.start(), *y_range.start());
+        for y in y_range {
+            for x in x_range.clone() {
+                let px = src.get_pixel(x, y);
+                if !skip(&px) {
+                    self.set_pixel_checked(at_x + x - x0, at_y + y - y0, px);
+                }
+            }
+        }
+    }
+
+    /// Like [`Self::blit`], but leaves the destination alone under transparent source pixels
+    /// (see [`PixelInterface::is_transparent`]), e.g. to paste a sprite over the image
+    fn blit_over(&mut self, src: &(impl Image<Pixel = Self::Pixel> + ?Sized), at: (isize, isize))
+    where
+        Self::Pixel: PixelInterface,
+    {
+        self.blit_with(src, at, PixelInterface::is_transparent);
+    }
+
+    /// Calls `f` on every pixel in the image, and writes back whatever it returns.
+    /// Writes go through `set_pixels` afterwards, so trackers see one batch.
+    fn for_each_pixel(
+        &mut self,
+        mut f: impl FnMut(isize, isize, Self::Pixel) -> Option<Self::Pixel>,
+    ) {
+        let (x_range, y_range) = self.image_boundaries();
+        let mut writes = vec![];
+        for y in y_range {
+            for x in x_range.clone() {
+                if let Some(px) = f(x, y, self.get_pixel(x, y)) {
+                    writes.push((x, y, px));
+                }
+            }
+        }
+        self.set_pixels(&mut writes.into_iter());
+    }
+
+    /// Combines every pixel in the image into one value, row by row
+    fn fold_pixels<B>(&self, init: B, mut f: impl FnMut(B, isize, isize, Self::Pixel) -> B) -> B {
+        let (x_range, y_range) = self.image_boundaries();
+        let mut acc = init;
+        for y in y_range {
+            for x in x_range.clone() {
+                acc = f(acc, x, y, self.get_pixel(x, y));
+            }
+        }
+        acc
+    }
+
+    /// Renders the whole image through [`PixelInterface::as_rgba`], e.g. for a thumbnail or
+    /// to save it. Pixel `(0, 0)` of the result is the top left of the image boundaries,
+    /// wherever those start.
+    fn to_color_image(&self) -> ColorImage
+    where
+        Self::Pixel: PixelInterface,
+    {
+        let (width, height) = self.dimensions();
+        let mut pixels = Vec::with_capacity(width * height);
+        let (x_range, y_range) = self.image_boundaries();
+        for y in y_range {
+            for x in x_range.clone() {
+                pixels.push(self.get_pixel(x, y).as_rgba());
+            }
+        }
+        ColorImage {
+            size: [width, height],
+            pixels,
+        }
+    }
+
     fn bounds_check(&self, x: isize, y: isize) -> bool {
         let (x_range, y_range) = self.image_boundaries();
         x_range.contains(&x) && y_range.contains(&y)
@@ -82,6 +483,46 @@ pub trait ImageExt: Image {
 
 impl<T: Image + ?Sized> ImageExt for T {}
 
+/// The intersection of the given ranges with the boundaries of `image`
+fn clip_to<I: Image + ?Sized>(
+    image: &I,
+    x_range: RangeInclusive<isize>,
+    y_range: RangeInclusive<isize>,
+) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
+    let (image_x_range, image_y_range) = image.image_boundaries();
+    let x_range =
+        (*x_range.start()).max(*image_x_range.start())..=(*x_range.end()).min(*image_x_range.end());
+    let y_range =
+        (*y_range.start()).max(*image_y_range.start())..=(*y_range.end()).min(*image_y_range.end());
+    (x_range, y_range)
+}
+
+impl<I: Image + ?Sized> CropRef<'_, I> {
+    pub fn get_pixel(&self, x: isize, y: isize) -> I::Pixel {
+        assert!(
+            self.bounds_check(x, y),
+            "Out of bounds get pixel in crop at {}, {} not in {:?}, {:?}",
+            x,
+            y,
+            self.x_range,
+            self.y_range
+        );
+        self.image.get_pixel(x, y)
+    }
+
+    pub fn get_pixel_checked(&self, x: isize, y: isize) -> Option<I::Pixel> {
+        self.bounds_check(x, y).then(|| self.image.get_pixel(x, y))
+    }
+
+    pub fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
+        (self.x_range.clone(), self.y_range.clone())
+    }
+
+    pub fn bounds_check(&self, x: isize, y: isize) -> bool {
+        self.x_range.contains(&x) && self.y_range.contains(&y)
+    }
+}
+
 impl<I: Image> Image for Crop<'_, I> {
     type Pixel = I::Pixel;
     fn get_pixel(&self, x: isize, y: isize) -> Self::Pixel {
@@ -108,28 +549,494 @@ impl<I: Image> Image for Crop<'_, I> {
         self.image.set_pixel(x, y, px);
     }
 
+    fn set_pixels(&mut self, pixels: &mut dyn Iterator<Item = (isize, isize, Self::Pixel)>) {
+        let (x_range, y_range) = (&self.x_range, &self.y_range);
+        self.image.set_pixels(&mut pixels.inspect(|(x, y, _)| {
+            assert!(
+                x_range.contains(x) && y_range.contains(y),
+                "Out of bounds set pixel in crop at {}, {} not in {:?}, {:?}",
+                x,
+                y,
+                x_range,
+                y_range
+            );
+        }));
+    }
+
+    /// Clips the run to the crop, like a whole image would, rather than panicking
+    fn fill_row(&mut self, y: isize, x_range: RangeInclusive<isize>, px: Self::Pixel)
+    where
+        Self::Pixel: Clone,
+    {
+        let start = *x_range.start().max(self.x_range.start());
+        let end = *x_range.end().min(self.x_range.end());
+        if self.y_range.contains(&y) && start <= end {
+            self.image.fill_row(y, start..=end, px);
+        }
+    }
+
     fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
         (self.x_range.clone(), self.y_range.clone())
     }
 }
 
+impl<I: ImageRef + ?Sized> ImageRef for Translate<'_, I> {
+    fn get_pixel_ref(&self, x: isize, y: isize) -> &Self::Pixel {
+        self.image.get_pixel_ref(x + self.dx, y + self.dy)
+    }
+}
+
+impl<I: Image + ?Sized> Image for Translate<'_, I> {
+    type Pixel = I::Pixel;
+    fn get_pixel(&self, x: isize, y: isize) -> Self::Pixel {
+        self.image.get_pixel(x + self.dx, y + self.dy)
+    }
+
+    fn set_pixel(&mut self, x: isize, y: isize, px: Self::Pixel) {
+        self.image.set_pixel(x + self.dx, y + self.dy, px);
+    }
+
+    fn set_pixels(&mut self, pixels: &mut dyn Iterator<Item = (isize, isize, Self::Pixel)>) {
+        let (dx, dy) = (self.dx, self.dy);
+        self.image
+            .set_pixels(&mut pixels.map(|(x, y, px)| (x + dx, y + dy, px)));
+    }
+
+    fn fill_row(&mut self, y: isize, x_range: RangeInclusive<isize>, px: Self::Pixel)
+    where
+        Self::Pixel: Clone,
+    {
+        let x_range = x_range.start() + self.dx..=x_range.end() + self.dx;
+        self.image.fill_row(y + self.dy, x_range, px);
+    }
+
+    fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
+        let (x_range, y_range) = self.image.image_boundaries();
+        (
+            x_range.start() - self.dx..=x_range.end() - self.dx,
+            y_range.start() - self.dy..=y_range.end() - self.dy,
+        )
+    }
+
+    fn set_pixel_out_of_bounds(&self) -> bool {
+        self.image.set_pixel_out_of_bounds()
+    }
+}
+
+impl<I, F> Image for Mask<'_, I, F>
+where
+    I: Image + ?Sized,
+    F: Fn(isize, isize) -> bool,
+{
+    type Pixel = I::Pixel;
+    fn get_pixel(&self, x: isize, y: isize) -> Self::Pixel {
+        self.image.get_pixel(x, y)
+    }
+
+    fn set_pixel(&mut self, x: isize, y: isize, px: Self::Pixel) {
+        if (self.predicate)(x, y) {
+            self.image.set_pixel(x, y, px);
+        }
+    }
+
+    fn set_pixels(&mut self, pixels: &mut dyn Iterator<Item = (isize, isize, Self::Pixel)>) {
+        let predicate = &self.predicate;
+        self.image
+            .set_pixels(&mut pixels.filter(|(x, y, _)| predicate(*x, *y)));
+    }
+
+    fn fill_row(&mut self, y: isize, x_range: RangeInclusive<isize>, px: Self::Pixel)
+    where
+        Self::Pixel: Clone,
+    {
+        // Passes on each run of pixels which the predicate lets through
+        let mut run_start = None;
+        for x in x_range.clone() {
+            match ((self.predicate)(x, y), run_start) {
+                (true, None) => run_start = Some(x),
+                (false, Some(first)) => {
+                    self.image.fill_row(y, first..=x - 1, px.clone());
+                    run_start = None;
+                }
+                _ => (),
+            }
+        }
+        if let Some(first) = run_start {
+            self.image.fill_row(y, first..=*x_range.end(), px);
+        }
+    }
+
+    fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
+        self.image.image_boundaries()
+    }
+
+    fn set_pixel_out_of_bounds(&self) -> bool {
+        self.image.set_pixel_out_of_bounds()
+    }
+}
+
+impl<I, F> Image for MapWrites<'_, I, F>
+where
+    I: Image + ?Sized,
+    F: Fn(I::Pixel) -> I::Pixel,
+{
+    type Pixel = I::Pixel;
+    fn get_pixel(&self, x: isize, y: isize) -> Self::Pixel {
+        self.image.get_pixel(x, y)
+    }
+
+    fn set_pixel(&mut self, x: isize, y: isize, px: Self::Pixel) {
+        self.image.set_pixel(x, y, (self.f)(px));
+    }
+
+    fn set_pixels(&mut self, pixels: &mut dyn Iterator<Item = (isize, isize, Self::Pixel)>) {
+        let f = &self.f;
+        self.image
+            .set_pixels(&mut pixels.map(|(x, y, px)| (x, y, f(px))));
+    }
+
+    fn fill_row(&mut self, y: isize, x_range: RangeInclusive<isize>, px: Self::Pixel)
+    where
+        Self::Pixel: Clone,
+    {
+        self.image.fill_row(y, x_range, (self.f)(px));
+    }
+
+    fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
+        self.image.image_boundaries()
+    }
+
+    fn set_pixel_out_of_bounds(&self) -> bool {
+        self.image.set_pixel_out_of_bounds()
+    }
+}
+
+impl<I: Image + ?Sized> Wrap<'_, I> {
+    fn wrap_coords(&self, x: isize, y: isize) -> (isize, isize) {
+        match self.mode {
+            WrapMode::Clip => (x, y),
+            WrapMode::WrapXY => {
+                let (x_range, y_range) = self.image.image_boundaries();
+                (wrap_into(x, &x_range), wrap_into(y, &y_range))
+            }
+        }
+    }
+}
+
+/// `v` moved by a multiple of the length of `range` so that it lands inside it
+fn wrap_into(v: isize, range: &RangeInclusive<isize>) -> isize {
+    let len = range.end() - range.start() + 1;
+    if len <= 0 {
+        return v;
+    }
+    range.start() + (v - range.start()).rem_euclid(len)
+}
+
+impl<I: Image + ?Sized> Image for OutOfBoundsWrites<'_, I> {
+    type Pixel = I::Pixel;
+    fn get_pixel(&self, x: isize, y: isize) -> Self::Pixel {
+        self.image.get_pixel(x, y)
+    }
+
+    fn set_pixel(&mut self, x: isize, y: isize, px: Self::Pixel) {
+        self.image.set_pixel(x, y, px);
+    }
+
+    fn set_pixels(&mut self, pixels: &mut dyn Iterator<Item = (isize, isize, Self::Pixel)>) {
+        self.image.set_pixels(pixels);
+    }
+
+    fn fill_row(&mut self, y: isize, x_range: RangeInclusive<isize>, px: Self::Pixel)
+    where
+        Self::Pixel: Clone,
+    {
+        self.image.fill_row(y, x_range, px);
+    }
+
+    fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
+        self.image.image_boundaries()
+    }
+
+    fn set_pixel_out_of_bounds(&self) -> bool {
+        self.policy == OutOfBounds::Grow || self.image.set_pixel_out_of_bounds()
+    }
+}
+
+impl<I: Image + ?Sized> Image for Wrap<'_, I> {
+    type Pixel = I::Pixel;
+    fn get_pixel(&self, x: isize, y: isize) -> Self::Pixel {
+        let (x, y) = self.wrap_coords(x, y);
+        self.image.get_pixel(x, y)
+    }
+
+    fn set_pixel(&mut self, x: isize, y: isize, px: Self::Pixel) {
+        let (x, y) = self.wrap_coords(x, y);
+        self.image.set_pixel(x, y, px);
+    }
+
+    fn set_pixels(&mut self, pixels: &mut dyn Iterator<Item = (isize, isize, Self::Pixel)>) {
+        let wrapped: Vec<_> = pixels
+            .map(|(x, y, px)| {
+                let (x, y) = self.wrap_coords(x, y);
+                (x, y, px)
+            })
+            .collect();
+        self.image.set_pixels(&mut wrapped.into_iter());
+    }
+
+    fn fill_row(&mut self, y: isize, x_range: RangeInclusive<isize>, px: Self::Pixel)
+    where
+        Self::Pixel: Clone,
+    {
+        if self.mode == WrapMode::Clip {
+            self.image.fill_row(y, x_range, px);
+            return;
+        }
+        // Splits the row wherever it wraps around from the right edge to the left
+        let (image_x_range, image_y_range) = self.image.image_boundaries();
+        let y = wrap_into(y, &image_y_range);
+        let mut x = *x_range.start();
+        while x <= *x_range.end() {
+            let first = wrap_into(x, &image_x_range);
+            let len = (x_range.end() - x).min(image_x_range.end() - first).max(0);
+            self.image.fill_row(y, first..=first + len, px.clone());
+            x += len + 1;
+        }
+    }
+
+    fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
+        self.image.image_boundaries()
+    }
+
+    fn set_pixel_out_of_bounds(&self) -> bool {
+        self.mode == WrapMode::WrapXY || self.image.set_pixel_out_of_bounds()
+    }
+}
+
+/// Index of `(x, y)` in a row-major buffer of the given size, or `None` if it is outside,
+/// rather than wrapping onto the next row
+fn row_major_index(x: isize, y: isize, width: usize, height: usize) -> Option<usize> {
+    let x: usize = x.try_into().ok().filter(|&x| x < width)?;
+    let y: usize = y.try_into().ok().filter(|&y| y < height)?;
+    Some(x + y * width)
+}
+
+/// Indices of the part of row `y` between `x_range` which lies inside a row-major buffer of
+/// the given size, dropping the rest as `row_major_index` does
+fn row_major_span(
+    y: isize,
+    x_range: RangeInclusive<isize>,
+    width: usize,
+    height: usize,
+) -> Option<Range<usize>> {
+    let y: usize = y.try_into().ok().filter(|&y| y < height)?;
+    let first = (*x_range.start()).max(0) as usize;
+    let end = usize::try_from(x_range.end() + 1).ok()?.min(width);
+    (first < end).then(|| y * width + first..y * width + end)
+}
+
+/// Out of bounds, reads are transparent and writes are dropped
 impl Image for ColorImage {
     type Pixel = Color32;
     fn get_pixel(&self, x: isize, y: isize) -> Self::Pixel {
-        let x: usize = x.try_into().unwrap();
-        let y: usize = y.try_into().unwrap();
-        self.pixels[x + y * self.width()]
+        row_major_index(x, y, self.width(), self.height())
+            .map_or(Color32::TRANSPARENT, |i| self.pixels[i])
     }
 
     fn set_pixel(&mut self, x: isize, y: isize, px: Self::Pixel) {
-        let x: usize = x.try_into().unwrap();
-        let y: usize = y.try_into().unwrap();
-        let width = self.width();
-        self.pixels[x + y * width] = px;
+        if let Some(i) = row_major_index(x, y, self.width(), self.height()) {
+            self.pixels[i] = px;
+        }
+    }
+
+    fn fill_row(&mut self, y: isize, x_range: RangeInclusive<isize>, px: Self::Pixel) {
+        if let Some(span) = row_major_span(y, x_range, self.width(), self.height()) {
+            self.pixels[span].fill(px);
+        }
+    }
+
+    fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
+        (
+            0..=self.width() as isize - 1,
+            0..=self.height() as isize - 1,
+        )
+    }
+
+    fn set_pixel_out_of_bounds(&self) -> bool {
+        false
+    }
+}
+
+impl<P: Clone> Buffer2D<P> {
+    pub fn new(width: usize, height: usize, fill: P) -> Self {
+        Self {
+            data: vec![fill; width * height],
+            width,
+            height,
+        }
+    }
+}
+
+/// Out of bounds, writes are dropped, but reads panic since there is no pixel to return
+impl<P: Clone> Image for Buffer2D<P> {
+    type Pixel = P;
+    fn get_pixel(&self, x: isize, y: isize) -> Self::Pixel {
+        let (width, height) = (self.width, self.height);
+        let i = row_major_index(x, y, width, height).unwrap_or_else(|| {
+            panic!("Out of bounds get pixel at {x}, {y} in a {width}x{height} buffer")
+        });
+        self.data[i].clone()
+    }
+
+    fn set_pixel(&mut self, x: isize, y: isize, px: Self::Pixel) {
+        if let Some(i) = row_major_index(x, y, self.width, self.height) {
+            self.data[i] = px;
+        }
+    }
+
+    fn fill_row(&mut self, y: isize, x_range: RangeInclusive<isize>, px: Self::Pixel) {
+        if let Some(span) = row_major_span(y, x_range, self.width, self.height) {
+            self.data[span].fill(px);
+        }
+    }
+
+    fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
+        (0..=self.width as isize - 1, 0..=self.height as isize - 1)
+    }
+}
+
+impl<P: Clone> ImageRef for Buffer2D<P> {
+    fn get_pixel_ref(&self, x: isize, y: isize) -> &Self::Pixel {
+        let (width, height) = (self.width, self.height);
+        let i = row_major_index(x, y, width, height).unwrap_or_else(|| {
+            panic!("Out of bounds get pixel at {x}, {y} in a {width}x{height} buffer")
+        });
+        &self.data[i]
+    }
+}
+
+impl<'a> SliceImage<'a> {
+    /// Panics if `data` doesn't hold exactly `width * height` pixels
+    pub fn new(data: &'a mut [Color32], width: usize, height: usize) -> Self {
+        assert_eq!(
+            data.len(),
+            width * height,
+            "Slice of {} pixels does not match dimensions {}x{}",
+            data.len(),
+            width,
+            height
+        );
+        Self {
+            data,
+            width,
+            height,
+        }
+    }
+}
+
+/// Like `ColorImage`, out of bounds reads are transparent and writes are dropped
+impl Image for SliceImage<'_> {
+    type Pixel = Color32;
+    fn get_pixel(&self, x: isize, y: isize) -> Self::Pixel {
+        row_major_index(x, y, self.width, self.height)
+            .map_or(Color32::TRANSPARENT, |i| self.data[i])
+    }
+
+    fn set_pixel(&mut self, x: isize, y: isize, px: Self::Pixel) {
+        if let Some(i) = row_major_index(x, y, self.width, self.height) {
+            self.data[i] = px;
+        }
+    }
+
+    fn fill_row(&mut self, y: isize, x_range: RangeInclusive<isize>, px: Self::Pixel) {
+        if let Some(span) = row_major_span(y, x_range, self.width, self.height) {
+            self.data[span].fill(px);
+        }
     }
 
     fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
-        (0..=(self.width() - 1) as _, 0..=(self.height() - 1) as _)
+        (0..=self.width as isize - 1, 0..=self.height as isize - 1)
+    }
+}
+
+impl ResizableColorImage {
+    /// Wraps `image`, growing automatically with `fill`
+    pub fn new(image: ColorImage, fill: Color32) -> Self {
+        Self {
+            image,
+            origin: (0, 0),
+            fill,
+            auto_grow: true,
+        }
+    }
+
+    /// Grows the image to the right and downwards to at least `width` by `height`, filling
+    /// the new pixels with `fill`. Never shrinks it, since images may not shrink.
+    pub fn grow_to(&mut self, width: usize, height: usize, fill: Color32) {
+        let [old_width, old_height] = self.image.size;
+        let size = [width.max(old_width), height.max(old_height)];
+        self.resize(self.origin, size, fill);
+    }
+
+    /// Grows the image in whichever directions it takes to include `(x, y)`, filling the
+    /// new pixels with `fill`
+    pub fn grow_to_include(&mut self, x: isize, y: isize, fill: Color32) {
+        let (x_range, y_range) = self.image_boundaries();
+        let (left, top) = ((*x_range.start()).min(x), (*y_range.start()).min(y));
+        let (right, bottom) = ((*x_range.end()).max(x), (*y_range.end()).max(y));
+        let size = [(right - left + 1) as usize, (bottom - top + 1) as usize];
+        self.resize((left, top), size, fill);
+    }
+
+    /// Moves the top left corner to `origin` and the size to `size`, which must both keep
+    /// every existing pixel, filling the new ones with `fill`
+    fn resize(&mut self, origin: (isize, isize), size: [usize; 2], fill: Color32) {
+        if (origin, size) == (self.origin, self.image.size) {
+            return;
+        }
+        let [width, height] = size;
+        let [old_width, old_height] = self.image.size;
+        // Where the old pixels land among the new ones
+        let dx = (self.origin.0 - origin.0) as usize;
+        let dy = (self.origin.1 - origin.1) as usize;
+        let mut pixels = vec![fill; width * height];
+        for row in 0..old_height {
+            let start = (row + dy) * width + dx;
+            let old_start = row * old_width;
+            pixels[start..start + old_width]
+                .copy_from_slice(&self.image.pixels[old_start..old_start + old_width]);
+        }
+        self.image = ColorImage { size, pixels };
+        self.origin = origin;
+    }
+}
+
+/// Out of bounds, reads return `fill`. Writes grow the image if `auto_grow` is set, and are
+/// dropped otherwise.
+impl Image for ResizableColorImage {
+    type Pixel = Color32;
+    fn get_pixel(&self, x: isize, y: isize) -> Self::Pixel {
+        let [width, height] = self.image.size;
+        let (x, y) = (x - self.origin.0, y - self.origin.1);
+        row_major_index(x, y, width, height).map_or(self.fill, |i| self.image.pixels[i])
+    }
+
+    fn set_pixel(&mut self, x: isize, y: isize, px: Self::Pixel) {
+        if self.auto_grow {
+            self.grow_to_include(x, y, self.fill);
+        }
+        let (x0, y0) = self.origin;
+        self.image.set_pixel(x - x0, y - y0, px);
+    }
+
+    fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
+        let [width, height] = self.image.size;
+        let (x, y) = self.origin;
+        (x..=x + width as isize - 1, y..=y + height as isize - 1)
+    }
+
+    fn set_pixel_out_of_bounds(&self) -> bool {
+        self.auto_grow
     }
 }
 
@@ -137,6 +1044,22 @@ impl PixelInterface for Color32 {
     fn as_rgba(&self) -> Color32 {
         *self
     }
+
+    /// Interpolates each of the (premultiplied) channels separately
+    fn lerp(&self, other: &Self, t: f32, threshold: f32) -> Self {
+        let channel = |a: u8, b: u8| {
+            let value = a as f32 + (b as f32 - a as f32) * t;
+            (value + 1.0 - threshold).floor().clamp(0.0, 255.0) as u8
+        };
+        let [r, g, b, a] = self.to_array();
+        let [or, og, ob, oa] = other.to_array();
+        Color32::from_rgba_premultiplied(
+            channel(r, or),
+            channel(g, og),
+            channel(b, ob),
+            channel(a, oa),
+        )
+    }
 }
 
 #[cfg(feature = "ndarray")]
@@ -156,6 +1079,307 @@ impl<T: Clone> Image for ndarray::Array2<T> {
 
     fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
         let shape = self.shape();
-        (0..=(shape[0] - 1) as _, 0..=(shape[1] - 1) as _)
+        (0..=shape[0] as isize - 1, 0..=shape[1] as isize - 1)
+    }
+}
+
+#[cfg(feature = "ndarray")]
+impl<T: Clone> ImageRef for ndarray::Array2<T> {
+    fn get_pixel_ref(&self, x: isize, y: isize) -> &Self::Pixel {
+        let x: usize = x.try_into().unwrap();
+        let y: usize = y.try_into().unwrap();
+        &self[(x, y)]
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_2d() {
+        let mut buffer = Buffer2D::new(3, 2, 0u8);
+        assert_eq!(buffer.image_boundaries(), (0..=2, 0..=1));
+
+        buffer.set_pixel(2, 1, 7);
+        assert_eq!(buffer.data, [0, 0, 0, 0, 0, 7]);
+        assert_eq!(buffer.get_pixel(2, 1), 7);
+        assert_eq!(buffer.get_pixel_checked(3, 1), None);
+    }
+
+    #[test]
+    fn test_get_pixel_ref() {
+        let mut buffer = Buffer2D::new(2, 2, String::new());
+        buffer.set_pixel(1, 0, "large".to_string());
+        assert!(std::ptr::eq(buffer.get_pixel_ref(1, 0), &buffer.data[1]));
+        assert_eq!(buffer.translate(1, 0).get_pixel_ref(0, 0), "large");
+    }
+
+    #[test]
+    fn test_color_image_out_of_bounds() {
+        let mut image = ColorImage::new([2, 2], Color32::BLACK);
+        image.set_pixel(-1, 0, Color32::RED);
+        image.set_pixel(2, 0, Color32::RED);
+        assert_eq!(image.pixels, [Color32::BLACK; 4]);
+        assert_eq!(image.get_pixel(-1, -1), Color32::TRANSPARENT);
+        assert_eq!(image.get_pixel(0, 2), Color32::TRANSPARENT);
+
+        let mut buffer = Buffer2D::new(2, 2, 0u8);
+        buffer.set_pixel(2, 0, 1);
+        assert_eq!(buffer.data, [0; 4]);
+    }
+
+    #[test]
+    fn test_empty_image() {
+        let mut image = ColorImage::new([0, 0], Color32::BLACK);
+        assert!(!image.bounds_check(0, 0));
+        assert!(!image.set_pixel_checked(0, 0, Color32::RED));
+        assert_eq!(image.get_pixel_checked(-1, 0), None);
+        image.fill_all(Color32::RED);
+        assert!(image.pixels.is_empty());
+    }
+
+    #[test]
+    fn test_fill_rect_clips() {
+        let mut buffer = Buffer2D::new(3, 3, 0u8);
+        buffer.fill_rect(-4..=1, 2..=9, 1);
+        assert_eq!(buffer.data, [0, 0, 0, 0, 0, 0, 1, 1, 0]);
+
+        buffer.fill_rect(5..=6, 0..=2, 2);
+        assert_eq!(buffer.data, [0, 0, 0, 0, 0, 0, 1, 1, 0]);
+
+        buffer.fill_all(3);
+        assert_eq!(buffer.data, [3; 9]);
+    }
+
+    #[test]
+    fn test_blit() {
+        let mut dst = Buffer2D::new(3, 3, 0u8);
+        let src = Buffer2D {
+            data: vec![1, 2, 3, 4],
+            width: 2,
+            height: 2,
+        };
+        dst.blit(&src, (2, -1));
+        assert_eq!(dst.data, [0, 0, 3, 0, 0, 0, 0, 0, 0]);
+
+        dst.blit_with(&src, (0, 1), |&px| px == 3);
+        assert_eq!(dst.data, [0, 0, 3, 1, 2, 0, 0, 4, 0]);
+    }
+
+    #[test]
+    fn test_blit_over() {
+        let mut src = ColorImage::new([2, 1], Color32::RED);
+        src.set_pixel(1, 0, Color32::from_rgba_premultiplied(0, 0, 0, 0));
+        assert!(!Color32::RED.is_transparent());
+        assert!(Color32::TRANSPARENT.is_transparent());
+
+        let mut dst = ColorImage::new([3, 1], Color32::BLUE);
+        dst.blit_over(&src, (1, 0));
+        assert_eq!(dst.pixels, [Color32::BLUE, Color32::RED, Color32::BLUE]);
+    }
+
+    #[test]
+    fn test_translate() {
+        let mut buffer = Buffer2D::new(20, 20, 0u8);
+        let mut translated = buffer.translate(10, 10);
+        assert_eq!(translated.image_boundaries(), (-10..=9, -10..=9));
+        translated.set_pixel(0, 0, 1);
+        assert_eq!(buffer.get_pixel(10, 10), 1);
+
+        // A crop moved to the origin behaves like an image of its own
+        let mut crop = buffer.crop(12..=15, 14..=19);
+        let mut region = crop.translate(12, 14);
+        assert_eq!(region.image_boundaries(), (0..=3, 0..=5));
+        region.fill_all(2);
+        assert!(!region.set_pixel_checked(4, 0, 3));
+        assert_eq!(buffer.get_pixel(12, 14), 2);
+        assert_eq!(buffer.get_pixel(15, 19), 2);
+        assert_eq!(buffer.get_pixel(16, 14), 0);
+    }
+
+    #[test]
+    fn test_to_color_image() {
+        let mut buffer = Buffer2D::new(3, 2, Color32::BLACK);
+        buffer.set_pixel(0, 0, Color32::RED);
+        buffer.set_pixel(2, 1, Color32::BLUE);
+        let shifted = buffer.translate(1, 5);
+        assert_eq!(shifted.image_boundaries(), (-1..=1, -5..=-4));
+
+        let image = shifted.to_color_image();
+        assert_eq!(image.size, [3, 2]);
+        assert_eq!(image.pixels[0], Color32::RED);
+        assert_eq!(image.pixels[5], Color32::BLUE);
+        assert_eq!(image.pixels, buffer.to_color_image().pixels);
+    }
+
+    #[test]
+    fn test_wrap() {
+        let mut buffer = Buffer2D::new(3, 2, 0u8);
+        let mut wrapped = buffer.wrap(WrapMode::WrapXY);
+        assert!(wrapped.set_pixel_checked(-1, 0, 1));
+        wrapped.set_pixel(4, 3, 2);
+        wrapped.set_pixels(&mut [(6, -2, 3)].into_iter());
+        assert_eq!(wrapped.get_pixel(-3, -4), 3);
+        assert_eq!(buffer.data, [3, 0, 1, 0, 2, 0]);
+
+        let mut clipped = buffer.wrap(WrapMode::Clip);
+        assert!(!clipped.set_pixel_checked(-1, 0, 4));
+        assert_eq!(buffer.data, [3, 0, 1, 0, 2, 0]);
+    }
+
+    #[test]
+    fn test_fill_row() {
+        let mut image = ColorImage::new([4, 2], Color32::BLACK);
+        image.fill_row(1, -2..=1, Color32::RED);
+        image.fill_row(2, 0..=3, Color32::RED);
+        image.fill_row(0, 3..=9, Color32::BLUE);
+        let (r, b, k) = (Color32::RED, Color32::BLUE, Color32::BLACK);
+        assert_eq!(image.pixels, [k, k, k, b, r, r, k, k]);
+
+        // Masks split the run around the pixels they reject
+        let mut buffer = Buffer2D::new(5, 1, 0u8);
+        buffer.mask(|x, _| x != 2).fill_row(0, 0..=4, 1);
+        assert_eq!(buffer.data, [1, 1, 0, 1, 1]);
+
+        // Wrapping splits it where it crosses the edge
+        buffer.wrap(WrapMode::WrapXY).fill_row(-1, 3..=6, 2);
+        assert_eq!(buffer.data, [2, 2, 0, 2, 2]);
+
+        buffer.translate(1, 0).fill_row_checked(0, -3..=-1, 3);
+        assert_eq!(buffer.data, [3, 2, 0, 2, 2]);
+
+        // Crops clip it to their own bounds
+        let mut crop = buffer.crop(1..=3, 0..=0);
+        crop.fill_row(0, -5..=9, 4);
+        crop.fill_row(1, 0..=4, 5);
+        assert_eq!(buffer.data, [3, 4, 4, 4, 2]);
+    }
+
+    #[test]
+    fn test_crop_ref() {
+        let mut buffer = Buffer2D::new(4, 4, 0u8);
+        buffer.set_pixel(3, 2, 5);
+        let crop = buffer.crop_ref(2..=9, -1..=2);
+        // Another shared borrow at the same time
+        let whole = buffer.crop_ref(0..=3, 0..=3);
+
+        assert_eq!(crop.image_boundaries(), (2..=3, 0..=2));
+        assert_eq!(crop.get_pixel(3, 2), 5);
+        assert_eq!(crop.get_pixel_checked(3, 3), None);
+        assert_eq!(whole.get_pixel_checked(3, 3), Some(0));
+        assert!(!crop.bounds_check(1, 0));
+    }
+
+    #[test]
+    fn test_invert() {
+        let mut image = ColorImage::new([2, 2], Color32::BLACK);
+        image.set_pixel(1, 0, Color32::from_rgb(10, 20, 30));
+        image.for_each_pixel(|_, _, px| {
+            let [r, g, b, _] = px.to_array();
+            Some(Color32::from_rgb(255 - r, 255 - g, 255 - b))
+        });
+        assert_eq!(image.pixels[0], Color32::WHITE);
+        assert_eq!(image.pixels[1], Color32::from_rgb(245, 235, 225));
+
+        let bright = image.fold_pixels(0, |n, _, _, px| n + (px == Color32::WHITE) as usize);
+        assert_eq!(bright, 3);
+    }
+
+    #[test]
+    fn test_slice_image() {
+        let mut data = vec![Color32::BLACK; 6];
+        let mut image = SliceImage::new(&mut data, 3, 2);
+        assert_eq!(image.image_boundaries(), (0..=2, 0..=1));
+        image.set_pixel(1, 1, Color32::RED);
+        assert_eq!(image.get_pixel(1, 1), Color32::RED);
+        assert_eq!(data[4], Color32::RED);
+    }
+
+    #[test]
+    #[should_panic(expected = "does not match dimensions 3x3")]
+    fn test_slice_image_wrong_size() {
+        SliceImage::new(&mut [Color32::BLACK; 6], 3, 3);
+    }
+
+    /// Grows to fit whatever is written to it
+    #[derive(Default)]
+    pub(crate) struct Sparse(HashMap<(isize, isize), u8>);
+
+    impl Image for Sparse {
+        type Pixel = u8;
+        fn get_pixel(&self, x: isize, y: isize) -> u8 {
+            self.0.get(&(x, y)).copied().unwrap_or(0)
+        }
+
+        fn set_pixel(&mut self, x: isize, y: isize, px: u8) {
+            self.0.insert((x, y), px);
+        }
+
+        fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
+            let grow = |r: RangeInclusive<isize>, v: isize| v.min(*r.start())..=v.max(*r.end());
+            self.0.keys().fold((0..=0, 0..=0), |(xr, yr), &(x, y)| {
+                (grow(xr, x), grow(yr, y))
+            })
+        }
+
+        fn set_pixel_out_of_bounds(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_grow_keeps_pixels() {
+        let (a, b, c, d) = (Color32::RED, Color32::GREEN, Color32::BLUE, Color32::WHITE);
+        let image = ColorImage {
+            size: [2, 2],
+            pixels: vec![a, b, c, d],
+        };
+        let mut image = ResizableColorImage::new(image, Color32::BLACK);
+        image.grow_to(4, 4, Color32::BLACK);
+        assert_eq!(image.image_boundaries(), (0..=3, 0..=3));
+        let quadrant = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(x, y)| image.get_pixel(x, y));
+        assert_eq!(quadrant, [a, b, c, d]);
+        let black = |px: &&Color32| **px == Color32::BLACK;
+        assert_eq!(image.image.pixels.iter().filter(black).count(), 12);
+
+        // Never shrinks
+        image.grow_to(1, 1, Color32::BLACK);
+        assert_eq!(image.image.size, [4, 4]);
+    }
+
+    #[test]
+    fn test_auto_grow() {
+        let mut image = ResizableColorImage::new(ColorImage::default(), Color32::BLACK);
+        assert!(image.set_pixel_checked(2, 1, Color32::RED));
+        assert_eq!(image.image_boundaries(), (0..=2, 0..=1));
+        assert_eq!(image.get_pixel(2, 1), Color32::RED);
+        assert_eq!(image.get_pixel(5, 5), Color32::BLACK);
+
+        // Growing left and up keeps the other pixels where they were
+        image.set_pixel(-1, -2, Color32::BLUE);
+        assert_eq!(image.image_boundaries(), (-1..=2, -2..=1));
+        assert_eq!(image.get_pixel(-1, -2), Color32::BLUE);
+        assert_eq!(image.get_pixel(2, 1), Color32::RED);
+        assert_eq!(image.get_pixel(0, 0), Color32::BLACK);
+
+        image.auto_grow = false;
+        assert!(!image.set_pixel_checked(3, 0, Color32::RED));
+        assert_eq!(image.image.size, [4, 4]);
+    }
+
+    #[test]
+    fn test_set_pixel_out_of_bounds() {
+        let mut image = Sparse::default();
+        assert!(image.set_pixel_checked(-5, 3, 1));
+        assert_eq!(image.image_boundaries(), (-5..=0, 0..=3));
+        assert_eq!(image.get_pixel_checked(7, 7), Some(0));
+
+        // Writes through a mask still grow the image
+        assert!(image.mask(|_, _| true).set_pixel_checked(9, 0, 2));
+        assert_eq!(image.image_boundaries(), (-5..=9, 0..=3));
+
+        let mut buffer = Buffer2D::new(2, 2, 0u8);
+        assert!(!buffer.set_pixel_checked(-1, 0, 1));
     }
 }
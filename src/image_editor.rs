@@ -5,127 +5,2751 @@ use std::{
 
 use egui::{
     epaint::ImageDelta, Color32, ColorImage, Event, EventFilter, Id, ImageData, Key, Modifiers,
-    Painter, Pos2, Rect, Sense, Stroke, StrokeKind, TextureId, TextureOptions, Ui, Vec2, Widget,
+    Painter, PointerButton, Pos2, Rect, Sense, Stroke, StrokeKind, TextureId, TextureOptions,
+    TouchPhase, Ui, Vec2, Widget,
 };
 
 use crate::{
-    image::{Image, ImageExt, PixelInterface},
-    tiled_image::TiledEguiImage,
-    undo::SparseImageUndoer, Brush,
+    brush::{self, CompiledBrush},
+    fill, filter,
+    image::{Buffer2D, Image, ImageExt, MaybeSend, OutOfBounds, PixelInterface, WrapMode},
+    raster,
+    tiled_image::{TileStats, TiledEguiImage},
+    layers::{Layer, LayerStack},
+    undo::{OnChange, SparseImageUndoer}, Brush, Tool,
 };
 
+/// How far [`ImageEditor::pan_and_zoom`] lets the canvas zoom in and out
+const ZOOM_RANGE: RangeInclusive<f32> = 0.1..=100.0;
+
+/// The most pixels the fill tool's hover preview looks for, so that hovering over a huge
+/// area stays responsive. Clicking still fills all of it, and the cut off preview is
+/// outlined to show that.
+const FILL_PREVIEW_LIMIT: usize = 1 << 16;
+
 pub struct ImageEditor<Pixel> {
     tiles: TiledEguiImage,
+    /// Tiles of each onion skin image, along with the key of the image they were last
+    /// sampled from
+    onion_tiles: Vec<(u64, TiledEguiImage)>,
     undoer: SparseImageUndoer<Pixel>,
+    view: ViewTransform,
+    /// Start and current pixel of an in-progress shape drag
+    shape_drag: Option<((isize, isize), (isize, isize))>,
+    /// Where the smudge tool last picked up color during the current drag
+    smudge_from: Option<(isize, isize)>,
+    /// The last pixel the brush passed over during the current stroke, and how far it has
+    /// travelled since it last stamped
+    brush_stroke: Option<((isize, isize), f32)>,
+    /// The last brush used, so its rows aren't solved again on every stamp
+    brush_cache: Option<CompiledBrush>,
+    /// What the fill tool would fill from the hovered pixel, kept until the pointer moves
+    /// to another pixel or the image changes
+    fill_preview: Option<FillPreview>,
+    /// Pasted pixels drawn over the image until they are committed or cancelled
+    floating: Option<Floating<Pixel>>,
+    /// The canvas drawn by [`Self::draw_canvas`], until [`Self::handle_input`] takes it
+    canvas: Option<Canvas>,
+    /// Set whenever the editor changes a pixel, until [`Self::mark_saved`]
+    dirty: bool,
+    /// Whether the current press started a stroke or shape, rather than e.g. a pan
+    painting: bool,
+    /// Whether the current stroke was started with the secondary (right) button
+    secondary_stroke: bool,
+    /// Whether the current stroke has changed any pixel yet
+    stroke_drew: bool,
+    /// The changes of the last finished stroke, until [`Self::take_last_stroke`]
+    last_stroke: Option<Vec<(isize, isize, Pixel)>>,
+    /// The force of the most recent touch event, until that touch ends
+    pressure: Option<f32>,
+    /// When the current stroke was started, in seconds of [`egui::InputState::time`]
+    stroke_start: f64,
+    /// If set, all edits are restricted to these pixels, like a selection in other editors.
+    /// Pixels outside are left untouched and never enter the undo history.
+    pub mask: Option<HashSet<(isize, isize)>>,
+    /// If set, only pixels for which this returns `true` can be edited, e.g. to lock the
+    /// border of a tile map. Like pixels outside [`Self::mask`], the others are skipped by
+    /// every tool and never enter the undo history.
+    pub can_edit: Option<Box<CanEdit<'static>>>,
+    /// If set, every color the editor writes is replaced by the nearest entry, as found by
+    /// [`crate::nearest_in_palette`], so that brushes, fills and gradients stay within it.
+    /// Does nothing if empty.
+    pub palette: Option<Vec<Pixel>>,
+    /// Whether brush strokes and shapes which cross an edge of the image wrap around to the
+    /// opposite edge, for painting seamless textures
+    pub wrap_mode: WrapMode,
+    /// Whether brush strokes and shapes which cross an edge of the image are cut off there
+    /// or written past it, for images which grow to fit; see [`OutOfBounds::Grow`] for what
+    /// the image must allow. Wrapping with [`Self::wrap_mode`] takes precedence.
+    pub out_of_bounds: OutOfBounds,
+    /// If set, `edit` still draws the canvas and the hover outline but never modifies the
+    /// image: painting, undo and redo are all ignored
+    pub read_only: bool,
+    /// Shortcuts for undo and redo. Matching key events are consumed.
+    pub keybindings: Keybindings,
+    /// Whether `edit` reports the pixels it changed in [`EditResponse::changed`].
+    /// Turn this off to avoid building large lists during big operations.
+    pub record_changes: bool,
+    /// If set, `edit` fills the space available to it and moves the canvas within it, for
+    /// hosts without a `Scene`. Dragging with the middle button or with space held pans,
+    /// as does scrolling, and pinching or Ctrl+scrolling zooms. Nothing is painted while
+    /// the middle button or space is held.
+    pub pan_and_zoom: bool,
+    /// Offset of the corner of pixel `(0, 0)` from the top left of the canvas widget, in
+    /// points. Along with `zoom`, this can be saved to restore the view later.
+    pub pan: Vec2,
+    /// Width of a pixel on screen, in points
+    pub zoom: f32,
+    /// How far in pixels the pointer moves along a brush stroke between stamps. The
+    /// stroke follows a line between pointer samples, so fast drags leave no gaps.
+    /// Raising this spaces out the dabs of large brushes, which overlap heavily at 1.
+    pub brush_spacing: f32,
+    /// If set, pen pressure scales the brush of [`Tool::Brush`] strokes by a factor from
+    /// the start of this range at no pressure to its end at full pressure; see
+    /// [`Brush::scaled`]. Pointers which report no pressure, like mice, paint at the
+    /// brush's own size.
+    pub pressure_range: Option<RangeInclusive<f32>>,
+    /// The largest width or height in pixels `edit` lets a brush cover, after any scaling
+    /// by pressure or [`Self::dynamics`]. Larger brushes are clamped, outline included, so
+    /// that a huge size doesn't freeze the UI.
+    pub max_brush_extent: isize,
+    /// The image [`Tool::Stamp`] stamps, whose `None` pixels are left untouched. Empty to
+    /// begin with.
+    pub stamp: Buffer2D<Option<Pixel>>,
+    /// Called before each stamp of a [`Tool::Brush`] stroke to adjust its size and opacity,
+    /// e.g. for jitter, tapering or thinning at speed. The size multiplies any scaling
+    /// from [`Self::pressure_range`]. If unset, every stamp is the same.
+    pub dynamics: Option<Box<BrushDynamics<'static>>>,
+    /// Called with the coordinates and new value of every pixel the editor changes.
+    /// Only fires when the value actually differs from the old one, and fires before the
+    /// texture containing the pixel is marked dirty.
+    pub on_change: Option<Box<OnChange<'static, Pixel>>>,
+}
+
+/// The region [`Tool::Fill`] would fill from `start`, for drawing under the pointer
+struct FillPreview {
+    start: (isize, isize),
+    tolerance: u8,
+    /// The [`TiledEguiImage::generation`] it was found in, since any write may change it
+    generation: u64,
+    /// Sorted by row, and capped at [`FILL_PREVIEW_LIMIT`] pixels
+    region: Vec<(isize, isize)>,
+    /// Whether `region` is the whole region, rather than cut off at the cap
+    complete: bool,
+}
+
+/// The canvas widget of one pass, shared between drawing it and handling its input
+struct Canvas {
+    response: egui::Response,
+    /// Whether the user is panning rather than painting
+    navigating: bool,
+    /// The [`egui::Context::cumulative_pass_nr`] it was drawn in
+    pass: u64,
+}
+
+/// Pixels pasted with [`ImageEditor::paste`] which haven't been written to the image yet
+struct Floating<Pixel> {
+    pixels: Buffer2D<Option<Pixel>>,
+    /// Where the top left corner lands on the image
+    at: (isize, isize),
+}
+
+impl<Pixel: Clone> Floating<Pixel> {
+    fn write(&self, image: &mut dyn Image<Pixel = Pixel>) {
+        let width = self.pixels.width as isize;
+        for (i, px) in (0..).zip(&self.pixels.data) {
+            if let Some(px) = px {
+                image.set_pixel_checked(self.at.0 + i % width, self.at.1 + i / width, px.clone());
+            }
+        }
+    }
+}
+
+impl<Pixel: PixelInterface> Floating<Pixel> {
+    /// Draws the pixels, and an outline around all of them
+    fn draw(&self, painter: &Painter, view: ViewTransform) {
+        let width = self.pixels.width as isize;
+        let pixel_size = Vec2::splat(view.zoom);
+        for (i, px) in (0..).zip(&self.pixels.data) {
+            if let Some(px) = px {
+                let min = view.to_screen((self.at.0 + i % width, self.at.1 + i / width));
+                let rect = Rect::from_min_size(min, pixel_size);
+                painter.rect_filled(rect, 0.0, px.as_rgba());
+            }
+        }
+        let max = (self.at.0 + width, self.at.1 + self.pixels.height as isize);
+        let outline = Rect::from_min_max(view.to_screen(self.at), view.to_screen(max));
+        let stroke = brush::outline_stroke(view.zoom);
+        painter.rect_stroke(outline, 0.0, stroke, StrokeKind::Middle);
+    }
+}
+
+/// A whole editing session: the image, its undo history and the editing settings. Take
+/// one with [`ImageEditor::save_state`] and restore it with [`ImageEditor::load_state`].
+///
+/// With the `serde` feature it can be serialized whenever the image and its pixels can,
+/// e.g. a [`crate::image::Buffer2D`] or a `ColorImage`. No textures are saved. The view
+/// isn't either, but [`ImageEditor::pan`] and [`ImageEditor::zoom`] can be saved alongside.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EditorState<I, Pixel> {
+    pub image: I,
+    pub history: SparseImageUndoer<Pixel>,
+    pub tool: Tool<Pixel>,
+    pub stamp: Buffer2D<Option<Pixel>>,
+    pub mask: Option<HashSet<(isize, isize)>>,
+    pub wrap_mode: WrapMode,
+    pub brush_spacing: f32,
+}
+
+/// Keyboard shortcuts handled by [`ImageEditor::edit`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keybindings {
+    /// If `false`, `edit` ignores the keyboard and leaves all key events to the application
+    pub enabled: bool,
+    pub undo: Option<(Key, Modifiers)>,
+    pub redo: Option<(Key, Modifiers)>,
+    /// A second redo shortcut, since platforms disagree on which one is standard
+    pub redo_alt: Option<(Key, Modifiers)>,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            undo: Some((Key::Z, Modifiers::COMMAND)),
+            redo: Some((Key::Y, Modifiers::COMMAND)),
+            redo_alt: Some((Key::Z, Modifiers::SHIFT | Modifiers::COMMAND)),
+        }
+    }
+}
+
+/// What happened during a call to [`ImageEditor::edit`]
+pub struct EditResponse {
+    /// The response of the canvas widget, e.g. to check `dragged()` for a stroke in progress
+    pub egui_response: egui::Response,
+    /// Whether any pixel changed during this call, whether or not it was recorded
+    pub drew: bool,
+    /// Whether the pointer was released during this call, ending a stroke or shape. Its
+    /// undo frame is complete from here on. Releasing it after panning doesn't count.
+    pub stroke_finished: bool,
+    /// Every pixel painted during this call, without duplicates.
+    /// Always empty if [`ImageEditor::record_changes`] is off.
+    pub changed: Vec<(isize, isize)>,
+    /// What is under the pointer, if it is over the canvas
+    pub hover: Option<HoverInfo>,
+}
+
+/// What the pointer is doing over the canvas; see [`ImageEditor::interact`]
+pub struct Interaction {
+    /// The pixel under the pointer, even outside the image boundaries
+    pub hover: Option<(isize, isize)>,
+    /// Whether the primary button is held after being pressed on the canvas. Never set
+    /// while panning with [`ImageEditor::pan_and_zoom`].
+    pub primary_down: bool,
+    /// Like `primary_down`, for the secondary (right) button
+    pub secondary_down: bool,
+    /// Where the canvas widget is on screen
+    pub rect: Rect,
+    pub egui_response: egui::Response,
+}
+
+/// The pixel under the pointer; see [`EditResponse::hover`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HoverInfo {
+    /// The pixel a brush stamp would be centered on, even outside the image boundaries
+    pub pixel: (isize, isize),
+    /// How that pixel is displayed, or `None` if it is outside the image boundaries
+    pub color: Option<Color32>,
+}
+
+/// Predicate deciding which pixels may be edited; see [`ImageEditor::can_edit`]
+pub type CanEdit<'a> = dyn Fn(isize, isize) -> bool + 'a;
+
+/// Callback adjusting each stamp of a brush stroke; see [`ImageEditor::dynamics`]
+pub type BrushDynamics<'a> = dyn FnMut(&StrokePoint) -> BrushAdjustment + 'a;
+
+/// The pointer at one stamp of a brush stroke, as passed to [`BrushDynamics`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StrokePoint {
+    /// The pixel the brush is about to be stamped on
+    pub pixel: (isize, isize),
+    /// How fast the pointer is moving, in image pixels (not screen points) per second
+    pub velocity: Vec2,
+    /// The pen pressure from 0 to 1, or `None` if the pointer doesn't report one
+    pub pressure: Option<f32>,
+    /// Seconds since the stroke was started
+    pub time: f32,
+}
+
+/// How [`BrushDynamics`] changes one stamp
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BrushAdjustment {
+    /// Multiplies the width and height of the brush; see [`Brush::scaled`]
+    pub size: f32,
+    /// How far each pixel under the stamp moves towards the draw color, from 0 (not at
+    /// all) to 1 (replaced), blending with [`PixelInterface::lerp`]. Overlapping stamps
+    /// build up, like the flow setting of other editors.
+    pub opacity: f32,
+}
+
+impl Default for BrushAdjustment {
+    fn default() -> Self {
+        Self {
+            size: 1.0,
+            opacity: 1.0,
+        }
+    }
+}
+
+/// Maps between screen (egui) positions and image pixel coordinates
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ViewTransform {
+    /// Screen position of the corner of pixel `(0, 0)`
+    pub origin: Pos2,
+    /// Width of a pixel on screen, in points
+    pub zoom: f32,
+}
+
+impl Default for ViewTransform {
+    fn default() -> Self {
+        Self::new(Pos2::ZERO)
+    }
+}
+
+impl ViewTransform {
+    pub fn new(origin: Pos2) -> Self {
+        Self { origin, zoom: 1.0 }
+    }
+
+    /// The pixel containing the screen position `pos`
+    pub fn to_pixel(&self, pos: Pos2) -> (isize, isize) {
+        let pos = ((pos - self.origin) / self.zoom).floor();
+        (pos.x as _, pos.y as _)
+    }
+
+    /// The screen position of the top-left corner of pixel `(x, y)`
+    pub fn to_screen(&self, (x, y): (isize, isize)) -> Pos2 {
+        self.origin + Vec2::new(x as _, y as _) * self.zoom
+    }
 }
 
 impl<Pixel: PixelInterface> ImageEditor<Pixel> {
     pub fn from_tile_size(tile_texture_width: usize) -> Self {
-        Self {
-            tiles: TiledEguiImage::from_tile_size(tile_texture_width),
-            undoer: SparseImageUndoer::new(),
-        } 
+        Self::from_tiles(TiledEguiImage::from_tile_size(tile_texture_width))
     }
 
+    /// An editor whose tiles are sized to suit the image it is drawn with: small images get
+    /// a single texture of about their own size, while larger ones are still tiled. The
+    /// tiles grow along with the image.
     pub fn new(ctx: &egui::Context) -> Self {
+        Self::from_tiles(TiledEguiImage::new(ctx))
+    }
+
+    /// Like [`Self::new`], but starting with tiles to suit an image of `(width, height)`
+    /// pixels
+    pub fn new_for_image(ctx: &egui::Context, dims: (usize, usize)) -> Self {
+        Self::from_tiles(TiledEguiImage::new_for_image(ctx, dims))
+    }
+
+    fn from_tiles(tiles: TiledEguiImage) -> Self {
         Self {
-            tiles: TiledEguiImage::new(ctx),
+            tiles,
+            onion_tiles: vec![],
             undoer: SparseImageUndoer::new(),
+            view: ViewTransform::default(),
+            shape_drag: None,
+            smudge_from: None,
+            brush_stroke: None,
+            brush_cache: None,
+            fill_preview: None,
+            floating: None,
+            canvas: None,
+            dirty: false,
+            painting: false,
+            secondary_stroke: false,
+            stroke_drew: false,
+            last_stroke: None,
+            pressure: None,
+            stroke_start: 0.0,
+            mask: None,
+            can_edit: None,
+            palette: None,
+            wrap_mode: WrapMode::Clip,
+            out_of_bounds: OutOfBounds::Clip,
+            read_only: false,
+            keybindings: Keybindings::default(),
+            record_changes: true,
+            pan_and_zoom: false,
+            pan: Vec2::ZERO,
+            zoom: 1.0,
+            brush_spacing: 1.0,
+            pressure_range: None,
+            max_brush_extent: 1024,
+            stamp: Buffer2D {
+                data: vec![],
+                width: 0,
+                height: 0,
+            },
+            dynamics: None,
+            on_change: None,
         }
     }
 
-    pub fn draw(&mut self, 
+    /// Draws the image without editing it. Only needs to read the image, so the same one
+    /// can be drawn by several editors at once.
+    pub fn draw(&mut self, ui: &mut Ui, image: &impl Image<Pixel = Pixel>, pos: Pos2)
+    where
+        Pixel: MaybeSend,
+    {
+        self.tiles.draw(ui, image, ViewTransform::new(pos))
+    }
+
+    /// Draws the image and lets the user edit it with `tool`, painting in `draw_color`
+    /// with either mouse button
+    pub fn edit(
+        &mut self,
         ui: &mut Ui,
         image: &mut impl Image<Pixel = Pixel>,
-        pos: Pos2,
-    ) {
-        self.tiles.draw(ui, image, pos)
+        draw_color: Pixel,
+        tool: impl Into<Tool<Pixel>>,
+    ) -> EditResponse
+    where
+        Pixel: PartialEq + Clone + MaybeSend,
+    {
+        self.edit_with_secondary(ui, image, draw_color.clone(), draw_color, tool)
     }
 
-    pub fn edit(
+    /// Like [`Self::edit`], but strokes started with the secondary (right) button paint in
+    /// `secondary` instead of `primary`, e.g. for a background color
+    pub fn edit_with_secondary(
         &mut self,
         ui: &mut Ui,
         image: &mut impl Image<Pixel = Pixel>,
+        primary: Pixel,
+        secondary: Pixel,
+        tool: impl Into<Tool<Pixel>>,
+    ) -> EditResponse
+    where
+        Pixel: PartialEq + Clone + MaybeSend,
+    {
+        self.draw_canvas(ui, image);
+        self.handle_input(ui, image, primary, secondary, tool)
+    }
+
+    /// Like [`Self::edit`] for a [`LayerStack`]: shows its visible layers blended together,
+    /// but paints on the active one only, and undoes on the layer each change was made to
+    pub fn edit_layers(
+        &mut self,
+        ui: &mut Ui,
+        stack: &mut LayerStack<Pixel>,
         draw_color: Pixel,
-        brush: Brush,
-    ) -> egui::Response where
-        Pixel: PartialEq + Copy,
+        tool: impl Into<Tool<Pixel>>,
+    ) -> EditResponse
+    where
+        Pixel: PartialEq + Clone + MaybeSend,
     {
-        let (x_range, y_range) = image.image_boundaries();
-        let image_rect = Rect::from_min_max(
-            Pos2::new(*x_range.start() as f32, *y_range.start() as f32),
-            Pos2::new(*x_range.end() as f32 + 1.0, *y_range.end() as f32 + 1.0),
-        );
+        self.draw_canvas(ui, &stack.composite());
+        // Another layer has other regions to fill
+        if self.undoer.layer() != stack.active() {
+            self.fill_preview = None;
+        }
+        self.undoer.set_layer(stack.active());
+        // Taken before `handle_input` sees them, which would undo on the active layer
+        let (undos, redos) = self.consume_undo_keys(ui);
+        for _ in 0..undos {
+            self.undo_layers(stack);
+        }
+        for _ in 0..redos {
+            self.redo_layers(stack);
+        }
+        self.handle_input(ui, stack, draw_color.clone(), draw_color, tool)
+    }
+
+    /// Removes the layer at `index` from `stack` along with its undo history, and renumbers
+    /// the history of the layers above it; see [`LayerStack::remove_layer`]
+    pub fn remove_layer(
+        &mut self,
+        stack: &mut LayerStack<Pixel>,
+        index: usize,
+    ) -> Option<Layer<Pixel>>
+    where
+        Pixel: Clone,
+    {
+        let layer = stack.remove_layer(index)?;
+        self.undoer.remove_layer(index);
+        self.undoer.set_layer(stack.active());
+        self.invalidate_all();
+        Some(layer)
+    }
+
+    /// The first half of [`Self::edit`]: allocates the canvas, handles panning and zooming
+    /// it and draws the image, without editing anything. Follow it with
+    /// [`Self::handle_input`] in the same pass, e.g. after drawing overlays of your own
+    /// over the image. What is drawn needn't be the image being edited, nor even have the
+    /// same type of pixel, e.g. [`crate::LayerStack::composite`].
+    pub fn draw_canvas<Shown: PixelInterface + MaybeSend>(
+        &mut self,
+        ui: &mut Ui,
+        image: &impl Image<Pixel = Shown>,
+    ) -> egui::Response {
+        self.draw_canvas_behind(ui, image, |_, _| ())
+    }
+
+    /// Like [`Self::draw_canvas`], but first draws each image of `onion_skin` multiplied
+    /// by its tint, e.g. the neighbouring frames of an animation in translucent red and
+    /// green. They show through wherever the image is transparent, and only the image
+    /// itself is edited.
+    ///
+    /// Each image comes with a key, which the caller changes whenever the image in that
+    /// place is replaced or edited, e.g. the frame number along with a count of its edits.
+    /// The textures of each onion skin image are kept between passes while its key stays
+    /// the same, and sampled again when it changes.
+    pub fn draw_canvas_with_onion_skin<Shown, U, T>(
+        &mut self,
+        ui: &mut Ui,
+        image: &impl Image<Pixel = Shown>,
+        onion_skin: &[(u64, &U, Color32)],
+    ) -> egui::Response
+    where
+        Shown: PixelInterface + MaybeSend,
+        U: Image<Pixel = T>,
+        T: PixelInterface + MaybeSend,
+    {
+        self.draw_canvas_behind(ui, image, |editor, ui| {
+            editor.onion_tiles.truncate(onion_skin.len());
+            while editor.onion_tiles.len() < onion_skin.len() {
+                let tiles = editor.tiles.empty_like();
+                editor.onion_tiles.push((0, tiles));
+            }
+            for (&(key, image, tint), (sampled, tiles)) in
+                onion_skin.iter().zip(&mut editor.onion_tiles)
+            {
+                if *sampled != key {
+                    *sampled = key;
+                    tiles.invalidate_all();
+                }
+                tiles.draw_tinted(ui, image, editor.view, tint);
+            }
+        })
+    }
 
-        let resp = ui.allocate_response(image_rect.size(), Sense::click_and_drag());
+    /// Like [`Self::edit`], drawing `onion_skin` behind the image as
+    /// [`Self::draw_canvas_with_onion_skin`] does
+    pub fn edit_with_onion_skin<U, T>(
+        &mut self,
+        ui: &mut Ui,
+        image: &mut impl Image<Pixel = Pixel>,
+        onion_skin: &[(u64, &U, Color32)],
+        draw_color: Pixel,
+        tool: impl Into<Tool<Pixel>>,
+    ) -> EditResponse
+    where
+        Pixel: PartialEq + Clone + MaybeSend,
+        U: Image<Pixel = T>,
+        T: PixelInterface + MaybeSend,
+    {
+        self.draw_canvas_with_onion_skin(ui, image, onion_skin);
+        self.handle_input(ui, image, draw_color.clone(), draw_color, tool)
+    }
 
-        if resp.drag_started() || resp.clicked() {
-            self.undoer.new_frame();
+    /// Samples every onion skin image again on the next draw, whatever their keys
+    pub fn invalidate_onion_skin(&mut self) {
+        for (_, tiles) in &mut self.onion_tiles {
+            tiles.invalidate_all();
         }
+    }
 
-        self.tiles.draw(ui, image, resp.rect.min);
+    /// Allocates the canvas and draws the image over whatever `behind` draws first
+    fn draw_canvas_behind<Shown: PixelInterface + MaybeSend>(
+        &mut self,
+        ui: &mut Ui,
+        image: &impl Image<Pixel = Shown>,
+        behind: impl FnOnce(&mut Self, &mut Ui),
+    ) -> egui::Response {
+        let (response, navigating) = self.allocate_canvas(ui, image);
 
-        let mut image = self.tiles.track(image);
+        // The canvas may be panned past the edges of the widget
+        let clip_rect = ui.clip_rect();
+        if self.pan_and_zoom {
+            ui.set_clip_rect(clip_rect.intersect(response.rect));
+        }
+        behind(self, ui);
+        self.tiles.draw(ui, image, self.view);
+        ui.set_clip_rect(clip_rect);
+
+        self.canvas = Some(Canvas {
+            response: response.clone(),
+            navigating,
+            pass: ui.ctx().cumulative_pass_nr(),
+        });
+        response
+    }
+
+    /// The second half of [`Self::edit_with_secondary`]: handles the shortcuts and lets the
+    /// user edit the image with `tool` on the canvas from [`Self::draw_canvas`]. Draws the
+    /// canvas first if it wasn't drawn during this pass.
+    pub fn handle_input(
+        &mut self,
+        ui: &mut Ui,
+        image: &mut impl Image<Pixel = Pixel>,
+        primary: Pixel,
+        secondary: Pixel,
+        tool: impl Into<Tool<Pixel>>,
+    ) -> EditResponse
+    where
+        Pixel: PartialEq + Clone + MaybeSend,
+    {
+        let tool = tool.into().clamp_brushes(self.max_brush_extent);
+        let Canvas {
+            response: resp,
+            navigating,
+            ..
+        } = self.take_canvas(ui, image);
+        let view = self.view;
+        let can_paint = !self.read_only && !navigating && self.floating.is_none();
 
-        let events = ui.input(|i| i.filtered_events(&EventFilter::default()));
-        for event in events {
-            match event {
-                // Undo
-                Event::Key {
-                    key: Key::Z,
-                    pressed: true,
-                    modifiers,
-                    ..
-                } if modifiers.matches_logically(Modifiers::COMMAND) => {
-                    self.undoer.undo(&mut image);
+        // Each press starts a new undo frame. Waiting for a drag or click to be recognized
+        // would put whatever is drawn before then in the previous frame.
+        let pressed = resp.is_pointer_button_down_on() && ui.input(|i| i.pointer.any_pressed());
+        if can_paint && pressed {
+            let time = ui.input(|i| i.time);
+            self.undoer.new_frame_at(tool.name(), time);
+        }
+        if pressed {
+            self.painting = can_paint;
+            self.stroke_drew = false;
+            let button = |i: &egui::InputState| i.pointer.button_pressed(PointerButton::Secondary);
+            self.secondary_stroke = ui.input(button);
+            self.stroke_start = ui.input(|i| i.time);
+        }
+        let draw_color = if self.secondary_stroke {
+            &secondary
+        } else {
+            &primary
+        };
+
+        ui.input(|i| {
+            for event in &i.events {
+                if let Event::Touch { phase, force, .. } = event {
+                    self.pressure = match phase {
+                        TouchPhase::End | TouchPhase::Cancel => None,
+                        _ => force.or(self.pressure),
+                    };
                 }
+            }
+        });
+        let brush_scale = match (&self.pressure_range, self.pressure) {
+            (Some(range), Some(pressure)) => {
+                range.start() + (range.end() - range.start()) * pressure.clamp(0.0, 1.0)
+            }
+            _ => 1.0,
+        };
+
+        let clip_rect = ui.clip_rect();
+        if self.pan_and_zoom {
+            ui.set_clip_rect(clip_rect.intersect(resp.rect));
+        }
+
+        let (undos, redos) = self.consume_undo_keys(ui);
+        for _ in 0..undos {
+            self.undo(image);
+        }
+        for _ in 0..redos {
+            self.redo(image);
+        }
+
+        // Not one of the configurable shortcuts, but only taken from other widgets while the
+        // canvas is hovered or focused
+        let targeted = resp.hover_pos().is_some() || resp.has_focus();
+        if self.floating.is_some() && !self.read_only && targeted {
+            let count = |mods, key| ui.input_mut(|i| i.count_and_consume_key(mods, key));
+            // Shift first, since unmodified arrows would also match shifted ones
+            for (step, modifiers) in [(10, Modifiers::SHIFT), (1, Modifiers::NONE)] {
+                let dx = count(modifiers, Key::ArrowRight) as isize
+                    - count(modifiers, Key::ArrowLeft) as isize;
+                let dy = count(modifiers, Key::ArrowDown) as isize
+                    - count(modifiers, Key::ArrowUp) as isize;
+                self.nudge_floating(dx * step, dy * step);
+            }
+            if count(Modifiers::NONE, Key::Enter) > 0 {
+                self.commit_floating(image);
+            } else if count(Modifiers::NONE, Key::Escape) > 0 {
+                self.cancel_floating();
+            }
+        }
+
+        if let Some(floating) = &self.floating {
+            floating.draw(ui.painter(), view);
+        }
+
+        let mut image = image.out_of_bounds(self.out_of_bounds);
+        let mut image = self.tiles.track(&mut image);
+
+        let mut changed = vec![];
+        let mut drew = false;
+        let record_changes = self.record_changes;
+        let user_on_change = &mut self.on_change;
+        let dirty = &mut self.dirty;
+        let mut notify = |x: isize, y: isize, px: &Pixel| {
+            *dirty = true;
+            drew = true;
+            if let Some(f) = user_on_change {
+                f(x, y, px);
+            }
+            if record_changes {
+                changed.push((x, y));
+            }
+        };
+
+        let hover = resp.hover_pos().map(|pos| view.to_pixel(pos));
+        if let Some(hover) = hover {
+            tool.draw_hover(ui.painter(), view, hover, &self.stamp);
+            if can_paint {
+                ui.output_mut(|o| o.cursor_icon = tool.cursor_icon());
+            }
+        }
 
-                // Redo
-                Event::Key {
-                    key,
-                    pressed: true,
-                    modifiers,
-                    ..
-                } if (modifiers.matches_logically(Modifiers::COMMAND) && key == Key::Y)
-                    || (modifiers.matches_logically(Modifiers::SHIFT | Modifiers::COMMAND)
-                        && key == Key::Z) =>
-                {
-                    self.undoer.redo(&mut image);
+        if matches!(tool, Tool::Brush(_) | Tool::Stamp)
+            && can_paint
+            && let Some(interact_pointer_pos) = resp.interact_pointer_pos()
+        {
+            let to = view.to_pixel(interact_pointer_pos);
+            let stamps = stroke_to(&mut self.brush_stroke, self.brush_spacing, to);
+            let mut image = self.undoer.track_with(&mut image, Some(&mut notify));
+            let mut image = image.mask(|x, y| editable(&self.mask, &self.can_edit, x, y));
+            let mut image = image.map_writes(|px| snap(&self.palette, px));
+            let mut image = image.wrap(self.wrap_mode);
+            match tool {
+                Tool::Brush(brush) => {
+                    let (velocity, time) = ui.input(|i| (i.pointer.velocity(), i.time));
+                    for pixel in stamps {
+                        let point = StrokePoint {
+                            pixel,
+                            velocity: velocity / view.zoom,
+                            pressure: self.pressure,
+                            time: (time - self.stroke_start) as f32,
+                        };
+                        let adjust = match &mut self.dynamics {
+                            Some(dynamics) => dynamics(&point),
+                            None => BrushAdjustment::default(),
+                        };
+                        // Recompiled whenever pressure or dynamics change the size
+                        let scale = brush_scale * adjust.size;
+                        let max = self.max_brush_extent;
+                        let scaled = (scale != 1.0).then(|| brush.scaled(scale).clamped(max));
+                        let brush = scaled.unwrap_or(brush);
+                        let brush = CompiledBrush::cached(&mut self.brush_cache, brush);
+                        if adjust.opacity >= 1.0 {
+                            brush.runs(pixel.0, pixel.1, |y, x_range| {
+                                image.fill_row_checked(y, x_range, draw_color.clone());
+                            });
+                        } else {
+                            brush.pixels(pixel.0, pixel.1, |x, y| {
+                                if let Some(px) = image.get_pixel_checked(x, y) {
+                                    let px = px.lerp(draw_color, adjust.opacity, 0.5);
+                                    image.set_pixel_checked(x, y, px);
+                                }
+                            });
+                        }
+                    }
+                }
+                Tool::Stamp => {
+                    for pos in stamps {
+                        brush::stamp(&mut image, &self.stamp, pos);
+                    }
                 }
                 _ => (),
             }
+        } else {
+            self.brush_stroke = None;
+        }
+
+        if let Tool::Smudge { brush, strength } = tool
+            && can_paint
+            && let Some(interact_pointer_pos) = resp.interact_pointer_pos()
+        {
+            let to = view.to_pixel(interact_pointer_pos);
+            if let Some(from) = self.smudge_from
+                && from != to
+            {
+                let mut image = self.undoer.track_with(&mut image, Some(&mut notify));
+                let mut image = image.mask(|x, y| editable(&self.mask, &self.can_edit, x, y));
+                let mut image = image.map_writes(|px| snap(&self.palette, px));
+                let mut image = image.wrap(self.wrap_mode);
+                let brush = CompiledBrush::cached(&mut self.brush_cache, brush);
+                brush::smudge(&mut image, brush, from, to, strength);
+            }
+            self.smudge_from = Some(to);
+        } else {
+            self.smudge_from = None;
+        }
+
+        if tool.is_shape() && can_paint {
+            if resp.drag_started()
+                && let Some(origin) = ui.input(|i| i.pointer.press_origin())
+            {
+                let origin = view.to_pixel(origin);
+                self.shape_drag = Some((origin, origin));
+            }
+
+            if let Some((_, end)) = &mut self.shape_drag
+                && let Some(pos) = resp.interact_pointer_pos()
+            {
+                *end = view.to_pixel(pos);
+            }
+
+            if let Some((start, end)) = self.shape_drag {
+                let end = tool.constrain(start, end, ui.input(|i| i.modifiers.shift));
+                if resp.drag_stopped() {
+                    self.shape_drag = None;
+                    let mut image = self.undoer.track_with(&mut image, Some(&mut notify));
+                    let mut image = image.mask(|x, y| editable(&self.mask, &self.can_edit, x, y));
+                    let mut image = image.map_writes(|px| snap(&self.palette, px));
+                    let mut image = image.wrap(self.wrap_mode);
+                    tool.draw_shape(&mut image, start, end, draw_color.clone());
+                } else {
+                    let color = snap(&self.palette, draw_color.clone()).as_rgba();
+                    tool.draw_preview(ui.painter(), view, start, end, color);
+                }
+            }
+        } else {
+            self.shape_drag = None;
+        }
+
+        if let Tool::Fill { tolerance } = tool
+            && can_paint
+        {
+            if pressed && let Some(pos) = resp.interact_pointer_pos() {
+                let mut image = self.undoer.track_with(&mut image, Some(&mut notify));
+                let mut image = image.mask(|x, y| editable(&self.mask, &self.can_edit, x, y));
+                let mut image = image.map_writes(|px| snap(&self.palette, px));
+                fill::flood_fill(&mut image, view.to_pixel(pos), draw_color, tolerance);
+                self.fill_preview = None;
+            } else if let Some(hover) = hover {
+                let key = (hover, tolerance, image.generation());
+                let preview_key = |p: &FillPreview| (p.start, p.tolerance, p.generation);
+                if self.fill_preview.as_ref().map(preview_key) != Some(key) {
+                    let (mut region, complete) =
+                        fill::flood_region(&image, hover, tolerance, FILL_PREVIEW_LIMIT);
+                    region.retain(|&(x, y)| editable(&self.mask, &self.can_edit, x, y));
+                    region.sort_unstable_by_key(|&(x, y)| (y, x));
+                    self.fill_preview = Some(FillPreview {
+                        start: hover,
+                        tolerance,
+                        generation: key.2,
+                        region,
+                        complete,
+                    });
+                }
+                if let Some(preview) = &self.fill_preview {
+                    let color = snap(&self.palette, primary.clone()).as_rgba();
+                    draw_region(ui.painter(), view, &preview.region, color);
+                    // The fill would go past the part found so far
+                    if !preview.complete {
+                        outline_region(ui.painter(), view, &preview.region);
+                    }
+                }
+            }
+        } else {
+            self.fill_preview = None;
+        }
+
+        ui.set_clip_rect(clip_rect);
+
+        let stroke_finished = self.painting && (resp.drag_stopped() || resp.clicked());
+        let hover = hover.map(|pixel| HoverInfo {
+            pixel,
+            color: image
+                .bounds_check(pixel.0, pixel.1)
+                .then(|| image.get_pixel(pixel.0, pixel.1).as_rgba()),
+        });
+
+        if drew {
+            self.fill_preview = None;
+        }
+        self.stroke_drew |= drew;
+        if stroke_finished && self.stroke_drew {
+            self.stroke_drew = false;
+            self.last_stroke = Some(self.undoer.last_frame_changes());
         }
 
-        let egui_to_pixel = |pos: Pos2| -> (isize, isize) {
-            let pos = (pos - resp.rect.min.to_vec2()).floor();
-            (pos.x as _, pos.y as _)
+        changed.sort_unstable();
+        changed.dedup();
+        EditResponse {
+            egui_response: resp,
+            drew,
+            stroke_finished,
+            changed,
+            hover,
+        }
+    }
+
+    /// Allocates the canvas widget for `image` and handles panning and zooming it, leaving
+    /// the transform to draw with in `self.view`. Also returns whether the user is
+    /// navigating rather than painting, e.g. by holding space.
+    fn allocate_canvas<Shown>(
+        &mut self,
+        ui: &mut Ui,
+        image: &impl Image<Pixel = Shown>,
+    ) -> (egui::Response, bool) {
+        let (x_range, y_range) = image.image_boundaries();
+        let image_rect = Rect::from_min_max(
+            Pos2::new(*x_range.start() as f32, *y_range.start() as f32),
+            Pos2::new(*x_range.end() as f32 + 1.0, *y_range.end() as f32 + 1.0),
+        );
+
+        // Read-only canvases only sense hover, so drags fall through to e.g. a surrounding Scene
+        let sense = if self.read_only && !self.pan_and_zoom {
+            Sense::hover()
+        } else {
+            Sense::click_and_drag()
+        };
+        let size = if self.pan_and_zoom {
+            ui.available_size()
+        } else {
+            image_rect.size() * self.zoom
         };
+        let resp = ui.allocate_response(size, sense);
+
+        let navigating =
+            self.pan_and_zoom && ui.input(|i| i.key_down(Key::Space) || i.pointer.middle_down());
+        if navigating && resp.dragged() {
+            self.pan += resp.drag_delta();
+        }
+        if self.pan_and_zoom && resp.hovered() {
+            let (scroll, zoom_delta) = ui.input(|i| (i.smooth_scroll_delta, i.zoom_delta()));
+            self.pan += scroll;
+            if zoom_delta != 1.0
+                && let Some(pointer) = resp.hover_pos()
+            {
+                // Keep the point under the pointer where it is
+                let zoom = (self.zoom * zoom_delta).clamp(*ZOOM_RANGE.start(), *ZOOM_RANGE.end());
+                let anchor = pointer - resp.rect.min - self.pan;
+                self.pan += anchor - anchor * (zoom / self.zoom);
+                self.zoom = zoom;
+            }
+        }
 
-        let pixel_to_egui =
-            |(x, y): (isize, isize)| -> Pos2 { resp.rect.min + Vec2::new(x as _, y as _) };
+        self.view = ViewTransform {
+            origin: resp.rect.min + self.pan,
+            zoom: self.zoom,
+        };
+        (resp, navigating)
+    }
 
-        if let Some(pointer_pos) = resp.hover_pos() {
-            let quantized_pos = pixel_to_egui(egui_to_pixel(pointer_pos));
-            brush.draw(ui.painter(), quantized_pos);
+    /// The canvas drawn during this pass, drawing it now if it wasn't
+    /// How many times the undo and redo shortcuts were pressed during this pass, consuming
+    /// them. None are while the shortcuts are disabled or the editor is read-only.
+    fn consume_undo_keys(&self, ui: &mut Ui) -> (usize, usize) {
+        if !self.keybindings.enabled || self.read_only {
+            return (0, 0);
         }
+        let consume = |binding: Option<(Key, Modifiers)>| match binding {
+            Some((key, modifiers)) => ui.input_mut(|i| i.count_and_consume_key(modifiers, key)),
+            None => 0,
+        };
+        // Redo first: its default Cmd+Shift+Z would also match a Cmd+Z undo
+        let redos = consume(self.keybindings.redo_alt) + consume(self.keybindings.redo);
+        (consume(self.keybindings.undo), redos)
+    }
 
-        if let Some(interact_pointer_pos) = resp.interact_pointer_pos() {
-            let (x, y) = egui_to_pixel(interact_pointer_pos);
-            let mut image = self.undoer.track(&mut image);
-            brush.pixels(x, y, |x, y| {
-                image.set_pixel_checked(x, y, draw_color);
-            });
-            //self.undoer.sync_set_pixel(image, x, y, draw);
+    fn take_canvas(&mut self, ui: &mut Ui, image: &impl Image<Pixel = Pixel>) -> Canvas
+    where
+        Pixel: MaybeSend,
+    {
+        let pass = ui.ctx().cumulative_pass_nr();
+        match self.canvas.take() {
+            Some(canvas) if canvas.pass == pass => canvas,
+            _ => {
+                self.draw_canvas(ui, image);
+                self.canvas.take().expect("the canvas was just drawn")
+            }
+        }
+    }
+
+    /// Allocates and draws the canvas like [`Self::edit`], but leaves the image alone and
+    /// only reports what the pointer is doing, for tools of your own. Pan and zoom are
+    /// handled as usual, and [`Self::view_transform`] maps to and from the canvas after.
+    /// The shapes in [`crate::raster`] help fill in between pointer positions.
+    pub fn interact(&mut self, ui: &mut Ui, image: &impl Image<Pixel = Pixel>) -> Interaction
+    where
+        Pixel: MaybeSend,
+    {
+        self.draw_canvas(ui, image);
+        let Canvas {
+            response: resp,
+            navigating,
+            ..
+        } = self.take_canvas(ui, image);
+        let view = self.view;
+
+        let held = resp.is_pointer_button_down_on() && !navigating;
+        let down = |button| held && ui.input(|i| i.pointer.button_down(button));
+        Interaction {
+            hover: resp.hover_pos().map(|pos| view.to_pixel(pos)),
+            primary_down: down(PointerButton::Primary),
+            secondary_down: down(PointerButton::Secondary),
+            rect: resp.rect,
+            egui_response: resp,
         }
+    }
 
-        resp
+    /// Allocates textures for the canvas tiles overlapping `rect` (in pixel coordinates)
+    /// without drawing them, e.g. to warm up the tiles just outside the viewport
+    pub fn prefetch(
+        &mut self,
+        ctx: &egui::Context,
+        image: &impl Image<Pixel = Pixel>,
+        rect: Rect,
+    ) {
+        self.tiles.prefetch(ctx, image, rect);
     }
 
-    /// Forces the backend to upload to the GPU once more
-    pub fn force_image_update(&mut self) {
-        self.tiles.mark_all_dirty();
-        self.undoer.reset();
+    /// Runs `f` on the image as a single undo frame, keeping the textures in sync.
+    /// Use this for whole-image operations such as those in [`crate::transform`].
+    pub fn apply(
+        &mut self,
+        image: &mut impl Image<Pixel = Pixel>,
+        f: impl FnOnce(&mut dyn Image<Pixel = Pixel>),
+    ) where
+        Pixel: PartialEq + Clone,
+    {
+        self.apply_named("", image, f);
+    }
+
+    /// Like [`Self::apply`], but labels the undo frame; see [`SparseImageUndoer::history`]
+    pub fn apply_named(
+        &mut self,
+        label: impl Into<String>,
+        image: &mut impl Image<Pixel = Pixel>,
+        f: impl FnOnce(&mut dyn Image<Pixel = Pixel>),
+    ) where
+        Pixel: PartialEq + Clone,
+    {
+        self.undoer.new_frame_named(label);
+        self.fill_preview = None;
+        let mut image = self.tiles.track(image);
+        let user_on_change = &mut self.on_change;
+        let dirty = &mut self.dirty;
+        let mut notify = |x: isize, y: isize, px: &Pixel| {
+            *dirty = true;
+            if let Some(f) = user_on_change {
+                f(x, y, px);
+            }
+        };
+        let mut image = self.undoer.track_with(&mut image, Some(&mut notify));
+        let mut image = image.mask(|x, y| editable(&self.mask, &self.can_edit, x, y));
+        let mut image = image.map_writes(|px| snap(&self.palette, px));
+        f(&mut image);
+    }
+
+    /// The pixels changed by the last stroke painted with the mouse, with their new values,
+    /// once it has finished; see [`SparseImageUndoer::last_frame_changes`]. Only returns
+    /// each stroke once, e.g. for sending it to other peers editing the same image, which
+    /// pass it to [`Self::apply_remote_stroke`].
+    pub fn take_last_stroke(&mut self) -> Option<Vec<(isize, isize, Pixel)>> {
+        self.last_stroke.take()
+    }
+
+    /// Writes a stroke from [`Self::take_last_stroke`] of another editor as a single undo
+    /// frame, so that it can be undone locally like any other. The stroke was already
+    /// masked, checked against [`Self::can_edit`], wrapped and snapped to a palette where it
+    /// was painted, so none of that applies again here. Pixels outside of this image, such
+    /// as from a peer whose image is larger, are skipped.
+    pub fn apply_remote_stroke(
+        &mut self,
+        image: &mut impl Image<Pixel = Pixel>,
+        stroke: &[(isize, isize, Pixel)],
+    ) where
+        Pixel: PartialEq + Clone,
+    {
+        let (mask, palette) = (self.mask.take(), self.palette.take());
+        let can_edit = self.can_edit.take();
+        let (x_range, y_range) = image.image_boundaries();
+        let mut in_bounds = stroke
+            .iter()
+            .filter(|(x, y, _)| x_range.contains(x) && y_range.contains(y))
+            .cloned();
+        self.apply_named("Remote", image, |image| image.set_pixels(&mut in_bounds));
+        (self.mask, self.palette, self.can_edit) = (mask, palette, can_edit);
+    }
+
+    /// Sets every pixel within the image boundaries (and the mask, if any) to `color`,
+    /// as a single undo frame
+    pub fn fill_all(&mut self, image: &mut impl Image<Pixel = Pixel>, color: Pixel)
+    where
+        Pixel: PartialEq + Clone,
+    {
+        self.apply_named("Fill", image, |image| image.fill_all(color));
+    }
+
+    /// Reverts the most recent undo frame, as the undo shortcut does
+    pub fn undo(&mut self, image: &mut impl Image<Pixel = Pixel>)
+    where
+        Pixel: PartialEq + Clone,
+    {
+        self.dirty |= self.undoer.can_undo();
+        self.fill_preview = None;
+        self.undoer.undo(&mut self.tiles.track(image));
+    }
+
+    /// Like [`Self::undo`] for a [`LayerStack`]; see [`SparseImageUndoer::undo_layers`]
+    pub fn undo_layers(&mut self, stack: &mut LayerStack<Pixel>)
+    where
+        Pixel: PartialEq + Clone,
+    {
+        self.dirty |= self.undoer.can_undo();
+        self.fill_preview = None;
+        let Some(layer) = self.undoer.undo_layer() else {
+            return;
+        };
+        let (undoer, tiles) = (&mut self.undoer, &mut self.tiles);
+        stack.with_layer(layer, |stack| match stack {
+            Some(stack) => undoer.undo_on(Some(&mut tiles.track(stack))),
+            None => undoer.undo_on::<LayerStack<Pixel>>(None),
+        });
+    }
+
+    /// Reapplies the most recently undone frame, as the redo shortcut does
+    pub fn redo(&mut self, image: &mut impl Image<Pixel = Pixel>)
+    where
+        Pixel: PartialEq + Clone,
+    {
+        self.dirty |= self.undoer.can_redo();
+        self.fill_preview = None;
+        self.undoer.redo(&mut self.tiles.track(image));
+    }
+
+    /// Like [`Self::redo`] for a [`LayerStack`]; see [`SparseImageUndoer::redo_layers`]
+    pub fn redo_layers(&mut self, stack: &mut LayerStack<Pixel>)
+    where
+        Pixel: PartialEq + Clone,
+    {
+        self.dirty |= self.undoer.can_redo();
+        self.fill_preview = None;
+        let Some(layer) = self.undoer.redo_layer() else {
+            return;
+        };
+        let (undoer, tiles) = (&mut self.undoer, &mut self.tiles);
+        stack.with_layer(layer, |stack| match stack {
+            Some(stack) => undoer.redo_on(Some(&mut tiles.track(stack))),
+            None => undoer.redo_on::<LayerStack<Pixel>>(None),
+        });
+    }
+
+    /// Whether the editor has changed the image since it was created or last marked saved,
+    /// e.g. to show an asterisk in the title. Undoing back to the saved state still counts.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Clears [`Self::is_dirty`], e.g. after writing the image to disk
+    pub fn mark_saved(&mut self) {
+        self.dirty = false;
+    }
+
+    /// The mapping between screen and pixel coordinates used by the most recent call to
+    /// [`Self::edit`], for drawing overlays which line up with the canvas
+    pub fn view_transform(&self) -> ViewTransform {
+        self.view
+    }
+
+    /// Zooms so that an image of `image_dims` pixels, starting at `(0, 0)`, fills as much of
+    /// `available` as it can while keeping its aspect ratio, and centers it. `available`
+    /// should be the space [`Self::pan_and_zoom`] gives the canvas, e.g.
+    /// [`Ui::available_rect_before_wrap`] just before calling [`Self::edit`].
+    pub fn fit(&mut self, available: Rect, image_dims: (usize, usize)) {
+        let dims = Vec2::new(image_dims.0 as f32, image_dims.1 as f32);
+        let zoom = (available.size() / dims).min_elem();
+        self.zoom = zoom.clamp(*ZOOM_RANGE.start(), *ZOOM_RANGE.end());
+        self.center(available, image_dims);
+    }
+
+    /// Like [`Self::fit`], but at one point per pixel
+    pub fn reset_zoom(&mut self, available: Rect, image_dims: (usize, usize)) {
+        self.zoom = 1.0;
+        self.center(available, image_dims);
+    }
+
+    fn center(&mut self, available: Rect, (width, height): (usize, usize)) {
+        let dims = Vec2::new(width as f32, height as f32);
+        self.pan = (available.size() - dims * self.zoom) / 2.0;
+    }
+
+    /// The undo history, e.g. to configure frame limits or coalescing
+    pub fn undoer(&mut self) -> &mut SparseImageUndoer<Pixel> {
+        &mut self.undoer
+    }
+
+    /// The regions of the image changed through the editor since the last call, as
+    /// tile-sized rectangles, for uploading to a renderer of your own
+    pub fn take_dirty_rects(&mut self) -> Vec<(RangeInclusive<isize>, RangeInclusive<isize>)> {
+        self.tiles.take_dirty_rects()
+    }
+
+    /// Tile bookkeeping from the most recent draw, for profiling
+    pub fn tile_stats(&self) -> TileStats {
+        self.tiles.stats()
     }
-}
 
+    pub fn texture_options(&self) -> TextureOptions {
+        self.tiles.texture_options()
+    }
 
+    /// Sets how the canvas textures are sampled. Defaults to [`TextureOptions::NEAREST`],
+    /// which keeps pixels crisp when zoomed in but aliases when zoomed far out. To switch to
+    /// linear filtering only once a pixel is smaller than a screen pixel, set
+    /// `minification` to [`egui::TextureFilter::Linear`] and leave `magnification` nearest.
+    /// Far-out zoom levels still sparkle unless `mipmap_mode` is set too, in which case the
+    /// rendering backend builds a mip chain for each tile whenever it is uploaded.
+    pub fn set_texture_options(&mut self, texture_options: TextureOptions) {
+        self.tiles.set_texture_options(texture_options);
+    }
+
+    /// Forces the backend to upload to the GPU once more, and forgets the undo history
+    /// since it may no longer match the image
+    pub fn force_image_update(&mut self) {
+        self.tiles.invalidate_all();
+        self.fill_preview = None;
+        self.undoer.clear();
+    }
+
+    /// Re-samples every pixel on the next draw, keeping the undo history. Use this when the
+    /// way pixels are displayed changes, e.g. when editing the palette of an indexed image.
+    pub fn invalidate_all(&mut self) {
+        self.tiles.invalidate_all();
+        self.fill_preview = None;
+    }
+
+    /// Re-samples the given pixels on the next draw, e.g. after writing to them without
+    /// going through the editor
+    pub fn invalidate_rect(
+        &mut self,
+        x_range: RangeInclusive<isize>,
+        y_range: RangeInclusive<isize>,
+    ) {
+        self.tiles.invalidate_rect(x_range, y_range);
+        self.fill_preview = None;
+    }
+
+    /// Forgets all undo and redo history, e.g. after loading a different image
+    pub fn clear_history(&mut self) {
+        self.undoer.clear();
+    }
+
+    /// Floats `pixels` over the image with their top left corner at `at`, like a pasted
+    /// selection in other editors; `None` pixels are left out. Until the paste is committed
+    /// with Enter or [`Self::commit_floating`], or dropped with Escape or
+    /// [`Self::cancel_floating`], the arrow keys move it by a pixel (or ten, with shift)
+    /// and the tools don't paint. These keys work whether or not the
+    /// [`Keybindings`] are enabled, while the canvas is hovered or focused and the editor
+    /// isn't [read only](Self::read_only). Pasting again replaces it.
+    pub fn paste(&mut self, pixels: Buffer2D<Option<Pixel>>, at: (isize, isize)) {
+        self.floating = Some(Floating { pixels, at });
+    }
+
+    /// Whether a paste is waiting to be committed or cancelled
+    pub fn is_floating(&self) -> bool {
+        self.floating.is_some()
+    }
+
+    /// Moves the floating paste, if any, as the arrow keys do
+    pub fn nudge_floating(&mut self, dx: isize, dy: isize) {
+        if let Some(floating) = &mut self.floating {
+            floating.at = (floating.at.0 + dx, floating.at.1 + dy);
+        }
+    }
+
+    /// Writes the floating paste, if any, to the image where it is now, as a single undo
+    /// frame
+    pub fn commit_floating(&mut self, image: &mut impl Image<Pixel = Pixel>)
+    where
+        Pixel: PartialEq + Clone,
+    {
+        if let Some(floating) = self.floating.take() {
+            self.apply_named("Paste", image, |image| floating.write(image));
+        }
+    }
+
+    /// Drops the floating paste, if any, leaving the image as it was
+    pub fn cancel_floating(&mut self) {
+        self.floating = None;
+    }
+
+    /// Copies the image, the undo history and the settings of this editor into one value,
+    /// to checkpoint the session; see [`EditorState`]
+    pub fn save_state<I: Clone>(&self, image: &I, tool: &Tool<Pixel>) -> EditorState<I, Pixel>
+    where
+        Pixel: Clone,
+    {
+        EditorState {
+            image: image.clone(),
+            history: self.undoer.clone(),
+            tool: tool.clone(),
+            stamp: self.stamp.clone(),
+            mask: self.mask.clone(),
+            wrap_mode: self.wrap_mode,
+            brush_spacing: self.brush_spacing,
+        }
+    }
+
+    /// Restores a session taken by [`Self::save_state`], returning the image and tool to
+    /// edit with from now on. Every tile is sampled again from the restored image on the
+    /// next draw, and the editor counts as saved.
+    ///
+    /// Returns `None` and leaves the editor as it was if the history doesn't match the
+    /// image (see [`SparseImageUndoer::is_consistent_with`]), e.g. when the image was saved
+    /// separately and edited since. Check for that first and clear the history to load the
+    /// rest anyway.
+    pub fn load_state<I>(&mut self, state: EditorState<I, Pixel>) -> Option<(I, Tool<Pixel>)>
+    where
+        I: Image<Pixel = Pixel>,
+        Pixel: PartialEq,
+    {
+        if !state.history.is_consistent_with(&state.image) {
+            return None;
+        }
+        self.undoer = state.history;
+        self.stamp = state.stamp;
+        self.mask = state.mask;
+        self.wrap_mode = state.wrap_mode;
+        self.brush_spacing = state.brush_spacing;
+        self.shape_drag = None;
+        self.smudge_from = None;
+        self.brush_stroke = None;
+        self.fill_preview = None;
+        self.floating = None;
+        self.dirty = false;
+        self.tiles.invalidate_all();
+        Some((state.image, state.tool))
+    }
+}
+
+impl ImageEditor<Color32> {
+    /// Inverts the colors of the selection (or the whole image), as a single undo frame
+    pub fn invert(&mut self, image: &mut impl Image<Pixel = Color32>) {
+        self.apply_filter("Invert", image, filter::invert);
+    }
+
+    /// Brightens (or with a negative `delta`, darkens) the selection (or the whole image),
+    /// as a single undo frame
+    pub fn adjust_brightness(&mut self, image: &mut impl Image<Pixel = Color32>, delta: i16) {
+        self.apply_filter("Brightness", image, |px| {
+            filter::adjust_brightness(px, delta)
+        });
+    }
+
+    /// Converts the selection (or the whole image) to grayscale, as a single undo frame
+    pub fn to_grayscale(&mut self, image: &mut impl Image<Pixel = Color32>) {
+        self.apply_filter("Grayscale", image, filter::to_grayscale);
+    }
+
+    fn apply_filter(
+        &mut self,
+        label: &str,
+        image: &mut impl Image<Pixel = Color32>,
+        f: impl Fn(Color32) -> Color32,
+    ) {
+        self.apply_named(label, image, |image| {
+            image.for_each_pixel(|_, _, px| Some(f(px)))
+        });
+    }
+}
+
+/// Shades `region`, a list of pixels sorted by row, in translucent `color`. Horizontal runs
+/// are drawn as one rectangle each.
+fn draw_region(painter: &Painter, view: ViewTransform, region: &[(isize, isize)], color: Color32) {
+    let color = color.gamma_multiply(0.5);
+    for run in region.chunk_by(|a, b| a.1 == b.1 && a.0 + 1 == b.0) {
+        let (first, last) = (run[0], run[run.len() - 1]);
+        let max = view.to_screen((last.0 + 1, last.1 + 1));
+        painter.rect_filled(Rect::from_min_max(view.to_screen(first), max), 0.0, color);
+    }
+}
+
+/// Outlines the bounding box of `region`
+fn outline_region(painter: &Painter, view: ViewTransform, region: &[(isize, isize)]) {
+    let pixel = |&(x, y): &(isize, isize)| {
+        Rect::from_min_max(view.to_screen((x, y)), view.to_screen((x + 1, y + 1)))
+    };
+    let bounds = region.iter().map(pixel).fold(Rect::NOTHING, Rect::union);
+    let stroke = brush::outline_stroke(view.zoom);
+    painter.rect_stroke(bounds, 0.0, stroke, StrokeKind::Middle);
+}
+
+/// Whether `(x, y)` is inside the mask and allowed by `can_edit`, if either is set
+fn editable(
+    mask: &Option<HashSet<(isize, isize)>>,
+    can_edit: &Option<Box<CanEdit>>,
+    x: isize,
+    y: isize,
+) -> bool {
+    mask.as_ref().is_none_or(|mask| mask.contains(&(x, y)))
+        && can_edit.as_ref().is_none_or(|can_edit| can_edit(x, y))
+}
+
+/// The entry of `palette` nearest to `px`, or `px` itself without a palette
+fn snap<Pixel: PixelInterface + Clone>(palette: &Option<Vec<Pixel>>, px: Pixel) -> Pixel {
+    let nearest = palette
+        .as_deref()
+        .and_then(|palette| fill::nearest_in_palette(palette, px.as_rgba()));
+    nearest.cloned().unwrap_or(px)
+}
+
+/// Where to stamp the brush as the pointer moves to `to`: along the line from where it was
+/// on the previous frame, every `spacing` pixels. `stroke` holds where that was, and how
+/// far the pointer has travelled since the last stamp.
+fn stroke_to(
+    stroke: &mut Option<((isize, isize), f32)>,
+    spacing: f32,
+    to: (isize, isize),
+) -> Vec<(isize, isize)> {
+    let Some((from, mut travelled)) = *stroke else {
+        *stroke = Some((to, 0.0));
+        return vec![to];
+    };
+
+    let mut stamps = vec![];
+    let mut prev = from;
+    raster::line(from, to, |x, y| {
+        let (dx, dy) = ((x - prev.0) as f32, (y - prev.1) as f32);
+        travelled += dx.hypot(dy);
+        if travelled >= spacing && (x, y) != from {
+            stamps.push((x, y));
+            travelled = 0.0;
+        }
+        prev = (x, y);
+    });
+    *stroke = Some((to, travelled));
+    stamps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        image::{Buffer2D, ResizableColorImage},
+        Dither,
+    };
+    use egui::{CursorIcon, PointerButton, RawInput};
+    use std::{cell::RefCell, rc::Rc};
+
+    /// Runs one headless frame with the canvas placed at the screen origin
+    fn frame(ctx: &egui::Context, events: Vec<Event>, mut f: impl FnMut(&mut Ui)) {
+        let input = RawInput {
+            events,
+            screen_rect: Some(Rect::from_min_size(Pos2::ZERO, Vec2::splat(100.0))),
+            ..Default::default()
+        };
+        let _ = ctx.run(input, |ctx| {
+            egui::CentralPanel::default()
+                .frame(egui::Frame::NONE)
+                .show(ctx, |ui| f(ui));
+        });
+    }
+
+    fn button(pos: Pos2, pressed: bool) -> Event {
+        Event::PointerButton {
+            pos,
+            button: PointerButton::Primary,
+            pressed,
+            modifiers: Modifiers::NONE,
+        }
+    }
+
+    /// The events of each frame of a click of `button` at `pos`: moving there, pressing and
+    /// releasing
+    fn click_frames(pos: Pos2, button: PointerButton) -> [Vec<Event>; 3] {
+        let press = |pressed| Event::PointerButton {
+            pos,
+            button,
+            pressed,
+            modifiers: Modifiers::NONE,
+        };
+        [
+            vec![Event::PointerMoved(pos)],
+            vec![press(true)],
+            vec![press(false)],
+        ]
+    }
+
+    /// Clicks `button` at `pos`, calling `f` on each frame, and returns what it returned
+    fn click_with<R>(
+        ctx: &egui::Context,
+        pos: Pos2,
+        button: PointerButton,
+        mut f: impl FnMut(&mut Ui) -> R,
+    ) -> Vec<R> {
+        let mut results = vec![];
+        for events in click_frames(pos, button) {
+            frame(ctx, events, |ui| results.push(f(ui)));
+        }
+        results
+    }
+
+    /// Clicks at `pos` and returns what `edit` reported on each frame
+    fn click(
+        ctx: &egui::Context,
+        editor: &mut ImageEditor<Color32>,
+        image: &mut ColorImage,
+        pos: Pos2,
+        tool: Tool<Color32>,
+    ) -> Vec<EditResponse> {
+        click_with(ctx, pos, PointerButton::Primary, |ui| {
+            editor.edit(ui, image, Color32::WHITE, tool)
+        })
+    }
+
+    /// Drags from `a` to `b`, calling `before_release` just before letting go
+    fn drag(
+        ctx: &egui::Context,
+        editor: &mut ImageEditor<Color32>,
+        image: &mut ColorImage,
+        (a, b): (Pos2, Pos2),
+        tool: Tool<Color32>,
+        before_release: impl FnOnce(&ColorImage),
+    ) {
+        let mut run = |image: &mut ColorImage, events| {
+            frame(ctx, events, |ui| {
+                editor.edit(ui, image, Color32::WHITE, tool);
+            })
+        };
+        run(image, vec![Event::PointerMoved(a)]);
+        run(image, vec![button(a, true)]);
+        run(image, vec![Event::PointerMoved(a.lerp(b, 0.5))]);
+        run(image, vec![Event::PointerMoved(b)]);
+        before_release(image);
+        run(image, vec![button(b, false)]);
+    }
+
+    #[test]
+    fn test_brush_spacing() {
+        let ctx = egui::Context::default();
+        let row = |image: &ColorImage| -> Vec<usize> {
+            (0..16)
+                .filter(|&x| image.pixels[x + 16] == Color32::WHITE)
+                .collect()
+        };
+
+        for (spacing, expected) in [(1.0, (1..=12).collect()), (4.0, vec![1, 5, 9])] {
+            let mut image = ColorImage::new([16, 16], Color32::BLACK);
+            let mut editor = ImageEditor::from_tile_size(8);
+            editor.brush_spacing = spacing;
+            drag(
+                &ctx,
+                &mut editor,
+                &mut image,
+                (Pos2::new(1.5, 1.5), Pos2::new(12.5, 1.5)),
+                Tool::Brush(Brush::default()),
+                |_| (),
+            );
+            assert_eq!(row(&image), expected, "spacing {spacing}");
+        }
+    }
+
+    /// Clone but not Copy, like pixels carrying metadata
+    #[derive(Clone, Debug, PartialEq)]
+    struct Tagged(std::sync::Arc<str>);
+
+    impl PixelInterface for Tagged {
+        fn as_rgba(&self) -> Color32 {
+            Color32::from_gray(self.0.len() as u8)
+        }
+    }
+
+    #[test]
+    fn test_clone_only_pixels() {
+        let ctx = egui::Context::default();
+        let pos = Pos2::new(4.5, 4.5);
+        for brush in [Brush::Ellipse(2, 2), Brush::Rectangle(2, 1)] {
+            let (mut expected, tool) = (ColorImage::new([9, 9], Color32::BLACK), brush);
+            let mut editor = ImageEditor::from_tile_size(16);
+            click(&ctx, &mut editor, &mut expected, pos, Tool::Brush(tool));
+
+            let (empty, grass) = (Tagged("".into()), Tagged("grass".into()));
+            let mut image = Buffer2D::new(9, 9, empty.clone());
+            let mut editor = ImageEditor::from_tile_size(16);
+            click_with(&ctx, pos, PointerButton::Primary, |ui| {
+                editor.edit(ui, &mut image, grass.clone(), brush);
+            });
+            let painted: Vec<_> = image.data.iter().map(|px| *px == grass).collect();
+            let white = |px: &Color32| *px == Color32::WHITE;
+            let expected: Vec<_> = expected.pixels.iter().map(white).collect();
+            assert_eq!(painted, expected, "{brush:?}");
+
+            editor.undo(&mut image);
+            assert!(image.data.iter().all(|px| *px == empty));
+        }
+    }
+
+    #[test]
+    fn test_line_tool() {
+        let mut image = ColorImage::new([16, 16], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        drag(
+            &ctx,
+            &mut editor,
+            &mut image,
+            (Pos2::new(1.5, 1.5), Pos2::new(12.5, 1.5)),
+            Tool::Line(Brush::default()),
+            |image| assert!(image.pixels.iter().all(|px| *px == Color32::BLACK)),
+        );
+
+        for x in 0..16 {
+            let expected = if (1..=12).contains(&x) {
+                Color32::WHITE
+            } else {
+                Color32::BLACK
+            };
+            assert_eq!(image.pixels[x + 16], expected, "x={x}");
+        }
+
+        editor.undo(&mut image);
+        assert_eq!(image, ColorImage::new([16, 16], Color32::BLACK));
+    }
+
+    #[test]
+    fn test_gradient_tool() {
+        let mut image = ColorImage::new([16, 4], Color32::RED);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        let tool = Tool::Gradient {
+            end: Color32::BLACK,
+            dither: Dither::None,
+        };
+        drag(
+            &ctx,
+            &mut editor,
+            &mut image,
+            (Pos2::new(2.5, 1.5), Pos2::new(10.5, 1.5)),
+            tool,
+            |image| assert!(image.pixels.iter().all(|px| *px == Color32::RED)),
+        );
+
+        // Drawn from white (the draw color) to black, clamped outside of the drag
+        assert_eq!(image.pixels[0], Color32::WHITE);
+        assert_eq!(image.pixels[2], Color32::WHITE);
+        assert_eq!(image.pixels[6], Color32::from_gray(128));
+        assert_eq!(image.pixels[15 + 3 * 16], Color32::BLACK);
+
+        editor.undo(&mut image);
+        assert_eq!(image, ColorImage::new([16, 4], Color32::RED));
+    }
+
+    #[test]
+    fn test_palette() {
+        let ctx = egui::Context::default();
+        let mut editor = ImageEditor::from_tile_size(8);
+        editor.palette = Some(vec![Color32::BLACK, Color32::WHITE]);
+        let mut image = ColorImage::new([16, 4], Color32::RED);
+
+        // Mid-grays snap to whichever of black and white is closer
+        for (x, gray, expected) in [(1, 100, Color32::BLACK), (3, 160, Color32::WHITE)] {
+            let pos = Pos2::new(x as f32 + 0.5, 0.5);
+            let color = Color32::from_gray(gray);
+            let tool = Tool::Brush(Brush::Rectangle(0, 0));
+            click_with(&ctx, pos, PointerButton::Primary, |ui| {
+                editor.edit(ui, &mut image, color, tool);
+            });
+            assert_eq!(image.pixels[x], expected);
+        }
+
+        // So do the blends of a gradient
+        let tool = Tool::Gradient {
+            end: Color32::BLACK,
+            dither: Dither::None,
+        };
+        let line = (Pos2::new(2.5, 1.5), Pos2::new(10.5, 1.5));
+        drag(&ctx, &mut editor, &mut image, line, tool, |_| ());
+        assert_eq!(image.pixels[5], Color32::WHITE);
+        assert_eq!(image.pixels[7], Color32::BLACK);
+        let palette = [Color32::BLACK, Color32::WHITE];
+        assert!(image.pixels.iter().all(|px| palette.contains(px)));
+    }
+
+    #[test]
+    fn test_onion_skin() {
+        let ctx = egui::Context::default();
+        let mut editor = ImageEditor::from_tile_size(8);
+        let mut image = ColorImage::new([4, 4], Color32::TRANSPARENT);
+        let previous = ColorImage::new([4, 4], Color32::RED);
+        let next = ColorImage::new([4, 4], Color32::GREEN);
+        let tint = Color32::from_rgba_unmultiplied(255, 255, 255, 80);
+
+        let pos = Pos2::new(1.5, 1.5);
+        let tool = Tool::Brush(Brush::Rectangle(0, 0));
+        click_with(&ctx, pos, PointerButton::Primary, |ui| {
+            let onion_skin = [(0, &previous, tint), (2, &next, tint)];
+            editor.edit_with_onion_skin(ui, &mut image, &onion_skin, Color32::WHITE, tool);
+        });
+        // Only the image itself is edited
+        assert_eq!(image.pixels[5], Color32::WHITE);
+        assert_eq!(previous.pixels, [Color32::RED; 16]);
+        assert_eq!(next.pixels, [Color32::GREEN; 16]);
+        assert_eq!(editor.onion_tiles.len(), 2);
+        let uploaded = |(_, tiles): &(u64, TiledEguiImage)| tiles.stats().dirty_uploaded;
+        assert!(editor.onion_tiles.iter().all(|tiles| uploaded(tiles) == 0));
+
+        // The textures are kept while the key stays the same, and sampled again when it
+        // changes, even for an image edited in place
+        let mut previous = previous;
+        previous.pixels[0] = Color32::BLUE;
+        let mut draw = |key, onion: &ColorImage| {
+            let pass = |editor: &mut ImageEditor<Color32>| {
+                frame(&ctx, vec![], |ui| {
+                    editor.draw_canvas_with_onion_skin(ui, &image, &[(key, onion, tint)]);
+                });
+            };
+            pass(&mut editor);
+            // With the `parallel` feature, the patch is uploaded once it has arrived
+            if editor.onion_tiles[0].1.stats().dirty > 0 {
+                editor.onion_tiles[0].1.wait_for_patches();
+                pass(&mut editor);
+            }
+            uploaded(&editor.onion_tiles[0])
+        };
+        assert_eq!(draw(0, &previous), 0);
+        assert_eq!(draw(1, &previous), 1);
+        assert_eq!(editor.onion_tiles.len(), 1);
+    }
+
+    #[test]
+    fn test_cursor_per_tool() {
+        let ctx = egui::Context::default();
+        let mut editor = ImageEditor::from_tile_size(8);
+        let mut image = ColorImage::new([4, 4], Color32::BLACK);
+        image.pixels[5] = Color32::RED;
+        let pos = Pos2::new(1.5, 1.5);
+
+        for (tool, icon) in [
+            (Tool::default(), CursorIcon::Default),
+            (Tool::Fill { tolerance: 0 }, CursorIcon::Cell),
+            (Tool::Line(Brush::default()), CursorIcon::Crosshair),
+            (Tool::Eyedropper, CursorIcon::Crosshair),
+        ] {
+            frame(&ctx, vec![Event::PointerMoved(pos)], |ui| {
+                editor.edit(ui, &mut image, Color32::WHITE, tool);
+                assert_eq!(ui.output(|o| o.cursor_icon), icon);
+            });
+        }
+
+        // The eyedropper only reports the color it was clicked on
+        let before = image.clone();
+        let responses = click(&ctx, &mut editor, &mut image, pos, Tool::Eyedropper);
+        let released = responses.last().unwrap();
+        assert!(released.egui_response.clicked());
+        assert_eq!(released.hover.unwrap().color, Some(Color32::RED));
+        assert_eq!(image, before);
+        assert!(!editor.undoer().can_undo());
+    }
+
+    #[test]
+    fn test_edits_across_tiles_dirty_each_tile() {
+        let ctx = egui::Context::default();
+        let mut editor = ImageEditor::from_tile_size(8);
+        let mut image = ColorImage::new([16, 8], Color32::BLACK);
+        let both = [(0..=7, 0..=7), (8..=15, 0..=7)];
+
+        // A 3x3 stamp straddling the boundary between the two tiles
+        let brush = Tool::Brush(Brush::Rectangle(1, 1));
+        click(&ctx, &mut editor, &mut image, Pos2::new(7.5, 2.5), brush);
+        assert_eq!(image.pixels[6 + 16], Color32::WHITE);
+        assert_eq!(image.pixels[8 + 16], Color32::WHITE);
+        assert_eq!(editor.take_dirty_rects(), both);
+
+        // As does a fill spreading from one into the other
+        let fill = Tool::Fill { tolerance: 0 };
+        click(&ctx, &mut editor, &mut image, Pos2::new(0.5, 6.5), fill);
+        assert_eq!(image.pixels[15], Color32::WHITE);
+        assert_eq!(editor.take_dirty_rects(), both);
+    }
+
+    /// Grows to fit what is written to it, without telling the editor it can
+    struct Growing(ResizableColorImage);
+
+    impl Image for Growing {
+        type Pixel = Color32;
+        fn get_pixel(&self, x: isize, y: isize) -> Color32 {
+            self.0.get_pixel(x, y)
+        }
+
+        fn set_pixel(&mut self, x: isize, y: isize, px: Color32) {
+            self.0.set_pixel(x, y, px);
+        }
+
+        fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
+            self.0.image_boundaries()
+        }
+    }
+
+    #[test]
+    fn test_out_of_bounds_grow() {
+        let ctx = egui::Context::default();
+        let mut editor = ImageEditor::from_tile_size(8);
+        let image = ColorImage::new([4, 4], Color32::BLACK);
+        let mut image = Growing(ResizableColorImage::new(image, Color32::BLACK));
+        let pos = Pos2::new(3.5, 3.5);
+        // Reaches from 2 to 4 on both axes
+        let tool = Tool::Brush(Brush::Rectangle(1, 1));
+        let stamp = |editor: &mut ImageEditor<Color32>, image: &mut Growing| {
+            click_with(&ctx, pos, PointerButton::Primary, |ui| {
+                editor.edit(ui, image, Color32::WHITE, tool);
+            });
+        };
+
+        stamp(&mut editor, &mut image);
+        assert_eq!(image.image_boundaries(), (0..=3, 0..=3));
+
+        editor.out_of_bounds = OutOfBounds::Grow;
+        stamp(&mut editor, &mut image);
+        assert_eq!(image.image_boundaries(), (0..=4, 0..=4));
+        assert_eq!(image.get_pixel(4, 4), Color32::WHITE);
+
+        // Undoing leaves the image its new size, with the new pixels back to the fill
+        editor.undo(&mut image);
+        assert_eq!(image.image_boundaries(), (0..=4, 0..=4));
+        assert_eq!(image.get_pixel(4, 4), Color32::BLACK);
+        assert_eq!(image.get_pixel(3, 3), Color32::WHITE);
+    }
+
+    #[test]
+    fn test_grow_past_left_edge() {
+        let ctx = egui::Context::default();
+        let mut editor = ImageEditor::from_tile_size(8);
+        let black = ColorImage::new([4, 4], Color32::BLACK);
+        let mut image = ResizableColorImage::new(black.clone(), Color32::BLACK);
+        // Reaches from -1 to 1 across, and from 0 to 2 down
+        let tool = Tool::Brush(Brush::Rectangle(1, 1));
+        click_with(&ctx, Pos2::new(0.5, 1.5), PointerButton::Primary, |ui| {
+            editor.edit(ui, &mut image, Color32::WHITE, tool);
+        });
+        assert_eq!(image.image_boundaries(), (-1..=3, 0..=3));
+        assert_eq!(image.get_pixel(-1, 2), Color32::WHITE);
+        assert_eq!(image.get_pixel(2, 1), Color32::BLACK);
+        assert_eq!(editor.undoer().history().last(), Some(("Brush stroke", 9)));
+
+        editor.undo(&mut image);
+        assert_eq!(image.get_pixel(-1, 2), Color32::BLACK);
+        assert_eq!(image.image.pixels, [Color32::BLACK; 20]);
+    }
+
+    #[test]
+    fn test_can_edit() {
+        let ctx = egui::Context::default();
+        let mut editor = ImageEditor::from_tile_size(8);
+        editor.can_edit = Some(Box::new(|x, _| x != 2));
+        let mut image = ColorImage::new([10, 4], Color32::BLACK);
+        let locked_untouched = |editor: &mut ImageEditor<Color32>| {
+            let changes = editor.undoer().last_frame_changes();
+            !changes.is_empty() && changes.iter().all(|&(x, ..)| x != 2)
+        };
+
+        // A brush stroke and a line across the locked column. Both ends are far enough
+        // apart to count as a drag.
+        let stroke = (Pos2::new(0.5, 0.5), Pos2::new(9.5, 0.5));
+        let brush = Tool::default();
+        drag(&ctx, &mut editor, &mut image, stroke, brush, |_| ());
+        assert_eq!(image.pixels[1], Color32::WHITE);
+        assert_eq!(image.pixels[2], Color32::BLACK);
+        assert!(locked_untouched(&mut editor));
+
+        let line = (Pos2::new(0.5, 2.5), Pos2::new(9.5, 2.5));
+        let tool = Tool::Line(Brush::default());
+        drag(&ctx, &mut editor, &mut image, line, tool, |_| ());
+        assert_eq!(image.pixels[3 + 2 * 10], Color32::WHITE);
+        assert!(locked_untouched(&mut editor));
+
+        // A fill spreads past it without filling it
+        let fill = Tool::Fill { tolerance: 0 };
+        click(&ctx, &mut editor, &mut image, Pos2::new(0.5, 3.5), fill);
+        assert_eq!(image.pixels[9 + 3 * 10], Color32::WHITE);
+        assert!(locked_untouched(&mut editor));
+
+        editor.fill_all(&mut image, Color32::RED);
+        assert!(locked_untouched(&mut editor));
+        let column: Vec<_> = (0..4).map(|y| image.pixels[2 + y * 10]).collect();
+        assert_eq!(column, [Color32::BLACK; 4]);
+    }
+
+    #[test]
+    fn test_each_click_is_one_frame() {
+        let mut image = ColorImage::new([4, 4], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        click(
+            &ctx,
+            &mut editor,
+            &mut image,
+            Pos2::new(0.5, 0.5),
+            Tool::default(),
+        );
+        click(
+            &ctx,
+            &mut editor,
+            &mut image,
+            Pos2::new(2.5, 0.5),
+            Tool::default(),
+        );
+
+        editor.undo(&mut image);
+        assert_eq!(image.pixels[0], Color32::WHITE);
+        assert_eq!(image.pixels[2], Color32::BLACK);
+    }
+
+    #[test]
+    fn test_smudge_tool() {
+        let mut original = ColorImage::new([16, 4], Color32::BLACK);
+        for y in 0..4 {
+            original.fill_rect(0..=3, y..=y, Color32::RED);
+        }
+        let mut image = original.clone();
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        let tool = Tool::Smudge {
+            brush: Brush::Rectangle(1, 1),
+            strength: 1.0,
+        };
+        drag(
+            &ctx,
+            &mut editor,
+            &mut image,
+            (Pos2::new(2.5, 1.5), Pos2::new(10.5, 1.5)),
+            tool,
+            |_| (),
+        );
+
+        // The red was carried along to the end of the drag, but not beyond the brush
+        assert_eq!(image.pixels[10 + 16], Color32::RED);
+        assert_eq!(image.pixels[11 + 2 * 16], Color32::RED);
+        assert_eq!(image.pixels[12 + 16], Color32::BLACK);
+
+        editor.undo(&mut image);
+        assert_eq!(image, original);
+    }
+
+    #[test]
+    fn test_edit_layers_paints_active_layer() {
+        let mut stack = LayerStack::new(4, 4, Color32::BLACK);
+        let top = stack.push_layer(Color32::TRANSPARENT);
+        stack.push_layer(Color32::TRANSPARENT);
+        stack.set_active(top);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        let tool = Tool::Brush(Brush::Rectangle(0, 0));
+        click_with(&ctx, Pos2::new(1.5, 2.5), PointerButton::Primary, |ui| {
+            editor.edit_layers(ui, &mut stack, Color32::WHITE, tool);
+        });
+
+        let layers = stack.layers();
+        let painted = layers[top].image().data.iter();
+        let painted = painted.filter(|&&px| px != Color32::TRANSPARENT).count();
+        assert_eq!(painted, 1);
+        assert_eq!(layers[top].image().get_pixel(1, 2), Color32::WHITE);
+        assert_eq!(layers[0].image().data, [Color32::BLACK; 16]);
+        assert_eq!(layers[2].image().data, [Color32::TRANSPARENT; 16]);
+
+        // The stroke is undone on the layer it was painted on
+        stack.set_active(0);
+        editor.undo_layers(&mut stack);
+        assert_eq!(stack.layers()[top].image().data, [Color32::TRANSPARENT; 16]);
+        assert_eq!(stack.active(), 0);
+    }
+
+    #[test]
+    fn test_stamp_tool() {
+        let mut image = ColorImage::new([8, 8], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        let (r, b) = (Some(Color32::RED), Some(Color32::BLUE));
+        let stamp = Buffer2D {
+            data: vec![r, None, b, b, r, None],
+            width: 3,
+            height: 2,
+        };
+        editor.stamp = stamp;
+        click(
+            &ctx,
+            &mut editor,
+            &mut image,
+            Pos2::new(4.5, 4.5),
+            Tool::Stamp,
+        );
+
+        // Centered on (4, 4), rounding down, so the top row of the stamp is row 3
+        let row = |y: usize| &image.pixels[3 + y * 8..6 + y * 8];
+        assert_eq!(row(3), [Color32::RED, Color32::BLACK, Color32::BLUE]);
+        assert_eq!(row(4), [Color32::BLUE, Color32::RED, Color32::BLACK]);
+        assert_eq!(
+            image
+                .pixels
+                .iter()
+                .filter(|&&px| px != Color32::BLACK)
+                .count(),
+            4
+        );
+    }
+
+    fn undo_key() -> Event {
+        Event::Key {
+            key: Key::Z,
+            physical_key: None,
+            pressed: true,
+            repeat: false,
+            modifiers: Modifiers::COMMAND,
+        }
+    }
+
+    #[test]
+    fn test_keybindings() {
+        let mut image = ColorImage::new([4, 4], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        editor.apply(&mut image, |image| image.set_pixel(0, 0, Color32::RED));
+
+        // Disabled: the host sees the event and nothing is undone
+        let ctx = egui::Context::default();
+        editor.keybindings.enabled = false;
+        frame(&ctx, vec![undo_key()], |ui| {
+            editor.edit(ui, &mut image, Color32::WHITE, Tool::default());
+            assert!(ui.input(|i| i.events.contains(&undo_key())));
+        });
+        assert_eq!(image.pixels[0], Color32::RED);
+
+        editor.keybindings.enabled = true;
+        frame(&ctx, vec![undo_key()], |ui| {
+            editor.edit(ui, &mut image, Color32::WHITE, Tool::default());
+            assert!(!ui.input(|i| i.events.contains(&undo_key())));
+        });
+        assert_eq!(image.pixels[0], Color32::BLACK);
+    }
+
+    #[test]
+    fn test_undo_redo_methods() {
+        let mut image = ColorImage::new([16, 8], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        let draw = |editor: &mut ImageEditor<Color32>, image: &mut ColorImage| {
+            frame(&ctx, vec![], |ui| editor.draw(ui, image, Pos2::ZERO));
+        };
+
+        // Allocate both tiles
+        draw(&mut editor, &mut image);
+        assert_eq!(editor.tiles.dirty_tiles(), []);
+
+        editor.apply(&mut image, |image| image.set_pixel(9, 1, Color32::RED));
+        draw(&mut editor, &mut image);
+
+        editor.undo(&mut image);
+        assert_eq!(image.pixels[9 + 16], Color32::BLACK);
+        assert_eq!(editor.tiles.dirty_tiles(), [(1, 0)]);
+        draw(&mut editor, &mut image);
+
+        editor.redo(&mut image);
+        assert_eq!(image.pixels[9 + 16], Color32::RED);
+        assert_eq!(editor.tiles.dirty_tiles(), [(1, 0)]);
+    }
+
+    #[test]
+    fn test_dirty_flag() {
+        let mut image = ColorImage::new([8, 8], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        let pos = Pos2::new(1.5, 1.5);
+        assert!(!editor.is_dirty());
+
+        click(&ctx, &mut editor, &mut image, pos, Brush::default().into());
+        assert!(editor.is_dirty());
+        editor.mark_saved();
+        // Painting over a pixel with its own color changes nothing
+        click(&ctx, &mut editor, &mut image, pos, Brush::default().into());
+        assert!(!editor.is_dirty());
+
+        editor.undo(&mut image);
+        assert!(editor.is_dirty());
+        editor.mark_saved();
+        editor.undo(&mut image);
+        assert!(!editor.is_dirty());
+
+        editor.fill_all(&mut image, Color32::RED);
+        assert!(editor.is_dirty());
+    }
+
+    #[test]
+    fn test_draw_shared_image() {
+        let image = ColorImage::new([16, 8], Color32::RED);
+        let mut left = ImageEditor::from_tile_size(8);
+        let mut right = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        let shared = &image;
+        frame(&ctx, vec![], |ui| {
+            left.draw(ui, shared, Pos2::ZERO);
+            right.draw(ui, shared, Pos2::new(20.0, 0.0));
+        });
+        for editor in [&left, &right] {
+            assert_eq!(editor.tile_stats().resident, 2);
+        }
+    }
+
+    #[test]
+    fn test_fill_all() {
+        let mut image = ColorImage::new([16, 8], Color32::BLACK);
+        for (i, px) in image.pixels.iter_mut().enumerate() {
+            *px = Color32::from_gray(i as u8);
+        }
+        let before = image.clone();
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        frame(&ctx, vec![], |ui| editor.draw(ui, &image, Pos2::ZERO));
+
+        editor.fill_all(&mut image, Color32::RED);
+        assert!(image.pixels.iter().all(|&px| px == Color32::RED));
+        assert_eq!(editor.tiles.dirty_tiles(), [(0, 0), (1, 0)]);
+
+        editor.undo(&mut image);
+        assert_eq!(image.pixels, before.pixels);
+
+        // The fill was a single frame, so there is nothing left to undo
+        editor.undo(&mut image);
+        assert_eq!(image.pixels, before.pixels);
+    }
+
+    #[test]
+    fn test_filter_respects_mask() {
+        let mut image = ColorImage::new([4, 1], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        editor.mask = Some([(1, 0), (2, 0)].into_iter().collect());
+
+        editor.invert(&mut image);
+        let (black, white) = (Color32::BLACK, Color32::WHITE);
+        assert_eq!(image.pixels, [black, white, white, black]);
+
+        editor.undo(&mut image);
+        assert_eq!(image, ColorImage::new([4, 1], Color32::BLACK));
+    }
+
+    #[test]
+    fn test_read_only() {
+        let mut image = ColorImage::new([8, 8], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        editor.apply(&mut image, |image| image.set_pixel(0, 0, Color32::RED));
+        editor.read_only = true;
+
+        let responses = click(
+            &ctx,
+            &mut editor,
+            &mut image,
+            Pos2::new(2.5, 3.5),
+            Tool::default(),
+        );
+        let senses_drag = |r: &EditResponse| r.egui_response.sense.senses_drag();
+        assert!(!responses.iter().any(senses_drag));
+        assert_eq!(responses.last().unwrap().hover.unwrap().pixel, (2, 3));
+
+        frame(&ctx, vec![undo_key()], |ui| {
+            editor.edit(ui, &mut image, Color32::WHITE, Tool::default());
+        });
+
+        let mut expected = ColorImage::new([8, 8], Color32::BLACK);
+        expected.pixels[0] = Color32::RED;
+        assert_eq!(image, expected);
+    }
+
+    #[test]
+    fn test_pan_and_zoom() {
+        let mut image = ColorImage::new([8, 8], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        editor.pan_and_zoom = true;
+
+        // Dragging with the middle button moves the canvas instead of painting
+        let middle = |pos, pressed| Event::PointerButton {
+            pos,
+            button: PointerButton::Middle,
+            pressed,
+            modifiers: Modifiers::NONE,
+        };
+        let mut run = |image: &mut ColorImage, events| {
+            let mut finished = false;
+            frame(&ctx, events, |ui| {
+                let resp = editor.edit(ui, image, Color32::WHITE, Tool::default());
+                finished = resp.stroke_finished;
+            });
+            finished
+        };
+        let start = Pos2::new(10.0, 10.0);
+        run(&mut image, vec![Event::PointerMoved(start)]);
+        run(&mut image, vec![middle(start, true)]);
+        for step in 1..=10 {
+            let pos = start + Vec2::new(2.0, 1.0) * step as f32;
+            run(&mut image, vec![Event::PointerMoved(pos)]);
+        }
+        // Letting go ends the pan, not a stroke
+        assert!(!run(&mut image, vec![middle(Pos2::new(30.0, 20.0), false)]));
+        assert_eq!(image, ColorImage::new([8, 8], Color32::BLACK));
+        assert!(!editor.undoer().can_undo());
+        assert!(editor.pan.x > 0.0 && editor.pan.y > 0.0);
+
+        // Painting then goes through the panned and zoomed view
+        editor.zoom = 4.0;
+        let pos = editor.pan.to_pos2() + Vec2::new(6.0, 10.0);
+        click(&ctx, &mut editor, &mut image, pos, Tool::default());
+        let painted: Vec<usize> = (0..64)
+            .filter(|&i| image.pixels[i] == Color32::WHITE)
+            .collect();
+        assert_eq!(painted, [1 + 2 * 8]);
+        let view = editor.view_transform();
+        assert_eq!(view.to_pixel(pos), (1, 2));
+        assert_eq!(view.to_screen((1, 2)), pos - Vec2::new(2.0, 2.0));
+    }
+
+    #[test]
+    fn test_fit() {
+        let mut editor = ImageEditor::<Color32>::from_tile_size(8);
+        let available = Rect::from_min_size(Pos2::new(10.0, 10.0), Vec2::new(200.0, 100.0));
+
+        editor.fit(available, (50, 10));
+        assert_eq!((editor.zoom, editor.pan), (4.0, Vec2::new(0.0, 30.0)));
+        editor.fit(available, (10, 50));
+        assert_eq!((editor.zoom, editor.pan), (2.0, Vec2::new(90.0, 0.0)));
+
+        editor.reset_zoom(available, (50, 10));
+        assert_eq!((editor.zoom, editor.pan), (1.0, Vec2::new(75.0, 45.0)));
+    }
+
+    #[test]
+    fn test_wrap_mode() {
+        let mut image = ColorImage::new([8, 8], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        editor.wrap_mode = WrapMode::WrapXY;
+
+        // A 3x3 brush on the top left corner
+        let (pos, brush) = (Pos2::new(0.5, 0.5), Brush::Rectangle(1, 1));
+        click(&ctx, &mut editor, &mut image, pos, brush.into());
+        let painted: Vec<usize> = (0..64)
+            .filter(|&i| image.pixels[i] == Color32::WHITE)
+            .collect();
+        assert_eq!(painted, [0, 1, 7, 8, 9, 15, 56, 57, 63]);
+
+        // All in the same undo frame
+        editor.undo(&mut image);
+        assert_eq!(image, ColorImage::new([8, 8], Color32::BLACK));
+    }
+
+    #[test]
+    fn test_history_labels() {
+        let mut image = ColorImage::new([16, 16], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        let pos = Pos2::new(1.5, 1.5);
+        click(&ctx, &mut editor, &mut image, pos, Brush::default().into());
+        let (line, tool) = ((pos, Pos2::new(12.5, 1.5)), Tool::Line(Brush::default()));
+        drag(&ctx, &mut editor, &mut image, line, tool, |_| ());
+        editor.fill_all(&mut image, Color32::RED);
+
+        let history: Vec<_> = editor.undoer().history().collect();
+        assert_eq!(history, [("Brush stroke", 1), ("Line", 11), ("Fill", 256)]);
+    }
+
+    #[test]
+    fn test_secondary_color() {
+        let mut image = ColorImage::new([8, 8], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        let mut click = |image: &mut ColorImage, pos: Pos2, button| {
+            click_with(&ctx, pos, button, |ui| {
+                let tool = Tool::Brush(Brush::default());
+                editor.edit_with_secondary(ui, image, Color32::WHITE, Color32::RED, tool);
+            });
+        };
+        click(&mut image, Pos2::new(1.5, 1.5), PointerButton::Primary);
+        click(&mut image, Pos2::new(2.5, 1.5), PointerButton::Secondary);
+        click(&mut image, Pos2::new(3.5, 1.5), PointerButton::Primary);
+        assert_eq!(
+            image.pixels[9..12],
+            [Color32::WHITE, Color32::RED, Color32::WHITE]
+        );
+
+        // Each click is its own undo frame, whichever button it was
+        editor.undo(&mut image);
+        editor.undo(&mut image);
+        assert_eq!(
+            image.pixels[9..12],
+            [Color32::WHITE, Color32::BLACK, Color32::BLACK]
+        );
+    }
+
+    #[test]
+    fn test_fill_tool() {
+        let mut image = ColorImage::new([8, 8], Color32::BLACK);
+        for y in 0..8 {
+            image.pixels[4 + y * 8] = Color32::RED;
+        }
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        let tool = Tool::Fill { tolerance: 0 };
+
+        // Hovering previews the region without filling it
+        for _ in 0..2 {
+            frame(&ctx, vec![Event::PointerMoved(Pos2::new(1.5, 1.5))], |ui| {
+                editor.edit(ui, &mut image, Color32::WHITE, tool);
+            });
+        }
+        let preview = editor.fill_preview.as_ref().unwrap();
+        assert_eq!((preview.start, preview.region.len()), ((1, 1), 32));
+        assert!(preview.complete);
+        assert!(image.pixels.iter().all(|&px| px != Color32::WHITE));
+
+        // Any tracked write finds the region again, even one the editor didn't make
+        editor.tiles.track(&mut image).set_pixel(0, 7, Color32::RED);
+        frame(&ctx, vec![Event::PointerMoved(Pos2::new(1.5, 1.5))], |ui| {
+            editor.edit(ui, &mut image, Color32::WHITE, tool);
+        });
+        assert_eq!(editor.fill_preview.as_ref().unwrap().region.len(), 31);
+
+        click(&ctx, &mut editor, &mut image, Pos2::new(6.5, 1.5), tool);
+        let whites = image.pixels.iter().filter(|&&px| px == Color32::WHITE);
+        assert_eq!(whites.count(), 24);
+        assert_eq!(image.pixels[0], Color32::BLACK);
+
+        editor.undo(&mut image);
+        assert!(image.pixels.iter().all(|&px| px != Color32::WHITE));
+
+        // The preview of a huge region is cut off, and says so
+        let mut image = ColorImage::new([300, 300], Color32::BLACK);
+        for _ in 0..2 {
+            frame(&ctx, vec![Event::PointerMoved(Pos2::new(1.5, 1.5))], |ui| {
+                editor.edit(ui, &mut image, Color32::WHITE, tool);
+            });
+        }
+        let preview = editor.fill_preview.as_ref().unwrap();
+        assert_eq!(preview.region.len(), FILL_PREVIEW_LIMIT);
+        assert!(!preview.complete);
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let mut image = ColorImage::new([4, 4], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        let brush = Tool::default();
+        click(&ctx, &mut editor, &mut image, Pos2::new(0.5, 0.5), brush);
+        editor.brush_spacing = 3.0;
+        let state = editor.save_state(&image, &Tool::Fill { tolerance: 2 });
+
+        click(&ctx, &mut editor, &mut image, Pos2::new(1.5, 0.5), brush);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let Some((mut image, tool)) = editor.load_state(state.clone()) else {
+            panic!("the state was saved as it was");
+        };
+        assert!(matches!(tool, Tool::Fill { tolerance: 2 }));
+        assert_eq!(editor.brush_spacing, 3.0);
+        assert_eq!(image.pixels[..2], [Color32::WHITE, Color32::BLACK]);
+        assert!(!editor.is_dirty());
+
+        // The history came along, so the first click can still be undone
+        editor.undo(&mut image);
+        assert!(image.pixels.iter().all(|&px| px == Color32::BLACK));
+
+        // A history which doesn't match its image is refused
+        let mut state = state;
+        state.image.pixels[0] = Color32::RED;
+        assert!(editor.load_state(state.clone()).is_none());
+        assert!(editor.undoer().can_redo());
+        state.history.clear();
+        assert!(editor.load_state(state).is_some());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut image = Buffer2D::new(3, 2, Height(0.0));
+        let mut editor = ImageEditor::from_tile_size(8);
+        editor.apply(&mut image, |image| {
+            image.set_pixel(0, 0, Height(0.25));
+            image.set_pixel(2, 1, Height(0.5));
+        });
+        editor.fill_all(&mut image, Height(1.0));
+        editor.undo(&mut image);
+        editor.mask = Some(HashSet::from([(1, 1)]));
+        editor.stamp = Buffer2D {
+            data: vec![Some(Height(0.75)), None],
+            width: 2,
+            height: 1,
+        };
+        let mask = crate::StampMask::new(&[true, false, true], 3, 1).unwrap();
+        let state = editor.save_state(&image, &Tool::Line(Brush::Stamp(mask)));
+
+        let mut editor = ImageEditor::from_tile_size(8);
+        let json = serde_json::to_string(&state).unwrap();
+        let loaded: EditorState<Buffer2D<Height>, _> = serde_json::from_str(&json).unwrap();
+        let loaded = editor.load_state(loaded);
+        let (mut image, tool) = loaded.expect("the history still matches the image");
+        assert!(matches!(tool, Tool::Line(Brush::Stamp(m)) if m == mask));
+        assert_eq!((&editor.mask, &editor.stamp), (&state.mask, &state.stamp));
+        assert_eq!(image, state.image);
+        let history: Vec<_> = editor.undoer().history().map(|(_, len)| len).collect();
+        assert_eq!(history, [2]);
+
+        editor.redo(&mut image);
+        assert_eq!(image.data, [Height(1.0); 6]);
+        editor.undo(&mut image);
+        editor.undo(&mut image);
+        assert_eq!(image.data, [Height(0.0); 6]);
+    }
+
+    #[test]
+    fn test_pressure() {
+        let mut image = ColorImage::new([16, 8], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        editor.pressure_range = Some(0.0..=2.0);
+        let ctx = egui::Context::default();
+        let mut click = |image: &mut ColorImage, pos: Pos2, force: Option<f32>| {
+            let touch = |phase| Event::Touch {
+                device_id: egui::TouchDeviceId(0),
+                id: egui::TouchId(0),
+                phase,
+                pos,
+                force,
+            };
+            let [moved, mut pressed, mut released] = click_frames(pos, PointerButton::Primary);
+            pressed.insert(0, touch(TouchPhase::Start));
+            released.insert(0, touch(TouchPhase::End));
+            for events in [moved, pressed, released] {
+                frame(&ctx, events, |ui| {
+                    let tool = Tool::Brush(Brush::Rectangle(1, 1));
+                    editor.edit(ui, image, Color32::WHITE, tool);
+                });
+            }
+        };
+        let whites = |image: &ColorImage| {
+            let whites = image.pixels.iter().filter(|&&px| px == Color32::WHITE);
+            whites.count()
+        };
+
+        // Full pressure doubles the brush to 5x5
+        click(&mut image, Pos2::new(3.5, 3.5), Some(1.0));
+        assert_eq!(whites(&image), 25);
+        // Without any pressure reported, the brush keeps its own 3x3 size
+        click(&mut image, Pos2::new(11.5, 3.5), None);
+        assert_eq!(whites(&image), 34);
+    }
+
+    #[test]
+    fn test_brush_dynamics() {
+        let mut image = ColorImage::new([8, 8], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let points = Rc::new(RefCell::new(vec![]));
+        let seen = points.clone();
+        editor.dynamics = Some(Box::new(move |point| {
+            seen.borrow_mut().push(*point);
+            BrushAdjustment {
+                size: 2.0,
+                opacity: 0.5,
+            }
+        }));
+        let ctx = egui::Context::default();
+        let tool = Tool::Brush(Brush::Rectangle(1, 1));
+        click(&ctx, &mut editor, &mut image, Pos2::new(3.5, 3.5), tool);
+
+        let point = points.borrow()[0];
+        assert_eq!((point.pixel, point.pressure), ((3, 3), None));
+        assert!(point.time >= 0.0);
+        // A 5x5 stamp halfway to white; premultiplied, so opaque gray
+        let gray = Color32::from_gray(128);
+        let grays = image.pixels.iter().filter(|&&px| px == gray).count();
+        assert_eq!(grays, 25);
+    }
+
+    #[test]
+    fn test_draw_at_negative_coordinates() {
+        let mut image = ColorImage::new([8, 8], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        editor.pan_and_zoom = true;
+        editor.pan = Vec2::new(10.0, 0.0);
+        let ctx = egui::Context::default();
+        let tool = Tool::Brush(Brush::Rectangle(1, 1));
+        let responses = click(&ctx, &mut editor, &mut image, Pos2::new(9.5, 3.5), tool);
+
+        let hover = responses[1].hover.unwrap();
+        assert_eq!((hover.pixel, hover.color), ((-1, 3), None));
+        // Only the right edge of the brush lands on the image
+        let whites = image.pixels.iter().filter(|&&px| px == Color32::WHITE);
+        assert_eq!(whites.count(), 3);
+        assert_eq!(image.pixels[2 * 8], Color32::WHITE);
+    }
+
+    #[test]
+    fn test_max_brush_extent() {
+        let mut image = ColorImage::new([8, 8], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        editor.max_brush_extent = 3;
+        let ctx = egui::Context::default();
+        let tool = Tool::Brush(Brush::Rectangle(100_000, 100_000));
+        click(&ctx, &mut editor, &mut image, Pos2::new(3.5, 3.5), tool);
+
+        let whites = image.pixels.iter().filter(|&&px| px == Color32::WHITE);
+        assert_eq!(whites.count(), 9);
+    }
+
+    #[test]
+    fn test_floating_paste() {
+        let mut image = ColorImage::new([4, 4], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        let key = |key| Event::Key {
+            key,
+            physical_key: None,
+            pressed: true,
+            repeat: false,
+            modifiers: Modifiers::NONE,
+        };
+        let pixels = Buffer2D {
+            data: vec![Some(Color32::RED), None],
+            width: 2,
+            height: 1,
+        };
+        editor.paste(pixels, (0, 1));
+
+        // Nothing is painted while the paste floats, and moving it leaves the image alone
+        let pos = Pos2::new(3.5, 3.5);
+        click(&ctx, &mut editor, &mut image, pos, Tool::default());
+        let arrows = vec![key(Key::ArrowRight), key(Key::ArrowDown)];
+        frame(&ctx, arrows, |ui| {
+            editor.edit(ui, &mut image, Color32::WHITE, Tool::default());
+        });
+        assert!(image.pixels.iter().all(|&px| px == Color32::BLACK));
+
+        frame(&ctx, vec![key(Key::Enter)], |ui| {
+            editor.edit(ui, &mut image, Color32::WHITE, Tool::default());
+        });
+        assert!(!editor.is_floating());
+        let (red, black) = (Color32::RED, Color32::BLACK);
+        assert_eq!(image.pixels[8..12], [black, red, black, black]);
+        let history: Vec<_> = editor.undoer().history().collect();
+        assert_eq!(history, [("Paste", 1)]);
+
+        // The keys work without the shortcuts, but are left to other widgets while the
+        // pointer is away from the canvas
+        editor.keybindings.enabled = false;
+        editor.paste(Buffer2D::new(1, 1, Some(Color32::BLUE)), (0, 0));
+        for pointer in [Event::PointerGone, Event::PointerMoved(pos)] {
+            assert!(editor.is_floating());
+            frame(&ctx, vec![pointer, key(Key::Escape)], |ui| {
+                editor.edit(ui, &mut image, Color32::WHITE, Tool::default());
+            });
+        }
+        assert!(!editor.is_floating());
+        assert_eq!(image.pixels[0], Color32::BLACK);
+    }
+
+    #[test]
+    fn test_interact() {
+        let mut image = ColorImage::new([8, 8], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        editor.zoom = 2.0;
+        let ctx = egui::Context::default();
+        let pos = Pos2::new(5.0, 3.0);
+        let mut interactions = vec![];
+        for events in [vec![Event::PointerMoved(pos)], vec![button(pos, true)]] {
+            frame(&ctx, events, |ui| {
+                interactions.push(editor.interact(ui, &image))
+            });
+        }
+
+        let held = &interactions[1];
+        assert_eq!(held.hover, Some((2, 1)));
+        assert!(held.primary_down && !held.secondary_down);
+        assert_eq!(held.rect.size(), Vec2::splat(16.0));
+        assert!(image.pixels.iter().all(|&px| px == Color32::BLACK));
+        assert_eq!(editor.view_transform().to_pixel(pos), (2, 1));
+        // Nothing was painted, so there is nothing to undo
+        editor.undo(&mut image);
+        assert!(!editor.is_dirty());
+    }
+
+    #[test]
+    fn test_split_draw_and_input() {
+        let mut image = ColorImage::new([8, 8], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        let tool = Tool::Brush(Brush::Rectangle(0, 0));
+        let (a, b) = (Pos2::new(1.5, 1.5), Pos2::new(5.5, 5.5));
+
+        // Input handled without drawing the canvas first in the same pass draws it anew,
+        // rather than reusing the canvas of an earlier pass
+        frame(&ctx, vec![Event::PointerMoved(a)], |ui| {
+            editor.draw_canvas(ui, &image);
+        });
+        let (white, black) = (Color32::WHITE, Color32::BLACK);
+        for events in [vec![button(a, true)], vec![button(a, false)]] {
+            frame(&ctx, events, |ui| {
+                editor.handle_input(ui, &mut image, white, black, tool);
+            });
+        }
+        assert_eq!(image.pixels[1 + 8], white);
+
+        click_with(&ctx, b, PointerButton::Primary, |ui| {
+            let canvas = editor.draw_canvas(ui, &image);
+            ui.painter().rect_filled(canvas.rect, 0.0, Color32::RED);
+            let resp = editor.handle_input(ui, &mut image, white, black, tool);
+            // Both halves share one widget
+            assert_eq!(resp.egui_response.id, canvas.id);
+        });
+        assert_eq!(image.pixels[5 + 5 * 8], white);
+        assert_eq!(image.pixels.iter().filter(|&&px| px == white).count(), 2);
+    }
+
+    #[test]
+    fn test_remote_stroke() {
+        let mut image = ColorImage::new([8, 8], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        let tool = Tool::Brush(Brush::Rectangle(0, 0));
+        let (a, b) = (Pos2::new(1.5, 1.5), Pos2::new(4.5, 1.5));
+
+        // Nothing is reported until the stroke is finished
+        drag(&ctx, &mut editor, &mut image, (a, b), tool, |_| ());
+        let stroke = editor.take_last_stroke().unwrap();
+        let expected: Vec<_> = (1..=4).map(|x| (x, 1, Color32::WHITE)).collect();
+        assert_eq!(stroke, expected);
+        assert_eq!(editor.take_last_stroke(), None);
+
+        // A click over what is already painted changes nothing, so there is no stroke
+        click(&ctx, &mut editor, &mut image, a, tool);
+        assert_eq!(editor.take_last_stroke(), None);
+
+        let mut peer_image = ColorImage::new([8, 8], Color32::BLACK);
+        let mut peer = ImageEditor::<Color32>::from_tile_size(8);
+        peer.mask = Some(HashSet::new());
+        peer.apply_remote_stroke(&mut peer_image, &stroke);
+        assert_eq!(peer_image, image);
+        assert_eq!(peer.undoer().history().collect::<Vec<_>>(), [("Remote", 4)]);
+        assert_eq!(peer.mask, Some(HashSet::new()));
+
+        peer.undo(&mut peer_image);
+        assert_eq!(peer_image, ColorImage::new([8, 8], Color32::BLACK));
+
+        // Pixels past the edges of the image are dropped, and never reach the history
+        let outside = [(-1, 0), (2, 2), (8, 3)].map(|(x, y)| (x, y, Color32::RED));
+        peer.apply_remote_stroke(&mut peer_image, &outside);
+        assert_eq!(peer.undoer().history().last(), Some(("Remote", 1)));
+        assert_eq!(peer_image.pixels[2 * 8 + 2], Color32::RED);
+        peer.undo(&mut peer_image);
+        assert_eq!(peer_image, ColorImage::new([8, 8], Color32::BLACK));
+    }
+
+    #[test]
+    fn test_mask() {
+        let mut image = ColorImage::new([8, 8], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        editor.mask = Some([(0, 0), (1, 0)].into_iter().collect());
+
+        let brush = Tool::Brush(Brush::Rectangle(1, 1));
+        click(&ctx, &mut editor, &mut image, Pos2::new(4.5, 4.5), brush);
+        assert_eq!(image, ColorImage::new([8, 8], Color32::BLACK));
+        assert_eq!(editor.undoer.history_bytes(), 0);
+
+        // Partially inside the mask, and partially outside the image
+        let responses = click(&ctx, &mut editor, &mut image, Pos2::new(0.5, 0.5), brush);
+        let changed: Vec<_> = responses.into_iter().flat_map(|r| r.changed).collect();
+        assert_eq!(changed, [(0, 0), (1, 0)]);
+
+        editor.mask = None;
+        click(&ctx, &mut editor, &mut image, Pos2::new(4.5, 4.5), brush);
+        assert_eq!(image.pixels[4 + 4 * 8], Color32::WHITE);
+    }
+
+    #[test]
+    fn test_edit_reports_changed() {
+        let mut image = ColorImage::new([8, 8], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        let responses = click(
+            &ctx,
+            &mut editor,
+            &mut image,
+            Pos2::new(2.5, 3.5),
+            Tool::Brush(Brush::Rectangle(1, 0)),
+        );
+
+        let last = responses.last().unwrap();
+        let hover = HoverInfo {
+            pixel: (2, 3),
+            color: Some(Color32::WHITE),
+        };
+        assert_eq!(last.hover, Some(hover));
+        assert!(last.stroke_finished);
+        assert!(responses[1].drew && !responses[1].stroke_finished);
+        let changed: Vec<_> = responses.into_iter().flat_map(|r| r.changed).collect();
+        assert_eq!(changed, [(1, 3), (2, 3), (3, 3)]);
+        assert_eq!(image.pixels[2 + 3 * 8], Color32::WHITE);
+
+        // Painting over the same pixels changes nothing
+        let responses = click(
+            &ctx,
+            &mut editor,
+            &mut image,
+            Pos2::new(2.5, 3.5),
+            Tool::Brush(Brush::Rectangle(1, 0)),
+        );
+        assert!(responses.iter().all(|r| r.changed.is_empty() && !r.drew));
+
+        // Off the edge of the image
+        let responses = click(
+            &ctx,
+            &mut editor,
+            &mut image,
+            Pos2::new(9.5, 3.5),
+            Tool::default(),
+        );
+        let hover = responses.last().unwrap().hover.unwrap();
+        assert_eq!((hover.pixel, hover.color), ((9, 3), None));
+    }
+
+    #[test]
+    fn test_on_change_only_fires_on_change() {
+        let mut image = ColorImage::new([4, 4], Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+
+        let changed = Rc::new(RefCell::new(vec![]));
+        let sink = changed.clone();
+        editor.on_change = Some(Box::new(move |x, y, px| {
+            sink.borrow_mut().push((x, y, *px))
+        }));
+
+        editor.apply(&mut image, |image| {
+            image.set_pixel(1, 2, Color32::RED);
+            image.set_pixel(3, 3, Color32::BLACK);
+        });
+        assert_eq!(*changed.borrow(), [(1, 2, Color32::RED)]);
+    }
+
+    #[derive(Copy, Clone, PartialEq, Debug)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    struct Height(f32);
+
+    impl PixelInterface for Height {
+        fn as_rgba(&self) -> Color32 {
+            Color32::from_gray((self.0 * 255.0) as u8)
+        }
+    }
+
+    #[test]
+    fn test_non_color_pixels() {
+        let mut image = Buffer2D::new(8, 8, Height(0.0));
+        let mut editor = ImageEditor::from_tile_size(8);
+        let ctx = egui::Context::default();
+        let pos = Pos2::new(2.5, 3.5);
+        click_with(&ctx, pos, PointerButton::Primary, |ui| {
+            editor.edit(ui, &mut image, Height(0.5), Brush::Rectangle(1, 1));
+        });
+        assert_eq!(image.get_pixel(2, 3), Height(0.5));
+
+        editor.undo(&mut image);
+        assert_eq!(image, Buffer2D::new(8, 8, Height(0.0)));
+    }
+}
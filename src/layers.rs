@@ -0,0 +1,305 @@
+use std::ops::RangeInclusive;
+
+use egui::Color32;
+
+use crate::image::{Buffer2D, Image, ImageExt, ImageRef, PixelInterface};
+
+/// One layer of a [`LayerStack`]. Its image can only be changed through the stack, which
+/// keeps every layer the same size.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Layer<P> {
+    image: Buffer2D<P>,
+    /// Hidden layers are left out of the composite, but can still be edited
+    pub visible: bool,
+    /// How opaque the whole layer is in the composite, from 0 to 1
+    pub opacity: f32,
+}
+
+impl<P> Layer<P> {
+    pub fn image(&self) -> &Buffer2D<P> {
+        &self.image
+    }
+}
+
+/// Several layers of the same size, blended from the bottom up for display.
+///
+/// As an [`Image`], the stack reads and writes the layer at [`Self::active`], so the editor
+/// only paints on that one. The undo history records which layer each change was made to
+/// (see [`crate::SparseImageUndoer::set_layer`]), so that
+/// [`crate::ImageEditor::undo_layers`] goes back to it. Show [`Self::composite`] to see all
+/// of them at once, e.g. with [`crate::ImageEditor::edit_layers`], which does both. Changing
+/// the visibility or opacity of a layer changes the composite without writing any pixel, so
+/// the editor needs to be told with [`crate::ImageEditor::invalidate_all`].
+///
+/// There is always at least one layer, and the active one always exists.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LayerStack<P> {
+    /// From the bottom up
+    layers: Vec<Layer<P>>,
+    /// Index of the layer being edited
+    active: usize,
+    width: usize,
+    height: usize,
+}
+
+/// The visible layers of a [`LayerStack`] blended together; see [`LayerStack::composite`]
+pub struct Composite<'stack, P> {
+    stack: &'stack LayerStack<P>,
+}
+
+impl<P: Clone> LayerStack<P> {
+    /// A stack with a single visible, opaque layer filled with `fill`
+    pub fn new(width: usize, height: usize, fill: P) -> Self {
+        let mut stack = Self {
+            layers: vec![],
+            active: 0,
+            width,
+            height,
+        };
+        stack.push_layer(fill);
+        stack
+    }
+
+    /// Adds a visible, opaque layer filled with `fill` on top, returning its index
+    pub fn push_layer(&mut self, fill: P) -> usize {
+        self.layers.push(Layer {
+            image: Buffer2D::new(self.width, self.height, fill),
+            visible: true,
+            opacity: 1.0,
+        });
+        self.layers.len() - 1
+    }
+
+    /// Removes the layer at `index` and returns it, keeping the same layer active unless it
+    /// was the one removed, in which case the one below it (or the new bottom layer) is.
+    /// Returns `None` if there is no such layer, or it is the only one. Changes to the
+    /// layers above now refer to the wrong layer, so the undo history needs to be told
+    /// too; [`crate::ImageEditor::remove_layer`] does both.
+    pub fn remove_layer(&mut self, index: usize) -> Option<Layer<P>> {
+        if index >= self.layers.len() || self.layers.len() == 1 {
+            return None;
+        }
+        let layer = self.layers.remove(index);
+        if self.active > index || (self.active == index && index > 0) {
+            self.active -= 1;
+        }
+        Some(layer)
+    }
+
+    /// From the bottom up
+    pub fn layers(&self) -> &[Layer<P>] {
+        &self.layers
+    }
+
+    /// Shows or hides the layer at `index`, if there is one
+    pub fn set_visible(&mut self, index: usize, visible: bool) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.visible = visible;
+        }
+    }
+
+    /// Sets the opacity of the layer at `index`, if there is one, clamped to 0 to 1
+    pub fn set_opacity(&mut self, index: usize, opacity: f32) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.opacity = opacity.clamp(0.0, 1.0);
+        }
+    }
+
+    /// Index of the layer being edited
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    /// Edits the layer at `index` from now on. Indices past the top layer are ignored.
+    pub fn set_active(&mut self, index: usize) {
+        if index < self.layers.len() {
+            self.active = index;
+        }
+    }
+
+    /// Runs `f` with the layer at `layer` active, or without the stack if there is no such
+    /// layer, then switches back to the layer which was active before
+    pub(crate) fn with_layer<R>(
+        &mut self,
+        layer: usize,
+        f: impl FnOnce(Option<&mut Self>) -> R,
+    ) -> R {
+        let active = self.active;
+        if layer >= self.layers.len() {
+            return f(None);
+        }
+        self.active = layer;
+        let result = f(Some(self));
+        self.active = active;
+        result
+    }
+
+    /// A read-only view of the layers blended as they are displayed
+    pub fn composite(&self) -> Composite<'_, P> {
+        Composite { stack: self }
+    }
+}
+
+/// Reads and writes go to the active layer
+impl<P: Clone> Image for LayerStack<P> {
+    type Pixel = P;
+    fn get_pixel(&self, x: isize, y: isize) -> Self::Pixel {
+        self.layers[self.active].image.get_pixel(x, y)
+    }
+
+    fn set_pixel(&mut self, x: isize, y: isize, px: Self::Pixel) {
+        self.layers[self.active].image.set_pixel(x, y, px);
+    }
+
+    fn set_pixels(&mut self, pixels: &mut dyn Iterator<Item = (isize, isize, Self::Pixel)>) {
+        self.layers[self.active].image.set_pixels(pixels);
+    }
+
+    fn fill_row(&mut self, y: isize, x_range: RangeInclusive<isize>, px: Self::Pixel) {
+        self.layers[self.active].image.fill_row(y, x_range, px);
+    }
+
+    fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
+        (0..=self.width as isize - 1, 0..=self.height as isize - 1)
+    }
+}
+
+impl<P: Clone> ImageRef for LayerStack<P> {
+    fn get_pixel_ref(&self, x: isize, y: isize) -> &Self::Pixel {
+        self.layers[self.active].image.get_pixel_ref(x, y)
+    }
+}
+
+/// Each visible layer is blended over those below it, scaled by its opacity. Out of bounds,
+/// reads are transparent. Writes are dropped, since the composite has no pixels of its own.
+impl<P: PixelInterface + Clone> Image for Composite<'_, P> {
+    type Pixel = Color32;
+    fn get_pixel(&self, x: isize, y: isize) -> Self::Pixel {
+        if !self.stack.bounds_check(x, y) {
+            return Color32::TRANSPARENT;
+        }
+        let visible = self.stack.layers.iter().filter(|layer| layer.visible);
+        visible.fold(Color32::TRANSPARENT, |below, layer| {
+            let color = layer.image.get_pixel(x, y).as_rgba();
+            below.blend(color.gamma_multiply(layer.opacity))
+        })
+    }
+
+    fn set_pixel(&mut self, _x: isize, _y: isize, _px: Self::Pixel) {}
+
+    fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
+        self.stack.image_boundaries()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ImageEditor, SparseImageUndoer};
+
+    #[test]
+    fn test_composite() {
+        let mut stack = LayerStack::new(2, 1, Color32::BLACK);
+        let top = stack.push_layer(Color32::TRANSPARENT);
+        stack.set_active(top);
+        stack.set_pixel(1, 0, Color32::WHITE);
+        assert_eq!(stack.layers()[0].image.data, [Color32::BLACK; 2]);
+
+        let composite = |stack: &LayerStack<Color32>| -> Vec<_> {
+            (0..2).map(|x| stack.composite().get_pixel(x, 0)).collect()
+        };
+        assert_eq!(composite(&stack), [Color32::BLACK, Color32::WHITE]);
+
+        stack.set_opacity(top, 0.5);
+        assert_eq!(composite(&stack)[1], Color32::from_gray(128));
+
+        stack.set_visible(top, false);
+        stack.set_visible(0, false);
+        assert_eq!(composite(&stack), [Color32::TRANSPARENT; 2]);
+        assert_eq!(stack.composite().get_pixel(5, 0), Color32::TRANSPARENT);
+    }
+
+    #[test]
+    fn test_undo_returns_to_layer() {
+        let mut stack = LayerStack::new(2, 2, 0u8);
+        stack.push_layer(0);
+        let mut undoer = SparseImageUndoer::new();
+
+        undoer.new_frame();
+        stack.set_active(1);
+        undoer.set_layer(stack.active());
+        undoer.set_pixel(&mut stack, 0, 0, 1);
+        // Switching layers within a frame splits it, so each part undoes on its own layer
+        stack.set_active(0);
+        undoer.set_layer(stack.active());
+        undoer.set_pixel(&mut stack, 1, 1, 2);
+        assert_eq!(undoer.history().count(), 2);
+
+        undoer.undo_layers(&mut stack);
+        assert_eq!(stack.layers()[0].image.data, [0; 4]);
+        undoer.undo_layers(&mut stack);
+        assert_eq!(stack.layers()[1].image.data, [0; 4]);
+        // The active layer is left as it was
+        assert_eq!(stack.active(), 0);
+
+        undoer.redo_layers(&mut stack);
+        assert_eq!(stack.layers()[1].image.data, [1, 0, 0, 0]);
+        assert_eq!(stack.layers()[0].image.data, [0; 4]);
+    }
+
+    #[test]
+    fn test_editor_undo_on_layer() {
+        let mut stack = LayerStack::new(2, 2, Color32::BLACK);
+        let top = stack.push_layer(Color32::TRANSPARENT);
+        stack.set_active(top);
+        let mut editor = ImageEditor::from_tile_size(8);
+        editor.undoer().set_layer(top);
+        editor.fill_all(&mut stack, Color32::WHITE);
+
+        stack.set_active(0);
+        editor.undo_layers(&mut stack);
+        assert_eq!(stack.layers()[1].image.data, [Color32::TRANSPARENT; 4]);
+        assert_eq!(stack.layers()[0].image.data, [Color32::BLACK; 4]);
+    }
+
+    #[test]
+    fn test_remove_layer() {
+        let mut stack = LayerStack::new(1, 1, 0u8);
+        stack.push_layer(1);
+        stack.push_layer(2);
+        stack.set_active(5);
+        assert_eq!(stack.active(), 0);
+
+        stack.set_active(2);
+        let removed = stack.remove_layer(0).map(|layer| layer.image.data);
+        assert_eq!(removed, Some(vec![0]));
+        // Still on the same layer, which moved down
+        assert_eq!((stack.active(), stack.get_pixel(0, 0)), (1, 2));
+        // Removing the active layer moves to the one below it
+        stack.remove_layer(1);
+        assert_eq!((stack.active(), stack.get_pixel(0, 0)), (0, 1));
+        assert_eq!(stack.remove_layer(1), None);
+        assert_eq!(stack.remove_layer(0), None);
+    }
+
+    #[test]
+    fn test_editor_remove_layer() {
+        let mut stack = LayerStack::new(1, 1, Color32::BLACK);
+        let mut editor = ImageEditor::from_tile_size(8);
+        for gray in 0..3 {
+            let layer = stack.push_layer(Color32::TRANSPARENT);
+            stack.set_active(layer);
+            editor.undoer().set_layer(layer);
+            editor.fill_all(&mut stack, Color32::from_gray(gray));
+        }
+
+        assert!(editor.remove_layer(&mut stack, 2).is_some());
+        assert_eq!(editor.undoer().history().count(), 2);
+        // The last fill was on the removed layer, and the one before it moved down
+        editor.undo_layers(&mut stack);
+        assert_eq!(stack.layers()[2].image.data, [Color32::TRANSPARENT]);
+        assert_eq!(stack.layers()[1].image.data, [Color32::from_gray(0)]);
+    }
+}
@@ -12,9 +12,24 @@ use egui::{
 mod tiled_image;
 pub mod image;
 mod image_editor;
+mod layers;
 mod undo;
 mod brush;
 mod ellipse;
+mod fill;
+mod filter;
+pub mod transform;
+pub mod raster;
+mod tool;
 
-pub use image_editor::ImageEditor;
-pub use brush::Brush;
+pub use image_editor::{
+    BrushAdjustment, BrushDynamics, CanEdit, EditResponse, EditorState, HoverInfo, ImageEditor,
+    Interaction, Keybindings, StrokePoint, ViewTransform,
+};
+pub use brush::{Brush, CompiledBrush, StampMask};
+pub use tool::Tool;
+pub use fill::{nearest_in_palette, Dither};
+pub use image::{OutOfBounds, WrapMode};
+pub use layers::{Composite, Layer, LayerStack};
+pub use tiled_image::TileStats;
+pub use undo::{OnChange, SparseImageUndoer};
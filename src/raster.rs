@@ -0,0 +1,333 @@
+use crate::{
+    ellipse,
+    image::{Image, ImageExt, PixelInterface},
+};
+
+/// Calls `f` on each pixel of the line from `a` to `b` (inclusive), using Bresenham's algorithm
+pub fn line((x0, y0): (isize, isize), (x1, y1): (isize, isize), mut f: impl FnMut(isize, isize)) {
+    let dx = (x1 - x0).abs();
+    let dy = -(y1 - y0).abs();
+    let sx = if x0 < x1 { 1 } else { -1 };
+    let sy = if y0 < y1 { 1 } else { -1 };
+
+    let mut err = dx + dy;
+    let (mut x, mut y) = (x0, y0);
+    loop {
+        f(x, y);
+        if x == x1 && y == y1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+/// Calls `f` on each pixel near the line from `a` to `b` with how much of it the line
+/// covers, from 0 to 1, using Xiaolin Wu's algorithm. Points are in pixels, with pixel
+/// `(x, y)` centered on `(x as f32, y as f32)`. Each step along the longer axis shares a
+/// coverage of 1 between the two pixels straddling the line, so the coverages add up to
+/// the length of the line along that axis. A line which starts and ends in the same pixel
+/// covers it like a dot, and each pixel is visited at most once. Pixels with no coverage
+/// are skipped.
+pub fn line_aa((x0, y0): (f32, f32), (x1, y1): (f32, f32), mut f: impl FnMut(isize, isize, f32)) {
+    let steep = (y1 - y0).abs() > (x1 - x0).abs();
+    // Walk along x from left to right, swapping the axes back when plotting
+    let (mut a, mut b) = ((x0, y0), (x1, y1));
+    if steep {
+        (a, b) = ((a.1, a.0), (b.1, b.0));
+    }
+    if a.0 > b.0 {
+        (a, b) = (b, a);
+    }
+    let mut plot = |x: isize, y: isize, coverage: f32| {
+        let (x, y) = if steep { (y, x) } else { (x, y) };
+        if coverage > 0.0 {
+            f(x, y, coverage);
+        }
+    };
+
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let gradient = if dx == 0.0 { 1.0 } else { dy / dx };
+    let fract = |v: f32| v - v.floor();
+
+    // Each end only covers the part of its pixel on the line's side of the end point
+    let mut end = |(x, y): (f32, f32), gap: f32| {
+        let x_pixel = (x + 0.5).floor();
+        let y_end = y + gradient * (x_pixel - x);
+        let (x_pixel, y_pixel) = (x_pixel as isize, y_end.floor() as isize);
+        plot(x_pixel, y_pixel, (1.0 - fract(y_end)) * gap);
+        plot(x_pixel, y_pixel + 1, fract(y_end) * gap);
+        (x_pixel, y_end)
+    };
+    if (a.0 + 0.5).floor() == (b.0 + 0.5).floor() {
+        // Both ends share a pixel, so only one of them is plotted
+        end(a, 1.0);
+        return;
+    }
+    let (first, y_first) = end(a, 1.0 - fract(a.0 + 0.5));
+    let (last, _) = end(b, fract(b.0 + 0.5));
+
+    let mut y = y_first + gradient;
+    for x in first + 1..last {
+        plot(x, y.floor() as isize, 1.0 - fract(y));
+        plot(x, y.floor() as isize + 1, fract(y));
+        y += gradient;
+    }
+}
+
+/// Draws the anti-aliased line from `a` to `b` (see [`line_aa`]) onto the image, blending
+/// `color` over each pixel by its coverage with [`PixelInterface::lerp`]. Pixels which
+/// can't blend take the color where the line covers at least half of them.
+pub fn draw_line_aa<I>(image: &mut I, a: (f32, f32), b: (f32, f32), color: &I::Pixel)
+where
+    I: Image + ?Sized,
+    I::Pixel: PixelInterface + Clone,
+{
+    line_aa(a, b, |x, y, coverage| {
+        if let Some(px) = image.get_pixel_checked(x, y) {
+            image.set_pixel_checked(x, y, px.lerp(color, coverage, 0.5));
+        }
+    });
+}
+
+/// Moves `b` so that the line from `a` is horizontal, vertical, or diagonal,
+/// whichever is closest to the original direction
+pub fn snap_45((ax, ay): (isize, isize), (bx, by): (isize, isize)) -> (isize, isize) {
+    let (dx, dy) = (bx - ax, by - ay);
+    let (adx, ady) = (dx.abs(), dy.abs());
+    // tan(22.5 degrees) ~= 0.414
+    if ady * 1000 <= adx * 414 {
+        (bx, ay)
+    } else if adx * 1000 <= ady * 414 {
+        (ax, by)
+    } else {
+        let d = adx.max(ady);
+        (ax + d * dx.signum(), ay + d * dy.signum())
+    }
+}
+
+/// Calls `f` on each border pixel of the rectangle with corners `a` and `b` (inclusive)
+pub fn rect_outline(
+    (ax, ay): (isize, isize),
+    (bx, by): (isize, isize),
+    mut f: impl FnMut(isize, isize),
+) {
+    let (x0, x1) = (ax.min(bx), ax.max(bx));
+    let (y0, y1) = (ay.min(by), ay.max(by));
+
+    for x in x0..=x1 {
+        f(x, y0);
+        if y1 != y0 {
+            f(x, y1);
+        }
+    }
+    for y in y0 + 1..y1 {
+        f(x0, y);
+        if x1 != x0 {
+            f(x1, y);
+        }
+    }
+}
+
+/// Calls `f` on each boundary pixel of the ellipse inscribed in the rectangle with corners
+/// `a` and `b`. A pixel is on the boundary if it is inside the ellipse and at least one of
+/// its four neighbours is not. An even width or height has two middle columns or rows, and
+/// the ellipse is mirrored across the half pixel between them.
+pub fn ellipse_outline(
+    (ax, ay): (isize, isize),
+    (bx, by): (isize, isize),
+    mut f: impl FnMut(isize, isize),
+) {
+    // Larger rectangles are cut short on the right and bottom, rather than overflowing
+    let limit = |lo: isize, hi: isize| {
+        let max = lo.saturating_add(ellipse::MAX_RADIUS);
+        (lo, hi.min(max.saturating_add(ellipse::MAX_RADIUS)))
+    };
+    let (x0, x1) = limit(ax.min(bx), ax.max(bx));
+    let (y0, y1) = limit(ay.min(by), ay.max(by));
+    let (wx, wy) = ((x1 - x0) / 2, (y1 - y0) / 2);
+    // The middle columns and rows, which are the same ones for odd sizes
+    let (left, right) = (x0 + wx, x1 - wx);
+    let (top, bottom) = (y0 + wy, y1 - wy);
+
+    // Half-height of each column, or -1 outside the ellipse
+    let half_height = |dx: isize| {
+        if dx <= wx {
+            ellipse::solve_ellipse(wx, wy, dx)
+        } else {
+            -1
+        }
+    };
+    // Both sides of the middle, once if they are the same pixel
+    let mirror = |lo: isize, hi: isize| [Some(hi), (lo != hi).then_some(lo)].into_iter().flatten();
+
+    for dx in 0..=wx {
+        let h = half_height(dx);
+        // Rows above this are exposed on the outer side. The column on the inner side is
+        // at least as tall, so it never exposes more.
+        let covered = half_height(dx + 1);
+        for dy in (covered + 1).min(h)..=h {
+            for x in mirror(left - dx, right + dx) {
+                for y in mirror(top - dy, bottom + dy) {
+                    f(x, y);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+
+    fn collect(shape: impl FnOnce(&mut dyn FnMut(isize, isize))) -> HashSet<(isize, isize)> {
+        let mut set = HashSet::new();
+        shape(&mut |x, y| {
+            set.insert((x, y));
+        });
+        set
+    }
+
+    fn line_pixels(a: (isize, isize), b: (isize, isize)) -> Vec<(isize, isize)> {
+        let mut pixels = vec![];
+        line(a, b, |x, y| pixels.push((x, y)));
+        pixels
+    }
+
+    #[test]
+    fn test_line() {
+        assert_eq!(line_pixels((2, 2), (2, 2)), [(2, 2)]);
+        let horizontal = [(3, 1), (2, 1), (1, 1), (0, 1)];
+        assert_eq!(line_pixels((3, 1), (0, 1)), horizontal);
+        assert_eq!(line_pixels((0, -1), (0, 1)), [(0, -1), (0, 0), (0, 1)]);
+        assert_eq!(line_pixels((0, 0), (-2, 2)), [(0, 0), (-1, 1), (-2, 2)]);
+
+        // Shallow lines step once per column, from one end to the other
+        let pixels = line_pixels((0, 0), (6, 2));
+        assert_eq!(pixels.len(), 7);
+        assert_eq!((pixels[0], pixels[6]), ((0, 0), (6, 2)));
+        let step = |w: &[(isize, isize)]| (w[1].0 - w[0].0, w[1].1 - w[0].1);
+        assert!(pixels.windows(2).all(|w| matches!(step(w), (1, 0 | 1))));
+    }
+
+    fn coverage(a: (f32, f32), b: (f32, f32)) -> HashMap<(isize, isize), f32> {
+        let mut coverage = HashMap::new();
+        line_aa(a, b, |x, y, c| *coverage.entry((x, y)).or_default() += c);
+        coverage
+    }
+
+    #[test]
+    fn test_line_aa_coverage() {
+        for (a, b) in [
+            ((0.0, 0.0), (10.0, 3.0)),
+            ((2.0, 9.0), (-1.0, -4.0)),
+            ((0.0, 0.0), (-6.0, 6.0)),
+            ((0.3, 0.7), (7.6, 2.2)),
+        ] {
+            let coverage = coverage(a, b);
+            let sum: f32 = coverage.values().sum();
+            let length = (b.0 - a.0).abs().max((b.1 - a.1).abs());
+            assert!((sum - length).abs() < 1e-3, "{a:?} {b:?}: {sum}");
+            assert!(coverage.values().all(|c| (0.0..=1.0 + 1e-6).contains(c)));
+        }
+
+        // Lines along a row or a diagonal cover whole pixels, halving the ends
+        let horizontal = coverage((0.0, 2.0), (4.0, 2.0));
+        assert_eq!(horizontal.len(), 5);
+        assert_eq!((horizontal[&(0, 2)], horizontal[&(2, 2)]), (0.5, 1.0));
+        let diagonal = coverage((0.0, 0.0), (3.0, 3.0));
+        assert_eq!(diagonal.len(), 4);
+        assert_eq!(diagonal[&(1, 1)], 1.0);
+
+        // Points, and lines within one pixel, cover it once
+        for (a, b) in [((3.0, 1.0), (3.0, 1.0)), ((-0.2, 4.0), (0.3, 4.0))] {
+            let mut visits = vec![];
+            line_aa(a, b, |x, y, c| visits.push((x, y, c)));
+            assert_eq!(visits.len(), 1, "{a:?} {b:?}");
+        }
+        assert_eq!(coverage((3.0, 1.0), (3.0, 1.0))[&(3, 1)], 1.0);
+    }
+
+    #[test]
+    fn test_draw_line_aa() {
+        use egui::{Color32, ColorImage};
+        let mut image = ColorImage::new([8, 4], Color32::BLACK);
+        draw_line_aa(&mut image, (0.0, 1.0), (7.0, 2.0), &Color32::WHITE);
+        // Straddling two rows halfway along, and clipped at the edges of the image
+        let (upper, lower) = (image.pixels[4 + 8], image.pixels[4 + 2 * 8]);
+        assert!(upper.r() > 0 && lower.r() > 0 && upper.r() < 255);
+        assert_eq!(image.pixels[3 * 8 + 3], Color32::BLACK);
+
+        // A point blends once, rather than once for each end
+        draw_line_aa(&mut image, (5.0, 0.0), (5.0, 0.0), &Color32::WHITE);
+        assert_eq!(image.pixels[5], Color32::WHITE);
+    }
+
+    #[test]
+    fn test_snap_45() {
+        assert_eq!(snap_45((0, 0), (10, 3)), (10, 0));
+        assert_eq!(snap_45((0, 0), (-2, 9)), (0, 9));
+        assert_eq!(snap_45((1, 1), (-5, 6)), (-5, 7));
+    }
+
+    #[test]
+    fn test_rect_outline() {
+        let pixels = collect(|f| rect_outline((3, 2), (0, 0), f));
+        assert_eq!(pixels.len(), 10);
+        assert!(pixels.contains(&(0, 1)) && pixels.contains(&(3, 1)));
+        assert!(!pixels.contains(&(1, 1)) && !pixels.contains(&(2, 1)));
+
+        assert_eq!(collect(|f| rect_outline((5, 5), (5, 5), f)).len(), 1);
+    }
+
+    #[test]
+    fn test_ellipse_outline_touches_sides() {
+        for width in 1..=9 {
+            for height in 1..=9 {
+                let corner = (2 + width - 1, -3 + height - 1);
+                let outline = collect(|f| ellipse_outline((2, -3), corner, f));
+                let xs = outline.iter().map(|&(x, _)| x);
+                let ys = outline.iter().map(|&(_, y)| y);
+                let bounds = (xs.clone().min(), xs.max(), ys.clone().min(), ys.max());
+                let expected = (Some(2), Some(corner.0), Some(-3), Some(corner.1));
+                assert_eq!(bounds, expected, "width={width} height={height}");
+                // Symmetric about the middle both ways
+                for &(x, y) in &outline {
+                    assert!(outline.contains(&(2 + corner.0 - x, y)));
+                    assert!(outline.contains(&(x, -3 + corner.1 - y)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_ellipse_outline_is_boundary() {
+        for (wx, wy) in [(0, 0), (1, 3), (4, 4), (7, 2), (10, 6)] {
+            let outline = collect(|f| ellipse_outline((-wx, -wy), (wx, wy), f));
+            let inside = |x: isize, y: isize| {
+                x.abs() <= wx && y.abs() <= wy && ellipse::ellipse(wx, wy, x, y)
+            };
+            for y in -wy - 1..=wy + 1 {
+                for x in -wx - 1..=wx + 1 {
+                    let boundary = inside(x, y)
+                        && [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                            .iter()
+                            .any(|(dx, dy)| !inside(x + dx, y + dy));
+                    assert_eq!(
+                        outline.contains(&(x, y)),
+                        boundary,
+                        "x={x} y={y} wx={wx} wy={wy}"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -8,18 +8,68 @@ use egui::{
     Painter, Pos2, Rect, Sense, Stroke, StrokeKind, TextureId, TextureOptions, Ui, Vec2, Widget,
 };
 
-use crate::image::{Image, ImageExt, PixelInterface};
+#[cfg(feature = "parallel")]
+use std::{
+    panic::AssertUnwindSafe,
+    sync::{mpsc, Arc, Mutex, OnceLock},
+    thread,
+};
+
+use crate::{
+    image::{Image, ImageExt, MaybeSend, PixelInterface},
+    ViewTransform,
+};
 
 
 #[derive(Copy, Clone)]
 struct Tile {
     tex_id: TextureId,
     is_dirty: bool,
+    /// Counts the times the tile was marked dirty, so that a patch sampled in the
+    /// background before the latest change doesn't mark it clean
+    #[cfg(feature = "parallel")]
+    version: u64,
+    /// Whether a patch of the tile is being sampled in the background
+    #[cfg(feature = "parallel")]
+    sampling: bool,
+}
+
+/// A patch sampled in the background, along with the tile and version it was sampled for
+#[cfg(feature = "parallel")]
+type SampledPatch = ((isize, isize), u64, thread::Result<ColorImage>);
+
+/// Tile bookkeeping for the most recent [`TiledEguiImage::draw`], for debugging
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TileStats {
+    /// Tiles with a texture allocated
+    pub resident: usize,
+    /// Dirty tiles which were uploaded again
+    pub dirty_uploaded: usize,
+    /// Tiles which were given a texture since the previous draw, including by prefetching
+    pub allocated_this_frame: usize,
+    /// Resident tiles still waiting to be uploaded again, e.g. those outside the image
+    pub dirty: usize,
+    /// Roughly how much texture memory the resident tiles take, in bytes, at four bytes
+    /// per texel
+    pub bytes_estimate: usize,
 }
 
 pub struct TiledEguiImage {
     tiles: HashMap<(isize, isize), Tile>,
     texture_width: usize,
+    /// If set, the tiles grow along with the image up to this size; see [`Self::new`]
+    max_texture_width: Option<usize>,
+    texture_options: TextureOptions,
+    /// Counts since the previous draw
+    pending_stats: TileStats,
+    stats: TileStats,
+    /// Tiles written to since the last `take_dirty_rects`, whether or not they are resident
+    changed_tiles: HashSet<(isize, isize)>,
+    /// Counts the writes and invalidations; see [`Self::generation`]
+    generation: u64,
+    /// Where the background threads send the patches they sample for these tiles
+    #[cfg(feature = "parallel")]
+    patches: (mpsc::Sender<SampledPatch>, mpsc::Receiver<SampledPatch>),
 }
 
 impl TiledEguiImage {
@@ -27,89 +77,375 @@ impl TiledEguiImage {
         Self {
             tiles: Default::default(),
             texture_width,
+            max_texture_width: None,
+            texture_options: TextureOptions::NEAREST,
+            pending_stats: TileStats::default(),
+            stats: TileStats::default(),
+            changed_tiles: HashSet::new(),
+            generation: 0,
+            #[cfg(feature = "parallel")]
+            patches: mpsc::channel(),
         }
     }
 
+    /// Tiles no larger than needed to cover the image, so that small images don't get
+    /// mostly empty textures, up to the largest texture size the context allows (at most
+    /// 512). The size is checked against the image on every draw, and if the image has
+    /// outgrown it, every tile is dropped and sampled again at the larger size.
     pub fn new(ctx: &egui::Context) -> Self {
-        const MAX_TEXTURE_SIZE: usize = 512;
-        let texture_width = ctx.fonts(|r| r.max_texture_side()).min(MAX_TEXTURE_SIZE);
-        Self::from_tile_size(texture_width)
+        Self::new_for_image(ctx, (0, 0))
+    }
+
+    /// Like [`Self::new`], but starting with tiles to suit an image of `(width, height)`
+    /// pixels, so that they don't have to grow on the first draw
+    pub fn new_for_image(ctx: &egui::Context, dims: (usize, usize)) -> Self {
+        let max = max_tile_size(ctx);
+        Self {
+            max_texture_width: Some(max),
+            ..Self::from_tile_size(tile_size_for_image(max, dims))
+        }
+    }
+
+    /// Empty tiles of the same size as these, which grow with their image the same way
+    pub(crate) fn empty_like(&self) -> Self {
+        Self {
+            max_texture_width: self.max_texture_width,
+            ..Self::from_tile_size(self.texture_width)
+        }
+    }
+
+    pub fn stats(&self) -> TileStats {
+        self.stats
+    }
+
+    pub fn texture_options(&self) -> TextureOptions {
+        self.texture_options
+    }
+
+    /// Sets the sampling options for the tile textures, re-uploading every tile if they changed
+    pub fn set_texture_options(&mut self, texture_options: TextureOptions) {
+        if texture_options != self.texture_options {
+            self.texture_options = texture_options;
+            self.invalidate_all();
+        }
     }
 
     fn calc_tile(&self, x: isize, y: isize) -> (isize, isize) {
         let texture_width = self.texture_width as isize;
-        (x / texture_width, y / texture_width)
+        // Round towards negative infinity, so e.g. -1 lands in tile -1 rather than 0
+        (x.div_euclid(texture_width), y.div_euclid(texture_width))
     }
 
-    pub fn notify_change(&mut self, x: isize, y: isize) {
-        let tile_pos = self.calc_tile(x, y);
+    /// Changes whenever a tile is written to or invalidated, so that whatever was worked
+    /// out from the image before can tell it may be out of date
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn notify_tile(&mut self, tile_pos: (isize, isize)) {
+        self.generation += 1;
         if let Some(tile) = self.tiles.get_mut(&tile_pos) {
-            tile.is_dirty = true;
+            tile.mark_dirty();
         }
+        self.changed_tiles.insert(tile_pos);
     }
 
-    pub fn draw<T: PixelInterface>(
+    /// Returns the tile-sized rectangles which have been written to (or invalidated with
+    /// `invalidate_rect`) since the last call, for renderers which upload the image
+    /// themselves. Rectangles may extend past the edges of the image.
+    pub fn take_dirty_rects(&mut self) -> Vec<(RangeInclusive<isize>, RangeInclusive<isize>)> {
+        let mut changed: Vec<_> = self.changed_tiles.drain().collect();
+        changed.sort();
+        let last = self.texture_width as isize - 1;
+        changed
+            .into_iter()
+            .map(|tile_pos| {
+                let (x, y) = self.tile_origin(tile_pos);
+                (x..=x + last, y..=y + last)
+            })
+            .collect()
+    }
+
+    /// Draws the tiles covering the image, placed and scaled on screen by `view`. With the
+    /// `parallel` feature, dirty tiles are sampled on background threads, and show what
+    /// they showed before until their patches arrive in a later pass; see [`MaybeSend`].
+    pub fn draw<T: PixelInterface + MaybeSend>(
+        &mut self,
+        ui: &mut Ui,
+        image: &impl Image<Pixel = T>,
+        view: ViewTransform,
+    ) {
+        self.draw_tinted(ui, image, view, Color32::WHITE);
+    }
+
+    /// Like [`Self::draw`], but multiplies every pixel by `tint`, e.g. a translucent color
+    /// to fade the image
+    pub fn draw_tinted<T: PixelInterface + MaybeSend>(
         &mut self,
         ui: &mut Ui,
-        image: &mut impl Image<Pixel = T>,
-        pos: Pos2,
+        image: &impl Image<Pixel = T>,
+        view: ViewTransform,
+        tint: Color32,
     ) {
+        self.fit_to_image(ui.ctx(), image);
         let (x_range, y_range) = image.image_boundaries();
         let texture_width = self.texture_width as isize;
 
         // Draw and dynamically load tiles as the image bounds change
-        for tile_y in y_range.start() / texture_width..=y_range.end() / texture_width {
-            let y = tile_y * texture_width;
-            for tile_x in x_range.start() / texture_width..=x_range.end() / texture_width {
-                let x = tile_x * texture_width;
-
-                let tile_rect =
-                    Rect::from_min_size(Pos2::new(x as _, y as _), Vec2::splat(texture_width as _));
-
-                let tile_rect = tile_rect.translate(pos.to_vec2());
-
-                let mut get_patch = || {
-                    let crop = image.crop(x..=x + texture_width - 1, y..=y + texture_width - 1);
-                    sample_patch(&crop, self.texture_width)
-                };
-
-                let tex_options = TextureOptions::NEAREST;
-
-                let tile = self.tiles.entry((tile_x, tile_y)).or_insert_with(|| {
-                    let tex_id = ui.ctx().tex_manager().write().alloc(
-                        format!("Tile {x}, {y}"),
-                        get_patch().into(),
-                        tex_options,
-                    );
-                    Tile::new(tex_id)
-                });
+        let (min_tile_x, min_tile_y) = self.calc_tile(*x_range.start(), *y_range.start());
+        let (max_tile_x, max_tile_y) = self.calc_tile(*x_range.end(), *y_range.end());
+        let tile_positions: Vec<_> = (min_tile_y..=max_tile_y)
+            .flat_map(|tile_y| (min_tile_x..=max_tile_x).map(move |tile_x| (tile_x, tile_y)))
+            .collect();
 
-                if tile.is_dirty {
-                    let patch = get_patch();
-                    ui.ctx()
-                        .tex_manager()
-                        .write()
-                        .set(tile.tex_id, ImageDelta::full(patch, tex_options));
-                    tile.is_dirty = false;
-                }
+        let dirty: Vec<_> = tile_positions
+            .iter()
+            .copied()
+            .filter(|&tile_pos| self.resident_tile(ui.ctx(), image, tile_pos).is_dirty)
+            .collect();
+        self.update_tiles(ui.ctx(), image, &dirty);
+
+        for (tile_x, tile_y) in tile_positions {
+            let (x, y) = (tile_x * texture_width, tile_y * texture_width);
+            let tile_rect = Rect::from_min_size(
+                view.to_screen((x, y)),
+                Vec2::splat(texture_width as f32 * view.zoom),
+            );
+            let uv = Rect::from_min_size(Pos2::ZERO, Vec2::splat(1.));
+            let tex_id = self.tiles[&(tile_x, tile_y)].tex_id;
+            ui.painter().image(tex_id, tile_rect, uv, tint);
+        }
+
+        let texels = self.texture_width * self.texture_width;
+        self.stats = TileStats {
+            resident: self.tiles.len(),
+            dirty: self.tiles.values().filter(|tile| tile.is_dirty).count(),
+            bytes_estimate: self.tiles.len() * texels * 4,
+            ..std::mem::take(&mut self.pending_stats)
+        };
+    }
 
-                let uv = Rect::from_min_size(Pos2::ZERO, Vec2::splat(1.));
-                ui.painter()
-                    .image(tile.tex_id, tile_rect, uv, Color32::WHITE);
+    /// Allocates textures for the tiles overlapping `rect` (in pixel coordinates) ahead of
+    /// time, e.g. just outside the viewport, so they don't all appear in the same frame.
+    /// Tiles which are already resident are left alone.
+    pub fn prefetch<T: PixelInterface>(
+        &mut self,
+        ctx: &egui::Context,
+        image: &impl Image<Pixel = T>,
+        rect: Rect,
+    ) {
+        self.fit_to_image(ctx, image);
+        let (x_range, y_range) = image.image_boundaries();
+        let min_x = (rect.min.x.floor() as isize).max(*x_range.start());
+        let min_y = (rect.min.y.floor() as isize).max(*y_range.start());
+        let max_x = (rect.max.x.ceil() as isize - 1).min(*x_range.end());
+        let max_y = (rect.max.y.ceil() as isize - 1).min(*y_range.end());
+        if min_x > max_x || min_y > max_y {
+            return;
+        }
+
+        let (min_tile_x, min_tile_y) = self.calc_tile(min_x, min_y);
+        let (max_tile_x, max_tile_y) = self.calc_tile(max_x, max_y);
+        for tile_y in min_tile_y..=max_tile_y {
+            for tile_x in min_tile_x..=max_tile_x {
+                self.resident_tile(ctx, image, (tile_x, tile_y));
             }
         }
     }
 
+    /// Switches to larger tiles if the image has outgrown them and they were sized by
+    /// [`Self::new`], freeing the textures of the old ones. Regions written to since the
+    /// last `take_dirty_rects` are carried over to the new tiles.
+    fn fit_to_image(&mut self, ctx: &egui::Context, image: &impl Image) {
+        let Some(max) = self.max_texture_width else {
+            return;
+        };
+        let (x_range, y_range) = image.image_boundaries();
+        let len = |range: RangeInclusive<isize>| (range.end() - range.start() + 1).max(0);
+        let dims = (len(x_range) as usize, len(y_range) as usize);
+        let texture_width = tile_size_for_image(max, dims);
+        if texture_width <= self.texture_width {
+            return;
+        }
+
+        let changed = self.take_dirty_rects();
+        let tex_manager = ctx.tex_manager();
+        for (_, tile) in self.tiles.drain() {
+            tex_manager.write().free(tile.tex_id);
+        }
+        self.texture_width = texture_width;
+        for (x_range, y_range) in changed {
+            self.invalidate_rect(x_range, y_range);
+        }
+    }
+
+    /// Returns the tile at `tile_pos`, allocating its texture if it isn't resident yet
+    fn resident_tile<T: PixelInterface>(
+        &mut self,
+        ctx: &egui::Context,
+        image: &impl Image<Pixel = T>,
+        tile_pos: (isize, isize),
+    ) -> &mut Tile {
+        if !self.tiles.contains_key(&tile_pos) {
+            let (x, y) = self.tile_origin(tile_pos);
+            let tex_id = ctx.tex_manager().write().alloc(
+                format!("Tile {x}, {y}"),
+                self.sample_tile(image, tile_pos).into(),
+                self.texture_options,
+            );
+            self.tiles.insert(tile_pos, Tile::new(tex_id));
+            self.pending_stats.allocated_this_frame += 1;
+        }
+        self.tiles.get_mut(&tile_pos).unwrap()
+    }
+
+    fn tile_origin(&self, (tile_x, tile_y): (isize, isize)) -> (isize, isize) {
+        let texture_width = self.texture_width as isize;
+        (tile_x * texture_width, tile_y * texture_width)
+    }
+
+    fn sample_tile<T: PixelInterface>(
+        &self,
+        image: &impl Image<Pixel = T>,
+        tile_pos: (isize, isize),
+    ) -> ColorImage {
+        sample_patch(image, self.tile_origin(tile_pos), self.texture_width)
+    }
+
+    /// Samples the dirty tiles at `dirty` again and uploads them
+    #[cfg(not(feature = "parallel"))]
+    fn update_tiles<T: PixelInterface>(
+        &mut self,
+        ctx: &egui::Context,
+        image: &impl Image<Pixel = T>,
+        dirty: &[(isize, isize)],
+    ) {
+        for &tile_pos in dirty {
+            let patch = self.sample_tile(image, tile_pos);
+            self.tiles.get_mut(&tile_pos).unwrap().is_dirty = false;
+            self.upload(ctx, tile_pos, patch);
+        }
+    }
+
+    /// Uploads the patches sampled in the background since the previous draw, then copies
+    /// the pixels of the dirty tiles at `dirty` which aren't being sampled yet, and sends
+    /// them off to be sampled
+    #[cfg(feature = "parallel")]
+    fn update_tiles<T: PixelInterface + MaybeSend>(
+        &mut self,
+        ctx: &egui::Context,
+        image: &impl Image<Pixel = T>,
+        dirty: &[(isize, isize)],
+    ) {
+        let arrived: Vec<_> = self.patches.1.try_iter().collect();
+        for (tile_pos, version, patch) in arrived {
+            let patch = patch.unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            // Patches for tiles dropped meanwhile, e.g. to grow them, are of no use
+            let texture_width = self.texture_width;
+            let Some(tile) = self.tiles.get_mut(&tile_pos) else {
+                continue;
+            };
+            if patch.size != [texture_width; 2] {
+                continue;
+            }
+            tile.sampling = false;
+            // Still newer than what the tile shows, even if it changed again since
+            tile.is_dirty = tile.version != version;
+            self.upload(ctx, tile_pos, patch);
+        }
+
+        for &tile_pos in dirty {
+            let origin = self.tile_origin(tile_pos);
+            let tile = self.tiles.get_mut(&tile_pos).unwrap();
+            if tile.sampling {
+                continue;
+            }
+            tile.sampling = true;
+            let snapshot = PatchSnapshot::new(image, origin, self.texture_width);
+            let (version, patches, ctx) = (tile.version, self.patches.0.clone(), ctx.clone());
+            let job = move || {
+                let patch = std::panic::catch_unwind(AssertUnwindSafe(|| snapshot.sample()));
+                // Nobody is waiting if the tiles were dropped meanwhile
+                if patches.send((tile_pos, version, patch)).is_ok() {
+                    ctx.request_repaint();
+                }
+            };
+            sampling_threads()
+                .send(Box::new(job))
+                .expect("the sampling threads never stop");
+        }
+    }
+
+    fn upload(&mut self, ctx: &egui::Context, tile_pos: (isize, isize), patch: ColorImage) {
+        let tex_id = self.tiles[&tile_pos].tex_id;
+        ctx.tex_manager()
+            .write()
+            .set(tex_id, ImageDelta::full(patch, self.texture_options));
+        self.pending_stats.dirty_uploaded += 1;
+    }
+
     pub fn track<'tiles, 'image, I: Image>(
         &'tiles mut self,
         image: &'image mut I,
     ) -> TileChangeTracker<'image, 'tiles, I> {
-        TileChangeTracker { image, tiles: self }
+        TileChangeTracker {
+            image,
+            tiles: self,
+            last_tile: None,
+        }
+    }
+
+    /// Waits for the patches being sampled in the background, so that the next draw
+    /// uploads all of them
+    #[cfg(test)]
+    pub(crate) fn wait_for_patches(&mut self) {
+        #[cfg(feature = "parallel")]
+        {
+            let sampling = self.tiles.values().filter(|tile| tile.sampling).count();
+            let arrived: Vec<_> = self.patches.1.iter().take(sampling).collect();
+            for patch in arrived {
+                self.patches.0.send(patch).unwrap();
+            }
+        }
     }
 
-    pub fn mark_all_dirty(&mut self) {
+    #[cfg(test)]
+    pub(crate) fn dirty_tiles(&self) -> Vec<(isize, isize)> {
+        let mut dirty: Vec<_> = self
+            .tiles
+            .iter()
+            .filter_map(|(pos, tile)| tile.is_dirty.then_some(*pos))
+            .collect();
+        dirty.sort();
+        dirty
+    }
+
+    /// Marks every tile as stale, so the next `draw` samples them all again.
+    /// For changes which didn't go through the change tracker, such as a new palette.
+    pub fn invalidate_all(&mut self) {
         // TODO: Dealloc here?
-        self.tiles.values_mut().for_each(|tile| tile.is_dirty = true);
+        self.generation += 1;
+        self.tiles.values_mut().for_each(Tile::mark_dirty);
+    }
+
+    /// Marks the tiles overlapping the given pixel ranges as stale in one call, e.g. after
+    /// writing a whole rectangle to the image behind the tracker's back. Writes through
+    /// [`Self::track`] mark each tile they touch already, batched or not.
+    pub fn invalidate_rect(
+        &mut self,
+        x_range: RangeInclusive<isize>,
+        y_range: RangeInclusive<isize>,
+    ) {
+        if x_range.is_empty() || y_range.is_empty() {
+            return;
+        }
+        let (min_tile_x, min_tile_y) = self.calc_tile(*x_range.start(), *y_range.start());
+        let (max_tile_x, max_tile_y) = self.calc_tile(*x_range.end(), *y_range.end());
+        for tile_y in min_tile_y..=max_tile_y {
+            for tile_x in min_tile_x..=max_tile_x {
+                self.notify_tile((tile_x, tile_y));
+            }
+        }
     }
 }
 
@@ -118,6 +454,18 @@ impl Tile {
         Self {
             tex_id,
             is_dirty: false,
+            #[cfg(feature = "parallel")]
+            version: 0,
+            #[cfg(feature = "parallel")]
+            sampling: false,
+        }
+    }
+
+    fn mark_dirty(&mut self) {
+        self.is_dirty = true;
+        #[cfg(feature = "parallel")]
+        {
+            self.version += 1;
         }
     }
 }
@@ -125,6 +473,16 @@ impl Tile {
 pub struct TileChangeTracker<'image, 'tiles, I: Image + ?Sized> {
     image: &'image mut I,
     tiles: &'tiles mut TiledEguiImage,
+    /// The most recently dirtied tile. Tiles are only cleaned by `draw`, which can't run
+    /// while we borrow them, so consecutive writes to this tile need no bookkeeping.
+    last_tile: Option<(isize, isize)>,
+}
+
+impl<I: Image + ?Sized> TileChangeTracker<'_, '_, I> {
+    /// The [`TiledEguiImage::generation`] of the tiles being tracked
+    pub(crate) fn generation(&self) -> u64 {
+        self.tiles.generation()
+    }
 }
 
 impl<I> Image for TileChangeTracker<'_, '_, I>
@@ -133,10 +491,53 @@ where
 {
     type Pixel = I::Pixel;
     fn set_pixel(&mut self, x: isize, y: isize, px: Self::Pixel) {
-        self.tiles.notify_change(x, y);
+        let tile_pos = self.tiles.calc_tile(x, y);
+        if self.last_tile != Some(tile_pos) {
+            self.tiles.notify_tile(tile_pos);
+            self.last_tile = Some(tile_pos);
+        }
         self.image.set_pixel(x, y, px);
     }
 
+    fn set_pixels(&mut self, pixels: &mut dyn Iterator<Item = (isize, isize, Self::Pixel)>) {
+        // Collect the tiles first, then mark each of them once
+        let tiles = &*self.tiles;
+        let mut last_tile = self.last_tile;
+        let mut touched = HashSet::new();
+        let mut pixels = pixels.inspect(|&(x, y, _)| {
+            let tile_pos = tiles.calc_tile(x, y);
+            if last_tile != Some(tile_pos) {
+                touched.insert(tile_pos);
+                last_tile = Some(tile_pos);
+            }
+        });
+        self.image.set_pixels(&mut pixels);
+
+        for tile_pos in touched {
+            self.tiles.notify_tile(tile_pos);
+        }
+        self.last_tile = last_tile;
+    }
+
+    fn fill_row(&mut self, y: isize, x_range: RangeInclusive<isize>, px: Self::Pixel)
+    where
+        Self::Pixel: Clone,
+    {
+        if x_range.is_empty() {
+            return;
+        }
+        // Marks each tile the run crosses
+        let (first_x, tile_y) = self.tiles.calc_tile(*x_range.start(), y);
+        let (last_x, _) = self.tiles.calc_tile(*x_range.end(), y);
+        for tile_x in first_x..=last_x {
+            if self.last_tile != Some((tile_x, tile_y)) {
+                self.tiles.notify_tile((tile_x, tile_y));
+            }
+        }
+        self.last_tile = Some((last_x, tile_y));
+        self.image.fill_row(y, x_range, px);
+    }
+
     fn get_pixel(&self, x: isize, y: isize) -> Self::Pixel {
         self.image.get_pixel(x, y)
     }
@@ -144,29 +545,411 @@ where
     fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
         self.image.image_boundaries()
     }
+
+    fn set_pixel_out_of_bounds(&self) -> bool {
+        self.image.set_pixel_out_of_bounds()
+    }
 }
 
-fn sample_patch<T: PixelInterface>(
-    source: &impl Image<Pixel = T>,
+/// The `texture_width` square of pixels with its top left corner at `(x, y)`, padded with
+/// transparency where it extends past the image
+fn sample_patch<I: Image + ?Sized>(
+    source: &I,
+    origin: (isize, isize),
     texture_width: usize,
-) -> ColorImage {
-    let (x_range, y_range) = source.image_boundaries();
-    let mut pixels = vec![];
-
-    for y in 0..texture_width as isize {
-        let y = y + y_range.start();
-        for x in 0..texture_width as isize {
-            let x = x + x_range.start();
-            let color = match source.get_pixel_checked(x, y) {
-                Some(px) => px.as_rgba(),
-                None => Color32::TRANSPARENT,
-            };
-            pixels.push(color);
+) -> ColorImage
+where
+    I::Pixel: PixelInterface,
+{
+    PatchSnapshot::new(source, origin, texture_width).sample()
+}
+
+/// The pixels of a patch, copied out of the image so that they can be sampled without it,
+/// e.g. on another thread
+struct PatchSnapshot<Pixel> {
+    texture_width: usize,
+    /// The span of each row inside the image, one after the other
+    pixels: Vec<Pixel>,
+    /// Where each span starts in the patch, and how long it is
+    spans: Vec<(usize, usize)>,
+}
+
+impl<Pixel: PixelInterface> PatchSnapshot<Pixel> {
+    fn new<I: Image<Pixel = Pixel> + ?Sized>(
+        source: &I,
+        (x, y): (isize, isize),
+        texture_width: usize,
+    ) -> Self {
+        let last = texture_width as isize - 1;
+        let (x_range, y_range) = source.image_boundaries();
+        let mut snapshot = Self {
+            texture_width,
+            pixels: vec![],
+            spans: vec![],
+        };
+
+        // Each row's span inside the image is resolved in one go. Past the image is
+        // transparent, even for images which could grow there.
+        for (j, py) in (y..=y + last).enumerate() {
+            if !y_range.contains(&py) {
+                continue;
+            }
+            let (start, end) = (x.max(*x_range.start()), (x + last).min(*x_range.end()));
+            if start > end {
+                continue;
+            }
+            let offset = j * texture_width + (start - x) as usize;
+            snapshot.spans.push((offset, (end - start + 1) as usize));
+            (start..=end).for_each(|px| snapshot.pixels.push(source.get_pixel(px, py)));
+        }
+        snapshot
+    }
+
+    fn sample(&self) -> ColorImage {
+        let texture_width = self.texture_width;
+        let mut pixels = vec![Color32::TRANSPARENT; texture_width * texture_width];
+        let mut rows = &self.pixels[..];
+        for &(offset, len) in &self.spans {
+            let (row, rest) = rows.split_at(len);
+            Pixel::as_rgba_row(row, &mut pixels[offset..offset + len]);
+            rows = rest;
+        }
+        ColorImage {
+            size: [texture_width; 2],
+            pixels,
+        }
+    }
+}
+
+/// Where to send work for the threads which sample tiles in the background with the
+/// `parallel` feature. They are started by the first draw which needs them, one per core,
+/// and shared by every [`TiledEguiImage`].
+#[cfg(feature = "parallel")]
+fn sampling_threads() -> &'static mpsc::Sender<Box<dyn FnOnce() + Send>> {
+    static JOBS: OnceLock<mpsc::Sender<Box<dyn FnOnce() + Send>>> = OnceLock::new();
+    JOBS.get_or_init(|| {
+        let (jobs, queue) = mpsc::channel::<Box<dyn FnOnce() + Send>>();
+        let queue = Arc::new(Mutex::new(queue));
+        for i in 0..thread::available_parallelism().map_or(1, |n| n.get()) {
+            let queue = queue.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("tile sampler {i}"))
+                .spawn(move || loop {
+                    // Only locked while waiting for a job, not while doing it
+                    let job = queue.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                });
+            spawned.expect("failed to start a tile sampling thread");
+        }
+        jobs
+    })
+}
+
+/// The largest tiles to use, which are never larger than the backend allows
+fn max_tile_size(ctx: &egui::Context) -> usize {
+    const MAX_TEXTURE_SIZE: usize = 512;
+    ctx.fonts(|r| r.max_texture_side()).min(MAX_TEXTURE_SIZE)
+}
+
+/// The smallest power of two which covers the longer side of the image, up to `max`
+fn tile_size_for_image(max: usize, (width, height): (usize, usize)) -> usize {
+    const MIN_TEXTURE_SIZE: usize = 16;
+    width
+        .max(height)
+        .next_power_of_two()
+        .clamp(MIN_TEXTURE_SIZE, max.max(MIN_TEXTURE_SIZE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::ResizableColorImage;
+
+    #[test]
+    fn test_sample_patch_pads_with_transparent() {
+        let mut image = ColorImage::new([3, 3], Color32::RED);
+        image.set_pixel(2, 1, Color32::BLUE);
+        let patch = sample_patch(&image, (0, 0), 4);
+
+        assert_eq!(patch.size, [4, 4]);
+        assert_eq!(patch.pixels[2 + 4], Color32::BLUE);
+        for i in 0..4 {
+            assert_eq!(patch.pixels[3 + i * 4], Color32::TRANSPARENT);
+            assert_eq!(patch.pixels[i + 3 * 4], Color32::TRANSPARENT);
+        }
+        assert_eq!(patch.pixels[0], Color32::RED);
+
+        // Also past images which could grow there, rather than showing what they'd grow with
+        let image = ResizableColorImage::new(image, Color32::GREEN);
+        assert_eq!(sample_patch(&image, (0, 0), 4), patch);
+    }
+
+    #[test]
+    fn test_sample_patch_by_rows() {
+        /// Colors its pixels by their position within each row it is asked for
+        #[derive(Clone)]
+        struct RowPixel;
+        impl PixelInterface for RowPixel {
+            fn as_rgba(&self) -> Color32 {
+                Color32::RED
+            }
+
+            fn as_rgba_row(pixels: &[Self], out: &mut [Color32]) {
+                assert_eq!(pixels.len(), out.len());
+                for (i, out) in out.iter_mut().enumerate() {
+                    *out = Color32::from_gray(i as u8 + 1);
+                }
+            }
+        }
+
+        let image = crate::image::Buffer2D::new(3, 2, RowPixel);
+        let patch = sample_patch(&image, (-1, 0), 4);
+        let gray = Color32::from_gray;
+        let clear = Color32::TRANSPARENT;
+        assert_eq!(patch.pixels[..4], [clear, gray(1), gray(2), gray(3)]);
+        assert_eq!(patch.pixels[8..], [clear; 8]);
+    }
+
+    #[test]
+    fn test_tile_size_for_image() {
+        assert_eq!(tile_size_for_image(512, (32, 32)), 32);
+        assert_eq!(tile_size_for_image(512, (20, 3)), 32);
+        assert_eq!(tile_size_for_image(512, (1, 1)), 16);
+        assert_eq!(tile_size_for_image(512, (1000, 200)), 512);
+        assert_eq!(tile_size_for_image(256, (300, 300)), 256);
+    }
+
+    #[test]
+    fn test_prefetch() {
+        let ctx = egui::Context::default();
+        let image = ColorImage::new([24, 8], Color32::RED);
+        let mut tiles = TiledEguiImage::from_tile_size(8);
+
+        // Only overlaps the middle tile; the part above the image is ignored
+        let rect = Rect::from_min_max(Pos2::new(9.5, -4.), Pos2::new(15.5, 4.));
+        tiles.prefetch(&ctx, &image, rect);
+        let tex_id = tiles.tiles[&(1, 0)].tex_id;
+        assert_eq!(tiles.tiles.keys().collect::<Vec<_>>(), [&(1, 0)]);
+
+        tiles.prefetch(&ctx, &image, rect);
+        assert_eq!(tiles.tiles.len(), 1);
+        assert_eq!(tiles.tiles[&(1, 0)].tex_id, tex_id);
+        assert_eq!(tiles.dirty_tiles(), []);
+    }
+
+    #[test]
+    fn test_tiles_grow_with_image() {
+        let ctx = egui::Context::default();
+        let red = ColorImage::new([20, 10], Color32::RED);
+        let mut image = ResizableColorImage::new(red, Color32::RED);
+        let mut tiles = TiledEguiImage {
+            max_texture_width: Some(64),
+            ..TiledEguiImage::from_tile_size(16)
+        };
+
+        tiles.prefetch(&ctx, &image, Rect::EVERYTHING);
+        assert_eq!((tiles.texture_width, tiles.tiles.len()), (32, 1));
+
+        // Written past the old tile, then outgrowing it
+        tiles.track(&mut image).set_pixel(40, 0, Color32::BLUE);
+        tiles.prefetch(&ctx, &image, Rect::EVERYTHING);
+        assert_eq!((tiles.texture_width, tiles.tiles.len()), (64, 1));
+        assert_eq!(tiles.take_dirty_rects(), [(0..=63, 0..=63)]);
+
+        // Capped from then on, and never shrinking
+        image.grow_to(200, 10, Color32::RED);
+        tiles.prefetch(&ctx, &image, Rect::EVERYTHING);
+        assert_eq!((tiles.texture_width, tiles.tiles.len()), (64, 4));
+        image.image = ColorImage::new([4, 4], Color32::RED);
+        tiles.prefetch(&ctx, &image, Rect::EVERYTHING);
+        assert_eq!(tiles.texture_width, 64);
+
+        // Fixed-size tiles stay as they are
+        let mut fixed = TiledEguiImage::from_tile_size(8);
+        fixed.prefetch(&ctx, &image, Rect::EVERYTHING);
+        assert_eq!(fixed.texture_width, 8);
+    }
+
+    #[test]
+    fn test_set_texture_options() {
+        let ctx = egui::Context::default();
+        let image = ColorImage::new([16, 8], Color32::RED);
+        let mut tiles = TiledEguiImage::from_tile_size(8);
+        tiles.prefetch(&ctx, &image, Rect::EVERYTHING);
+
+        tiles.set_texture_options(TextureOptions::NEAREST);
+        assert_eq!(tiles.dirty_tiles(), []);
+
+        let options = TextureOptions {
+            minification: egui::TextureFilter::Linear,
+            ..TextureOptions::NEAREST
+        };
+        tiles.set_texture_options(options);
+        assert_eq!(tiles.texture_options(), options);
+        assert_eq!(tiles.dirty_tiles(), [(0, 0), (1, 0)]);
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let ctx = egui::Context::default();
+        let image = ColorImage::new([24, 16], Color32::RED);
+        let mut tiles = TiledEguiImage::from_tile_size(8);
+        tiles.prefetch(&ctx, &image, Rect::EVERYTHING);
+        assert_eq!(tiles.dirty_tiles(), []);
+
+        tiles.invalidate_rect(7..=8, 3..=3);
+        assert_eq!(tiles.dirty_tiles(), [(0, 0), (1, 0)]);
+
+        tiles.invalidate_all();
+        assert_eq!(tiles.dirty_tiles().len(), 6);
+    }
+
+    #[test]
+    fn test_take_dirty_rects() {
+        let mut image = ColorImage::new([24, 8], Color32::RED);
+        let mut tiles = TiledEguiImage::from_tile_size(8);
+
+        // Reported even though no tiles have been drawn
+        let mut tracked = tiles.track(&mut image);
+        tracked.set_pixel(20, 0, Color32::BLUE);
+        tracked.set_pixel(1, 7, Color32::BLUE);
+        tracked.set_pixel(2, 7, Color32::BLUE);
+        assert_eq!(tiles.take_dirty_rects(), [(0..=7, 0..=7), (16..=23, 0..=7)]);
+        assert_eq!(tiles.take_dirty_rects(), []);
+    }
+
+    #[test]
+    fn test_set_pixels_marks_each_tile() {
+        let ctx = egui::Context::default();
+        let mut image = ColorImage::new([24, 16], Color32::RED);
+        let mut tiles = TiledEguiImage::from_tile_size(8);
+        tiles.prefetch(&ctx, &image, Rect::EVERYTHING);
+
+        let mut pixels = (6..18).map(|x| (x, 9, Color32::BLUE));
+        tiles.track(&mut image).set_pixels(&mut pixels);
+        assert_eq!(tiles.dirty_tiles(), [(0, 1), (1, 1), (2, 1)]);
+        assert_eq!(image.pixels[17 + 9 * 24], Color32::BLUE);
+        assert_eq!(image.pixels[18 + 9 * 24], Color32::RED);
+    }
+
+    #[test]
+    fn test_sample_tile_from_its_origin() {
+        let mut image = ColorImage::new([10, 10], Color32::BLACK);
+        for x in 0..10 {
+            image.set_pixel(x, 0, Color32::from_gray(x as u8));
+        }
+        let tiles = TiledEguiImage::from_tile_size(8);
+
+        let patch = tiles.sample_tile(&image, (1, 0));
+        assert_eq!(patch.pixels[0], Color32::from_gray(8));
+        assert_eq!(patch.pixels[1], Color32::from_gray(9));
+        assert_eq!(patch.pixels[2], Color32::TRANSPARENT);
+
+        // A tile hanging off the start of the image is padded before the image, not after
+        let image = image.translate(3, 0);
+        let patch = tiles.sample_tile(&image, (-1, 0));
+        assert_eq!(patch.pixels[4], Color32::TRANSPARENT);
+        assert_eq!(patch.pixels[5], Color32::from_gray(0));
+        assert_eq!(patch.pixels[7], Color32::from_gray(2));
+    }
+
+    #[test]
+    fn test_negative_coordinates() {
+        let ctx = egui::Context::default();
+        let mut buffer = ColorImage::new([16, 16], Color32::RED);
+        let mut image = buffer.translate(3, 3);
+        assert_eq!(image.image_boundaries(), (-3..=12, -3..=12));
+        let mut tiles = TiledEguiImage::from_tile_size(8);
+        tiles.prefetch(&ctx, &image, Rect::EVERYTHING);
+        assert_eq!(tiles.stats().resident, 0);
+        assert_eq!(tiles.tiles.len(), 9);
+
+        tiles.track(&mut image).set_pixel(-1, -3, Color32::BLUE);
+        assert_eq!(tiles.dirty_tiles(), [(-1, -1)]);
+        tiles.track(&mut image).set_pixel(0, 0, Color32::BLUE);
+        assert_eq!(tiles.dirty_tiles(), [(-1, -1), (0, 0)]);
+    }
+
+    #[test]
+    fn test_stats() {
+        let ctx = egui::Context::default();
+        let mut image = ColorImage::new([24, 8], Color32::RED);
+        let mut tiles = TiledEguiImage::from_tile_size(8);
+        let draw = |tiles: &mut TiledEguiImage, image: &mut ColorImage| {
+            let _ = ctx.run(Default::default(), |ctx| {
+                egui::CentralPanel::default()
+                    .show(ctx, |ui| tiles.draw(ui, image, ViewTransform::default()));
+            });
+        };
+
+        draw(&mut tiles, &mut image);
+        let expected = TileStats {
+            resident: 3,
+            dirty_uploaded: 0,
+            allocated_this_frame: 3,
+            dirty: 0,
+            bytes_estimate: 3 * 8 * 8 * 4,
+        };
+        assert_eq!(tiles.stats(), expected);
+
+        tiles.track(&mut image).set_pixel(20, 0, Color32::BLUE);
+        draw(&mut tiles, &mut image);
+        // With the `parallel` feature, dirty tiles are uploaded by the first draw after
+        // their patches arrive
+        if cfg!(feature = "parallel") {
+            tiles.wait_for_patches();
+            draw(&mut tiles, &mut image);
+        }
+        let expected = TileStats {
+            resident: 3,
+            dirty_uploaded: 1,
+            allocated_this_frame: 0,
+            dirty: 0,
+            bytes_estimate: 3 * 8 * 8 * 4,
+        };
+        assert_eq!(tiles.stats(), expected);
+
+        // Only the first tile is drawn, so the other two stay dirty
+        tiles.invalidate_all();
+        let passes = if cfg!(feature = "parallel") { 2 } else { 1 };
+        for _ in 0..passes {
+            tiles.wait_for_patches();
+            let _ = ctx.run(Default::default(), |ctx| {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    tiles.draw(ui, &image.crop(0..=7, 0..=7), ViewTransform::default())
+                });
+            });
         }
+        assert_eq!((tiles.stats().dirty_uploaded, tiles.stats().dirty), (1, 2));
     }
 
-    ColorImage {
-        size: [texture_width as usize; 2],
-        pixels,
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_sample_in_background() {
+        let ctx = egui::Context::default();
+        let mut image = ColorImage::new([24, 8], Color32::RED);
+        let mut tiles = TiledEguiImage::from_tile_size(8);
+        let draw = |tiles: &mut TiledEguiImage, image: &mut ColorImage| {
+            let _ = ctx.run(Default::default(), |ctx| {
+                egui::CentralPanel::default()
+                    .show(ctx, |ui| tiles.draw(ui, image, ViewTransform::default()));
+            });
+            (tiles.stats().dirty_uploaded, tiles.dirty_tiles())
+        };
+        draw(&mut tiles, &mut image);
+
+        // The old texture is drawn until the patch arrives
+        tiles.track(&mut image).set_pixel(20, 0, Color32::BLUE);
+        assert_eq!(draw(&mut tiles, &mut image), (0, vec![(2, 0)]));
+
+        // A patch sampled before the latest change is uploaded, but leaves the tile dirty
+        tiles.track(&mut image).set_pixel(21, 0, Color32::BLUE);
+        tiles.wait_for_patches();
+        assert_eq!(draw(&mut tiles, &mut image), (1, vec![(2, 0)]));
+        tiles.wait_for_patches();
+        assert_eq!(draw(&mut tiles, &mut image), (1, vec![]));
     }
 }
@@ -0,0 +1,237 @@
+use egui::{Color32, CursorIcon, Painter, Rect, Vec2};
+
+use crate::{
+    brush::{self, CompiledBrush},
+    fill::{self, Dither},
+    image::{Buffer2D, Image, ImageExt, PixelInterface},
+    raster, Brush, ViewTransform,
+};
+
+/// What pressing and dragging on the canvas does
+#[derive(Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Tool<Pixel> {
+    /// Stamps the brush wherever the pointer is held down
+    Brush(Brush),
+    /// Drag from one corner to the other to draw an unfilled rectangle
+    RectOutline,
+    /// Drag from one corner to the other to draw an unfilled ellipse inside that rectangle
+    EllipseOutline,
+    /// Drag from one end to the other to draw a straight line stamped with the brush.
+    /// Hold shift to snap to multiples of 45 degrees.
+    Line(Brush),
+    /// Drag from one point to another to fill the selection (or the whole image) with a
+    /// linear gradient, from the draw color at the start of the drag to `end` at the end,
+    /// optionally dithered. Colors are blended by [`PixelInterface::lerp`]; for `Color32`
+    /// that is in premultiplied alpha, so fading to transparent doesn't darken the color.
+    Gradient { end: Pixel, dither: Dither },
+    /// Drag to smear the image, carrying the pixels under the brush along with the pointer.
+    /// Each step blends the carried pixels into the ones underneath by `strength`, from
+    /// 0 (no effect) to 1 (replace them). Blending uses [`PixelInterface::lerp`].
+    Smudge { brush: Brush, strength: f32 },
+    /// Stamps the small image in [`crate::ImageEditor::stamp`] wherever the pointer is held
+    /// down, centered (rounding down) on the pointer, instead of the draw color. `None`
+    /// pixels are left untouched. Raise [`crate::ImageEditor::brush_spacing`] to the
+    /// stamp's width to tile it.
+    Stamp,
+    /// Click to flood fill the area of similar color under the pointer, like the bucket of
+    /// other editors. Pixels join the area if they touch it along an edge and their
+    /// displayed color is within `tolerance` of the clicked one in every channel.
+    /// Hovering previews the area to be filled.
+    Fill { tolerance: u8 },
+    /// Click to pick the color under the pointer. Writes nothing; read it from
+    /// [`crate::EditResponse::hover`] when the canvas is clicked.
+    Eyedropper,
+}
+
+impl<Pixel> Tool<Pixel> {
+    /// What to call an edit made with this tool, e.g. in [`crate::SparseImageUndoer::history`]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tool::Brush(_) => "Brush stroke",
+            Tool::RectOutline => "Rectangle",
+            Tool::EllipseOutline => "Ellipse",
+            Tool::Line(_) => "Line",
+            Tool::Gradient { .. } => "Gradient",
+            Tool::Smudge { .. } => "Smudge",
+            Tool::Stamp => "Stamp",
+            Tool::Fill { .. } => "Fill",
+            Tool::Eyedropper => "Eyedropper",
+        }
+    }
+
+    /// The same tool with the sizes of its brushes limited to `max`; see [`Brush::clamped`]
+    pub(crate) fn clamp_brushes(self, max: isize) -> Self {
+        match self {
+            Tool::Brush(brush) => Tool::Brush(brush.clamped(max)),
+            Tool::Line(brush) => Tool::Line(brush.clamped(max)),
+            Tool::Smudge { brush, strength } => Tool::Smudge {
+                brush: brush.clamped(max),
+                strength,
+            },
+            tool => tool,
+        }
+    }
+
+    /// Whether this tool draws a shape between the start and end of a drag
+    pub(crate) fn is_shape(&self) -> bool {
+        !matches!(
+            self,
+            Tool::Brush(_)
+                | Tool::Smudge { .. }
+                | Tool::Stamp
+                | Tool::Fill { .. }
+                | Tool::Eyedropper
+        )
+    }
+
+    /// The pointer icon to show over the canvas. Tools which paint with a brush keep the
+    /// default arrow, since their footprint is outlined under it.
+    pub fn cursor_icon(&self) -> CursorIcon {
+        match self {
+            Tool::Brush(_) | Tool::Smudge { .. } | Tool::Stamp => CursorIcon::Default,
+            Tool::Fill { .. } => CursorIcon::Cell,
+            _ => CursorIcon::Crosshair,
+        }
+    }
+
+    /// Outlines what the tool would affect at `pixel`: the brush footprint for most
+    /// tools, a plus for fill and a crosshair around the picked pixel for the eyedropper
+    pub(crate) fn draw_hover(
+        &self,
+        painter: &Painter,
+        view: ViewTransform,
+        pixel: (isize, isize),
+        stamp: &Buffer2D<Option<Pixel>>,
+    ) {
+        let corner = view.to_screen(pixel);
+        let stroke = brush::outline_stroke(view.zoom);
+        // Long enough to see at any zoom
+        let arm = view.zoom.max(6.0);
+        let center = corner + Vec2::splat(0.5 * view.zoom);
+        let lines = |from: f32, to: f32| {
+            for dir in [Vec2::X, -Vec2::X, Vec2::Y, -Vec2::Y] {
+                painter.line_segment([center + dir * from, center + dir * to], stroke);
+            }
+        };
+        match self {
+            Tool::Fill { .. } => lines(0.0, arm),
+            Tool::Eyedropper => {
+                Brush::default().draw_zoomed(painter, corner, view.zoom);
+                let edge = 0.5 * view.zoom;
+                lines(edge, edge + arm);
+            }
+            Tool::Stamp => brush::draw_stamp(painter, corner, view.zoom, stamp),
+            _ => self.hover_brush().draw_zoomed(painter, corner, view.zoom),
+        }
+    }
+
+    /// The footprint to outline under the pointer
+    fn hover_brush(&self) -> Brush {
+        match self {
+            Tool::Brush(brush) | Tool::Line(brush) | Tool::Smudge { brush, .. } => *brush,
+            _ => Brush::default(),
+        }
+    }
+
+    /// Calls `f` on each pixel of the shape dragged out from `a` to `b`
+    pub(crate) fn shape_pixels(
+        &self,
+        a: (isize, isize),
+        b: (isize, isize),
+        f: impl FnMut(isize, isize),
+    ) {
+        match self {
+            Tool::Brush(_)
+            | Tool::Gradient { .. }
+            | Tool::Smudge { .. }
+            | Tool::Stamp
+            | Tool::Fill { .. }
+            | Tool::Eyedropper => (),
+            Tool::RectOutline => raster::rect_outline(a, b, f),
+            Tool::EllipseOutline => raster::ellipse_outline(a, b, f),
+            Tool::Line(brush) => {
+                let (brush, mut f) = (CompiledBrush::new(*brush), f);
+                raster::line(a, b, |x, y| brush.pixels(x, y, &mut f))
+            }
+        }
+    }
+
+    /// Writes the shape dragged out from `a` to `b` to the image
+    pub(crate) fn draw_shape(
+        &self,
+        image: &mut dyn Image<Pixel = Pixel>,
+        a: (isize, isize),
+        b: (isize, isize),
+        color: Pixel,
+    ) where
+        Pixel: PixelInterface + Clone,
+    {
+        match self {
+            Tool::Gradient { end, dither } => {
+                fill::linear_gradient(image, a, b, &color, end, *dither)
+            }
+            _ => self.shape_pixels(a, b, |x, y| {
+                image.set_pixel_checked(x, y, color.clone());
+            }),
+        }
+    }
+
+    /// Draws the uncommitted shape dragged out from `a` to `b`
+    pub(crate) fn draw_preview(
+        &self,
+        painter: &Painter,
+        view: ViewTransform,
+        a: (isize, isize),
+        b: (isize, isize),
+        color: Color32,
+    ) where
+        Pixel: PixelInterface,
+    {
+        let stroke = brush::outline_stroke(view.zoom);
+        let center = |p| view.to_screen(p) + Vec2::splat(0.5 * view.zoom);
+        match self {
+            Tool::Line(brush) => {
+                brush.draw_zoomed(painter, view.to_screen(a), view.zoom);
+                brush.draw_zoomed(painter, view.to_screen(b), view.zoom);
+                painter.line_segment([center(a), center(b)], stroke);
+            }
+            Tool::Gradient { end, .. } => {
+                // The axis, with each end marked by its color
+                painter.line_segment([center(a), center(b)], stroke);
+                for (p, color) in [(a, color), (b, end.as_rgba())] {
+                    painter.circle(center(p), 0.5 * view.zoom, color, stroke);
+                }
+            }
+            _ => self.shape_pixels(a, b, |x, y| {
+                let rect = Rect::from_min_size(view.to_screen((x, y)), Vec2::splat(view.zoom));
+                painter.rect_filled(rect, 0.0, color);
+            }),
+        }
+    }
+
+    /// Adjusts the end of a drag according to the held modifiers
+    pub(crate) fn constrain(
+        &self,
+        a: (isize, isize),
+        b: (isize, isize),
+        shift: bool,
+    ) -> (isize, isize) {
+        match self {
+            Tool::Line(_) if shift => raster::snap_45(a, b),
+            _ => b,
+        }
+    }
+}
+
+impl<Pixel> From<Brush> for Tool<Pixel> {
+    fn from(brush: Brush) -> Self {
+        Self::Brush(brush)
+    }
+}
+
+impl<Pixel> Default for Tool<Pixel> {
+    fn default() -> Self {
+        Self::Brush(Brush::default())
+    }
+}
@@ -0,0 +1,302 @@
+use egui::ColorImage;
+
+use crate::image::{Image, ImageExt};
+
+/// Mirrors the image left to right in place. For odd widths the middle column stays put.
+pub fn flip_horizontal<I: Image + ?Sized>(image: &mut I) {
+    let (x_range, y_range) = image.image_boundaries();
+    let (x0, x1) = (*x_range.start(), *x_range.end());
+    for y in y_range {
+        for dx in 0..(x1 - x0 + 1) / 2 {
+            swap_pixels(image, (x0 + dx, y), (x1 - dx, y));
+        }
+    }
+}
+
+/// Mirrors the image top to bottom in place. For odd heights the middle row stays put.
+pub fn flip_vertical<I: Image + ?Sized>(image: &mut I) {
+    let (x_range, y_range) = image.image_boundaries();
+    let (y0, y1) = (*y_range.start(), *y_range.end());
+    for dy in 0..(y1 - y0 + 1) / 2 {
+        for x in x_range.clone() {
+            swap_pixels(image, (x, y0 + dy), (x, y1 - dy));
+        }
+    }
+}
+
+/// Rotates the image by 90 degrees clockwise, in place, keeping its top left corner put.
+/// Returns whether it was rotated.
+///
+/// Rotating a non-square image changes its dimensions, so this only supports them if the
+/// image can grow to fit (see [`Image::set_pixel_out_of_bounds`]); since images can't
+/// shrink, the pixels of the old area left outside of the rotated one are set to
+/// `Pixel::default()`. Other non-square images are left as they were; use [`rotated_cw`]
+/// to build a new [`ColorImage`] instead.
+pub fn rotate_cw<I: Image + ?Sized>(image: &mut I) -> bool
+where
+    I::Pixel: Default,
+{
+    rotate(image, true)
+}
+
+/// Rotates the image by 90 degrees counter-clockwise, in place. Returns whether it was
+/// rotated.
+///
+/// See [`rotate_cw`] for non-square images.
+pub fn rotate_ccw<I: Image + ?Sized>(image: &mut I) -> bool
+where
+    I::Pixel: Default,
+{
+    rotate(image, false)
+}
+
+/// Returns a copy of `image` rotated by 90 degrees clockwise. Works for any dimensions.
+pub fn rotated_cw(image: &ColorImage) -> ColorImage {
+    let [width, height] = image.size;
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..width {
+        for x in 0..height {
+            pixels.push(image.pixels[y + (height - 1 - x) * width]);
+        }
+    }
+    ColorImage {
+        size: [height, width],
+        pixels,
+    }
+}
+
+/// Returns a copy of `image` rotated by 90 degrees counter-clockwise. Works for any dimensions.
+pub fn rotated_ccw(image: &ColorImage) -> ColorImage {
+    let [width, height] = image.size;
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..width {
+        for x in 0..height {
+            pixels.push(image.pixels[(width - 1 - y) + x * width]);
+        }
+    }
+    ColorImage {
+        size: [height, width],
+        pixels,
+    }
+}
+
+fn rotate<I: Image + ?Sized>(image: &mut I, clockwise: bool) -> bool
+where
+    I::Pixel: Default,
+{
+    let (x_range, y_range) = image.image_boundaries();
+    let (x0, y0) = (*x_range.start(), *y_range.start());
+    let width = x_range.end() - x0 + 1;
+    let height = y_range.end() - y0 + 1;
+    if width == height {
+        rotate_square(image, (x0, y0), width, clockwise);
+        return true;
+    }
+    if !image.set_pixel_out_of_bounds() {
+        return false;
+    }
+
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    image.for_each_in_rect(x_range, y_range, |x, y, px| {
+        pixels.push((x - x0, y - y0, px.unwrap()));
+    });
+    // The rotated image is `height` wide and `width` tall, so clear whatever of the old
+    // area it doesn't cover
+    for &(i, j, _) in &pixels {
+        if i >= height || j >= width {
+            image.set_pixel(x0 + i, y0 + j, I::Pixel::default());
+        }
+    }
+    for (i, j, px) in pixels {
+        let (i, j) = if clockwise {
+            (height - 1 - j, i)
+        } else {
+            (j, width - 1 - i)
+        };
+        image.set_pixel_checked(x0 + i, y0 + j, px);
+    }
+    true
+}
+
+/// Rotates the `n` by `n` square with its top left corner at `(x0, y0)`
+fn rotate_square<I: Image + ?Sized>(
+    image: &mut I,
+    (x0, y0): (isize, isize),
+    n: isize,
+    clockwise: bool,
+) {
+    // Each pixel belongs to a cycle of four positions which trade places
+    let at = |i: isize, j: isize| (x0 + i, y0 + j);
+    for i in 0..n / 2 {
+        for j in 0..(n + 1) / 2 {
+            let mut cycle = [
+                at(i, j),
+                at(n - 1 - j, i),
+                at(n - 1 - i, n - 1 - j),
+                at(j, n - 1 - i),
+            ];
+            if !clockwise {
+                cycle.reverse();
+            }
+            let values = cycle.map(|(x, y)| image.get_pixel(x, y));
+            for (k, px) in values.into_iter().enumerate() {
+                let (x, y) = cycle[(k + 1) % 4];
+                image.set_pixel(x, y, px);
+            }
+        }
+    }
+}
+
+fn swap_pixels<I: Image + ?Sized>(
+    image: &mut I,
+    (ax, ay): (isize, isize),
+    (bx, by): (isize, isize),
+) {
+    let a = image.get_pixel(ax, ay);
+    let b = image.get_pixel(bx, by);
+    image.set_pixel(ax, ay, b);
+    image.set_pixel(bx, by, a);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{image::tests::Sparse, undo::SparseImageUndoer};
+    use egui::{Color32, ColorImage};
+
+    /// 3x2 image with distinct pixels:
+    /// ```text
+    /// 0 1 2
+    /// 3 4 5
+    /// ```
+    fn distinct() -> ColorImage {
+        ColorImage {
+            size: [3, 2],
+            pixels: (0..6).map(Color32::from_gray).collect(),
+        }
+    }
+
+    fn grays(image: &ColorImage) -> Vec<u8> {
+        image.pixels.iter().map(|px| px.r()).collect()
+    }
+
+    #[test]
+    fn test_flip_horizontal() {
+        let mut image = distinct();
+        flip_horizontal(&mut image);
+        assert_eq!(grays(&image), [2, 1, 0, 5, 4, 3]);
+    }
+
+    #[test]
+    fn test_flip_vertical() {
+        let mut image = distinct();
+        flip_vertical(&mut image);
+        assert_eq!(grays(&image), [3, 4, 5, 0, 1, 2]);
+    }
+
+    #[test]
+    fn test_rotate_cw_four_times() {
+        let original = ColorImage {
+            size: [2, 2],
+            pixels: (0..4).map(Color32::from_gray).collect(),
+        };
+        let mut image = original.clone();
+
+        rotate_cw(&mut image);
+        assert_eq!(grays(&image), [2, 0, 3, 1]);
+        for _ in 0..3 {
+            rotate_cw(&mut image);
+        }
+        assert_eq!(image, original);
+    }
+
+    #[test]
+    fn test_rotate_ccw_inverts_cw() {
+        let original = ColorImage {
+            size: [3, 3],
+            pixels: (0..9).map(Color32::from_gray).collect(),
+        };
+        let mut image = original.clone();
+        rotate_cw(&mut image);
+        rotate_ccw(&mut image);
+        assert_eq!(image, original);
+    }
+
+    #[test]
+    fn test_rotated_non_square() {
+        let image = distinct();
+        assert_eq!(grays(&rotated_cw(&image)), [3, 0, 4, 1, 5, 2]);
+        assert_eq!(grays(&rotated_ccw(&image)), [2, 5, 1, 4, 0, 3]);
+        assert_eq!(rotated_ccw(&rotated_cw(&image)), image);
+    }
+
+    #[test]
+    fn test_rotate_undo() {
+        let original = ColorImage {
+            size: [3, 3],
+            pixels: (0..9).map(Color32::from_gray).collect(),
+        };
+        let mut image = original.clone();
+        let mut undoer = SparseImageUndoer::new();
+        undoer.new_frame();
+        rotate_cw(&mut undoer.track(&mut image));
+        undoer.undo(&mut image);
+        assert_eq!(image, original);
+    }
+
+    #[test]
+    fn test_flip_single_undo_frame() {
+        let mut image = distinct();
+        let mut undoer = SparseImageUndoer::new();
+        undoer.new_frame();
+        flip_horizontal(&mut undoer.track(&mut image));
+        undoer.undo(&mut image);
+        assert_eq!(image, distinct());
+    }
+
+    #[test]
+    fn test_rotate_growable() {
+        let mut image = Sparse::default();
+        for y in 0..2 {
+            for x in 0..3 {
+                image.set_pixel(x, y, (x + y * 3) as u8 + 1);
+            }
+        }
+        rotate_cw(&mut image);
+
+        assert_eq!(image.image_boundaries(), (0..=2, 0..=2));
+        let rotated: Vec<u8> = [(0, 0), (1, 0), (0, 1), (1, 1), (0, 2), (1, 2)]
+            .map(|(x, y)| image.get_pixel(x, y))
+            .into();
+        assert_eq!(rotated, [4, 1, 5, 2, 6, 3]);
+        // Outside of the rotated area, so cleared
+        assert_eq!(image.get_pixel(2, 0), 0);
+    }
+
+    #[test]
+    fn test_rotate_growable_wide() {
+        let mut image = Sparse::default();
+        for y in 0..2 {
+            for x in 0..4 {
+                image.set_pixel(x, y, (x + y * 4) as u8 + 1);
+            }
+        }
+        assert!(rotate_ccw(&mut image));
+
+        assert_eq!(image.image_boundaries(), (0..=3, 0..=3));
+        let rows: Vec<Vec<u8>> = (0..4)
+            .map(|y| (0..4).map(|x| image.get_pixel(x, y)).collect())
+            .collect();
+        assert_eq!(
+            rows,
+            [[4, 8, 0, 0], [3, 7, 0, 0], [2, 6, 0, 0], [1, 5, 0, 0]]
+        );
+    }
+
+    #[test]
+    fn test_rotate_non_square_fixed() {
+        let mut image = distinct();
+        assert!(!rotate_cw(&mut image));
+        assert_eq!(image, distinct());
+    }
+}
@@ -1,6 +1,8 @@
 use std::{
-    collections::{HashMap, HashSet},
+    cmp::Ordering,
+    collections::{HashMap, HashSet, VecDeque},
     ops::RangeInclusive,
+    time::Duration,
 };
 
 use egui::{
@@ -8,49 +10,300 @@ use egui::{
     Painter, Pos2, Rect, Sense, Stroke, StrokeKind, TextureId, TextureOptions, Ui, Vec2, Widget,
 };
 
-use crate::image::Image;
+use crate::{
+    image::{Image, ImageExt},
+    LayerStack,
+};
+
+/// The changes made during one frame, and what to call it in a history list
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct UndoFrame<Pixel> {
+    label: String,
+    /// The layer of a [`LayerStack`] all of the changes were made to; see
+    /// [`SparseImageUndoer::set_layer`]
+    #[cfg_attr(feature = "serde", serde(default))]
+    layer: usize,
+    pixels: Vec<(isize, isize, Pixel, Pixel)>,
+}
+
+impl<Pixel> UndoFrame<Pixel> {
+    fn new(label: String) -> Self {
+        Self {
+            label,
+            layer: 0,
+            pixels: vec![],
+        }
+    }
+}
 
-type UndoFrame<Pixel> = Vec<(isize, isize, Pixel, Pixel)>;
+/// Callback receiving the coordinates and new value of a changed pixel
+pub type OnChange<'a, Pixel> = dyn FnMut(isize, isize, &Pixel) + 'a;
 
+/// Records the changes made to an image so they can be undone and redone.
+///
+/// With the `serde` feature, the history (and its settings) can be saved along with the
+/// image to resume a session later. It only makes sense with the image as it was when the
+/// history was saved, which [`Self::is_consistent_with`] checks.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SparseImageUndoer<Pixel> {
     /// A list of "frames" of changes to the image.
     /// Each frame corresponds to a continuous mouse movement
-    changes: Vec<UndoFrame<Pixel>>,
+    changes: VecDeque<UndoFrame<Pixel>>,
     redo: Vec<UndoFrame<Pixel>>,
-    /// The maximum number of frames we keep before we start removing history
+    /// The maximum number of frames we keep before we start removing history. The frame
+    /// being recorded is always kept, so 0 keeps one frame, like 1.
     pub max_frames: usize,
+    /// If set, the oldest frames are also dropped until the estimated size of the history
+    /// (see [`Self::history_bytes`]) fits in this many bytes
+    pub max_bytes: Option<usize>,
+    /// If set, a frame started with [`Self::new_frame_at`] within this long of the previous
+    /// one is merged into it, provided the previous frame is no larger than
+    /// `coalesce_max_changes`
+    pub coalesce_window: Option<Duration>,
+    /// The largest frame (in changed pixels) which may still absorb the next frame
+    pub coalesce_max_changes: usize,
+    /// If set, debug builds panic when undo or redo finds a pixel which doesn't hold the
+    /// value the history expects, i.e. the image was changed behind the undoer's back.
+    /// Otherwise this is only logged as a warning, and the pixel is overwritten anyway.
+    pub strict: bool,
+    /// When the last frame was started, in the seconds passed to [`Self::new_frame_at`].
+    /// Cleared on undo/redo so that we never merge across a point where the redo history
+    /// was discarded
+    #[cfg_attr(feature = "serde", serde(skip))]
+    last_frame_start: Option<f64>,
+    /// How many transactions are open. While any are, new frames are suppressed.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    transaction_depth: usize,
+    /// The layer changes are recorded to from now on
+    #[cfg_attr(feature = "serde", serde(default))]
+    layer: usize,
 }
 
 impl<Pixel> SparseImageUndoer<Pixel> {
     pub fn new() -> Self {
         Self {
-            changes: vec![],
+            changes: VecDeque::new(),
             redo: vec![],
             max_frames: 100,
+            max_bytes: None,
+            coalesce_window: None,
+            coalesce_max_changes: 8,
+            strict: true,
+            last_frame_start: None,
+            transaction_depth: 0,
+            layer: 0,
         }
     }
 
     pub fn new_frame(&mut self) {
-        self.changes.push(vec![]);
-        if self.changes.len() > self.max_frames {
-            self.changes.remove(0);
+        self.new_frame_named("");
+    }
+
+    /// Like [`Self::new_frame`], but labels the frame in [`Self::history`]. Never merged
+    /// into the previous frame, since it has no time to go by; see [`Self::new_frame_at`].
+    pub fn new_frame_named(&mut self, label: impl Into<String>) {
+        if self.transaction_depth > 0 {
+            return;
+        }
+        self.last_frame_start = None;
+        self.push_frame(label.into());
+    }
+
+    /// Like [`Self::new_frame_named`], for a frame started at `time` in seconds, e.g.
+    /// egui's `InputState::time`. Merged into the previous frame if that was started
+    /// within [`Self::coalesce_window`] before, in which case it keeps the previous label.
+    pub fn new_frame_at(&mut self, label: impl Into<String>, time: f64) {
+        if self.transaction_depth > 0 {
+            return;
         }
+        let coalesce = match (
+            self.coalesce_window,
+            self.last_frame_start,
+            self.changes.back(),
+        ) {
+            (Some(window), Some(last), Some(frame)) => {
+                time - last <= window.as_secs_f64()
+                    && frame.pixels.len() <= self.coalesce_max_changes
+            }
+            _ => false,
+        };
+        self.last_frame_start = Some(time);
+        if coalesce {
+            return;
+        }
+        self.push_frame(label.into());
+    }
+
+    /// Starts a frame which everything up to the matching [`Self::end_transaction`] goes
+    /// into, however many times [`Self::new_frame`] is called in between, so that it all
+    /// undoes as one step. Transactions may be nested; only the outermost one has an effect.
+    pub fn begin_transaction(&mut self) {
+        if self.transaction_depth == 0 {
+            self.push_frame(String::new());
+        }
+        self.transaction_depth += 1;
+    }
+
+    /// Closes the transaction opened by the last [`Self::begin_transaction`]
+    pub fn end_transaction(&mut self) {
+        self.transaction_depth = self.transaction_depth.saturating_sub(1);
+        // The next frame must not be coalesced into the transaction
+        self.last_frame_start = None;
+    }
+
+    /// Records the changes from now on as made to the layer at `layer` of a [`LayerStack`],
+    /// usually its [`LayerStack::active`] one, so that [`Self::undo_layers`] and
+    /// [`Self::redo_layers`] go back to it. Changes to another layer than those before
+    /// continue in a new frame under the same label. Images with a single layer can leave
+    /// it at 0.
+    pub fn set_layer(&mut self, layer: usize) {
+        self.layer = layer;
+    }
+
+    /// The layer changes are recorded to; see [`Self::set_layer`]
+    pub fn layer(&self) -> usize {
+        self.layer
+    }
+
+    /// The current frame, for changes to [`Self::layer`]. A frame only holds changes to one
+    /// layer, so changes to another one continue in a new frame under the same label.
+    fn current_frame(&mut self) -> &mut UndoFrame<Pixel> {
+        let layer = self.layer;
+        match self.changes.back_mut() {
+            Some(frame) if frame.pixels.is_empty() => frame.layer = layer,
+            Some(frame) if frame.layer == layer => (),
+            frame => {
+                let label = frame.map(|frame| frame.label.clone()).unwrap_or_default();
+                self.push_frame(label);
+                self.changes.back_mut().unwrap().layer = layer;
+            }
+        }
+        self.changes.back_mut().unwrap()
+    }
+
+    fn push_frame(&mut self, label: String) {
+        self.changes.push_back(UndoFrame::new(label));
+        if self.changes.len() > self.max_frames.max(1) {
+            self.changes.pop_front();
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            while self.history_bytes() > max_bytes {
+                self.changes.pop_front();
+            }
+        }
+    }
+
+    /// Estimated memory used by the undo frames, in bytes
+    pub fn history_bytes(&self) -> usize {
+        let entry = std::mem::size_of::<(isize, isize, Pixel, Pixel)>();
+        self.changes
+            .iter()
+            .map(|frame| frame.pixels.len() * entry)
+            .sum()
     }
 
     pub fn set_pixel<I>(&mut self, image: &mut I, x: isize, y: isize, new_px: Pixel)
     where
         I: Image<Pixel = Pixel> + ?Sized,
-        I::Pixel: PartialEq + Copy,
+        I::Pixel: PartialEq + Clone,
     {
-        if self.changes.is_empty() {
-            self.changes.push(Vec::new());
-        }
-        let frame = self.changes.last_mut().unwrap();
+        self.set_pixel_with(image, x, y, new_px, |_, _, _| ());
+    }
 
+    /// Like `set_pixel`, but calls `on_change` just before writing if the pixel changes
+    pub fn set_pixel_with<I>(
+        &mut self,
+        image: &mut I,
+        x: isize,
+        y: isize,
+        new_px: Pixel,
+        on_change: impl FnOnce(isize, isize, &Pixel),
+    ) where
+        I: Image<Pixel = Pixel> + ?Sized,
+        I::Pixel: PartialEq + Clone,
+    {
+        let frame = self.current_frame();
         let old_px = image.get_pixel(x, y);
         if new_px != old_px {
-            frame.push((x, y, old_px, new_px));
-            image.set_pixel(x, y, new_px);
+            on_change(x, y, &new_px);
+            image.set_pixel(x, y, new_px.clone());
+            frame.pixels.push((x, y, old_px, new_px));
+            self.redo.clear();
+        }
+    }
+
+    /// Like `set_pixel_with` for many pixels, extending the current frame in one go and
+    /// passing the changed pixels on to `image.set_pixels`
+    pub fn set_pixels_with<I>(
+        &mut self,
+        image: &mut I,
+        pixels: &mut dyn Iterator<Item = (isize, isize, Pixel)>,
+        mut on_change: impl FnMut(isize, isize, &Pixel),
+    ) where
+        I: Image<Pixel = Pixel> + ?Sized,
+        I::Pixel: PartialEq + Clone,
+    {
+        let frame = self.current_frame();
+        let len_before = frame.pixels.len();
+        let write = |image: &mut I, changes: &[(isize, isize, Pixel, Pixel)]| {
+            image.set_pixels(&mut changes.iter().map(|(x, y, _, new)| (*x, *y, new.clone())));
+        };
+        // Changes from here on are recorded but not yet written. While the pixels come in
+        // row-major order none of them can be read again, so they are written in one
+        // batch; a pixel out of order writes what is pending first, so that its old value
+        // is read after the earlier writes.
+        let mut unwritten = len_before;
+        let mut last = None;
+        for (x, y, new_px) in pixels {
+            if last.is_some_and(|last| (y, x) <= last) {
+                write(image, &frame.pixels[unwritten..]);
+                unwritten = frame.pixels.len();
+            }
+            last = Some((y, x));
+            let old_px = image.get_pixel(x, y);
+            if new_px != old_px {
+                on_change(x, y, &new_px);
+                frame.pixels.push((x, y, old_px, new_px));
+            }
+        }
+        write(image, &frame.pixels[unwritten..]);
+        if frame.pixels.len() != len_before {
+            self.redo.clear();
+        }
+    }
+
+    /// Like `set_pixels_with` for a run of `px` along row `y`, passing each run of pixels
+    /// which change on to `image.fill_row`
+    pub fn fill_row_with<I>(
+        &mut self,
+        image: &mut I,
+        y: isize,
+        x_range: RangeInclusive<isize>,
+        px: Pixel,
+        mut on_change: impl FnMut(isize, isize, &Pixel),
+    ) where
+        I: Image<Pixel = Pixel> + ?Sized,
+        I::Pixel: PartialEq + Clone,
+    {
+        let frame = self.current_frame();
+        let len_before = frame.pixels.len();
+        let mut run_start = None;
+        for x in x_range.clone() {
+            let old_px = image.get_pixel(x, y);
+            if old_px != px {
+                on_change(x, y, &px);
+                frame.pixels.push((x, y, old_px, px.clone()));
+                run_start.get_or_insert(x);
+            } else if let Some(first) = run_start.take() {
+                image.fill_row(y, first..=x - 1, px.clone());
+            }
+        }
+        if let Some(first) = run_start {
+            image.fill_row(y, first..=*x_range.end(), px);
+        }
+        if frame.pixels.len() != len_before {
             self.redo.clear();
         }
     }
@@ -58,23 +311,53 @@ impl<Pixel> SparseImageUndoer<Pixel> {
     pub fn undo<I>(&mut self, image: &mut I)
     where
         I: Image<Pixel = Pixel> + ?Sized,
-        I::Pixel: PartialEq + Copy,
+        I::Pixel: PartialEq + Clone,
+    {
+        self.undo_on(Some(image));
+    }
+
+    /// Like [`Self::undo`] for a [`LayerStack`]: reverts the frame on the layer it was made
+    /// to (see [`Self::set_layer`]), and leaves the active layer as it was
+    pub fn undo_layers(&mut self, stack: &mut LayerStack<Pixel>)
+    where
+        Pixel: PartialEq + Clone,
+    {
+        if let Some(layer) = self.undo_layer() {
+            stack.with_layer(layer, |stack| self.undo_on(stack));
+        }
+    }
+
+    /// The layer of the frame the next undo reverts, if any
+    pub(crate) fn undo_layer(&self) -> Option<usize> {
+        let frames = self.changes.iter().rev();
+        let mut frames = frames.skip_while(|frame| frame.pixels.is_empty());
+        frames.next().map(|frame| frame.layer)
+    }
+
+    /// Reverts the most recent frame on `image`. Without an image, e.g. because the layer
+    /// the frame was made to is gone, the frame only moves to the redo history.
+    pub(crate) fn undo_on<I>(&mut self, mut image: Option<&mut I>)
+    where
+        I: Image<Pixel = Pixel> + ?Sized,
+        I::Pixel: PartialEq + Clone,
     {
+        self.last_frame_start = None;
         let frame = loop {
-            let Some(frame) = self.changes.pop() else {
+            let Some(frame) = self.changes.pop_back() else {
                 return;
             };
-            if !frame.is_empty() {
+            if !frame.pixels.is_empty() {
                 break frame;
             }
         };
 
-        for (x, y, old, new) in frame.iter().rev().copied() {
-            debug_assert!(
-                new == image.get_pixel(x, y),
-                "Undo History did not match canvas!"
-            );
-            image.set_pixel(x, y, old);
+        if let Some(image) = &mut image {
+            for (x, y, old, new) in frame.pixels.iter().rev() {
+                // Pixels the image no longer has are skipped rather than written out of bounds
+                let current = image.get_pixel_checked(*x, *y);
+                self.check_history(current.as_ref() == Some(new), "Undo", *x, *y);
+                image.set_pixel_checked(*x, *y, old.clone());
+            }
         }
 
         self.redo.push(frame);
@@ -83,52 +366,260 @@ impl<Pixel> SparseImageUndoer<Pixel> {
     pub fn redo<I>(&mut self, image: &mut I)
     where
         I: Image<Pixel = Pixel> + ?Sized,
-        I::Pixel: PartialEq + Copy,
+        I::Pixel: PartialEq + Clone,
+    {
+        self.redo_on(Some(image));
+    }
+
+    /// Like [`Self::redo`] for a [`LayerStack`]; see [`Self::undo_layers`]
+    pub fn redo_layers(&mut self, stack: &mut LayerStack<Pixel>)
+    where
+        Pixel: PartialEq + Clone,
+    {
+        if let Some(layer) = self.redo_layer() {
+            stack.with_layer(layer, |stack| self.redo_on(stack));
+        }
+    }
+
+    /// The layer of the frame the next redo reapplies, if any
+    pub(crate) fn redo_layer(&self) -> Option<usize> {
+        self.redo.last().map(|frame| frame.layer)
+    }
+
+    /// Reapplies the most recently undone frame on `image`; see [`Self::undo_on`]
+    pub(crate) fn redo_on<I>(&mut self, mut image: Option<&mut I>)
+    where
+        I: Image<Pixel = Pixel> + ?Sized,
+        I::Pixel: PartialEq + Clone,
     {
+        self.last_frame_start = None;
         let Some(frame) = self.redo.pop() else {
             return;
         };
 
-        for (x, y, old, new) in frame.iter().copied() {
-            debug_assert!(
-                old == image.get_pixel(x, y),
-                "Redo History did not match canvas!"
-            );
-            image.set_pixel(x, y, new);
+        if let Some(image) = &mut image {
+            for (x, y, old, new) in &frame.pixels {
+                let current = image.get_pixel_checked(*x, *y);
+                self.check_history(current.as_ref() == Some(old), "Redo", *x, *y);
+                image.set_pixel_checked(*x, *y, new.clone());
+            }
+        }
+
+        self.changes.push_back(frame);
+    }
+
+    /// Undoes up to `count` frames, stopping early once there are none left. Returns how
+    /// many were undone; each can be redone as usual.
+    pub fn undo_n<I>(&mut self, image: &mut I, count: usize) -> usize
+    where
+        I: Image<Pixel = Pixel> + ?Sized,
+        I::Pixel: PartialEq + Clone,
+    {
+        let count = count.min(self.undo_depth());
+        for _ in 0..count {
+            self.undo(image);
         }
+        count
+    }
+
+    /// Redoes up to `count` frames, stopping early once there are none left. Returns how
+    /// many were redone.
+    pub fn redo_n<I>(&mut self, image: &mut I, count: usize) -> usize
+    where
+        I: Image<Pixel = Pixel> + ?Sized,
+        I::Pixel: PartialEq + Clone,
+    {
+        let count = count.min(self.redo_depth());
+        for _ in 0..count {
+            self.redo(image);
+        }
+        count
+    }
+
+    /// How many times [`Self::undo`] would change something, e.g. for a history slider
+    pub fn undo_depth(&self) -> usize {
+        self.history().count()
+    }
+
+    /// How many times [`Self::redo`] would change something
+    pub fn redo_depth(&self) -> usize {
+        self.redo.len()
+    }
+
+    /// Whether [`Self::undo`] would change anything
+    pub fn can_undo(&self) -> bool {
+        self.changes.iter().any(|frame| !frame.pixels.is_empty())
+    }
+
+    /// The label and number of changed pixels of each frame [`Self::undo`] can revert,
+    /// oldest first, e.g. for a history panel. Unnamed frames have an empty label.
+    pub fn history(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.changes
+            .iter()
+            .filter(|frame| !frame.pixels.is_empty())
+            .map(|frame| (frame.label.as_str(), frame.pixels.len()))
+    }
+
+    /// The pixels changed by the most recent frame [`Self::undo`] can revert, each with
+    /// the last value it was changed to, sorted by row. Writing these to a copy of the image
+    /// as it was before the frame brings it up to date, e.g. to share a stroke over the
+    /// network. If the frame absorbed earlier ones (see [`Self::coalesce_window`]), their
+    /// changes are included.
+    pub fn last_frame_changes(&self) -> Vec<(isize, isize, Pixel)>
+    where
+        Pixel: Clone,
+    {
+        let Some(frame) = self.changes.iter().rev().find(|f| !f.pixels.is_empty()) else {
+            return vec![];
+        };
+        let mut latest = HashMap::new();
+        for (x, y, _, new) in &frame.pixels {
+            latest.insert((*y, *x), new);
+        }
+        let mut changes: Vec<_> = latest
+            .into_iter()
+            .map(|((y, x), px)| (x, y, px.clone()))
+            .collect();
+        changes.sort_unstable_by_key(|&(x, y, _)| (y, x));
+        changes
+    }
+
+    /// Whether every pixel in the history holds the value it was last changed to, and every
+    /// pixel the next redo would change holds the value that redo expects. Use this to check
+    /// a restored history against the image before undoing or redoing with it.
+    pub fn is_consistent_with(&self, image: &impl Image<Pixel = Pixel>) -> bool
+    where
+        Pixel: PartialEq,
+    {
+        // Only the most recent change to each pixel says what it should hold now
+        let mut seen = HashSet::new();
+        let latest = self
+            .changes
+            .iter()
+            .rev()
+            .flat_map(|frame| frame.pixels.iter().rev());
+        let undo_ok = latest
+            .filter(|(x, y, ..)| seen.insert((*x, *y)))
+            .all(|(x, y, _, new)| image.get_pixel_checked(*x, *y).as_ref() == Some(new));
+
+        // Likewise the earliest change to each pixel in the next redo frame
+        let mut seen = HashSet::new();
+        let next_redo = self.redo.last().into_iter().flat_map(|frame| &frame.pixels);
+        let redo_ok = next_redo
+            .filter(|(x, y, ..)| seen.insert((*x, *y)))
+            .all(|(x, y, old, _)| image.get_pixel_checked(*x, *y).as_ref() == Some(old));
+
+        undo_ok && redo_ok
+    }
+
+    /// Whether [`Self::redo`] would change anything
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
 
-        self.changes.push(frame);
+    fn check_history(&self, matches: bool, action: &str, x: isize, y: isize) {
+        if self.strict {
+            debug_assert!(matches, "{action} History did not match canvas!");
+        } else if !matches {
+            log::warn!("{action} history did not match canvas at {x}, {y}");
+        }
     }
 
     pub fn track<'undoer, 'image, I: Image<Pixel = Pixel>>(
         &'undoer mut self,
         image: &'image mut I,
+    ) -> UndoChangeTracker<'image, 'undoer, I> {
+        self.track_with(image, None)
+    }
+
+    /// Like `track`, but calls `on_change` for every pixel which actually changes
+    pub fn track_with<'undoer, 'image, I: Image<Pixel = Pixel>>(
+        &'undoer mut self,
+        image: &'image mut I,
+        on_change: Option<&'undoer mut OnChange<'undoer, Pixel>>,
     ) -> UndoChangeTracker<'image, 'undoer, I> {
         UndoChangeTracker {
             image,
             undoer: self,
+            on_change,
         }
     }
 
-    pub fn reset(&mut self) {
+    /// Forgets all undo and redo history
+    pub fn clear(&mut self) {
         self.changes.clear();
         self.redo.clear();
+        self.last_frame_start = None;
+    }
+
+    /// Forgets the redo history only
+    pub fn clear_redo(&mut self) {
+        self.redo.clear();
+    }
+
+    /// Forgets the changes made to `layer` and renumbers those made to the layers above it,
+    /// along with [`Self::layer`], once it has been removed from the image, e.g. by
+    /// [`LayerStack::remove_layer`]
+    pub fn remove_layer(&mut self, layer: usize) {
+        let keep = |frame: &mut UndoFrame<Pixel>| match frame.layer.cmp(&layer) {
+            Ordering::Less => true,
+            Ordering::Equal => false,
+            Ordering::Greater => {
+                frame.layer -= 1;
+                true
+            }
+        };
+        self.changes.retain_mut(keep);
+        self.redo.retain_mut(keep);
+        if self.layer > layer {
+            self.layer -= 1;
+        }
+    }
+}
+
+impl<Pixel> Default for SparseImageUndoer<Pixel> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 pub struct UndoChangeTracker<'image, 'undoer, I: Image + ?Sized> {
     image: &'image mut I,
     undoer: &'undoer mut SparseImageUndoer<I::Pixel>,
+    on_change: Option<&'undoer mut OnChange<'undoer, I::Pixel>>,
 }
 
 impl<I> Image for UndoChangeTracker<'_, '_, I>
 where
     I: Image + ?Sized,
-    I::Pixel: Copy + PartialEq,
+    I::Pixel: Clone + PartialEq,
 {
     type Pixel = I::Pixel;
     fn set_pixel(&mut self, x: isize, y: isize, px: Self::Pixel) {
-        self.undoer.set_pixel(self.image, x, y, px)
+        match &mut self.on_change {
+            Some(on_change) => self.undoer.set_pixel_with(self.image, x, y, px, on_change),
+            None => self.undoer.set_pixel(self.image, x, y, px),
+        }
+    }
+
+    fn set_pixels(&mut self, pixels: &mut dyn Iterator<Item = (isize, isize, Self::Pixel)>) {
+        match &mut self.on_change {
+            Some(on_change) => self.undoer.set_pixels_with(self.image, pixels, on_change),
+            None => self
+                .undoer
+                .set_pixels_with(self.image, pixels, |_, _, _| ()),
+        }
+    }
+
+    fn fill_row(&mut self, y: isize, x_range: RangeInclusive<isize>, px: Self::Pixel) {
+        match &mut self.on_change {
+            Some(on_change) => self
+                .undoer
+                .fill_row_with(self.image, y, x_range, px, on_change),
+            None => self
+                .undoer
+                .fill_row_with(self.image, y, x_range, px, |_, _, _| ()),
+        }
     }
 
     fn get_pixel(&self, x: isize, y: isize) -> Self::Pixel {
@@ -138,4 +629,421 @@ where
     fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
         self.image.image_boundaries()
     }
+
+    fn set_pixel_out_of_bounds(&self) -> bool {
+        self.image.set_pixel_out_of_bounds()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{Buffer2D, ImageExt};
+    use egui::{Color32, ColorImage};
+
+    /// Clicks `x` seconds in, so that clicks further right are later
+    fn click(undoer: &mut SparseImageUndoer<Color32>, image: &mut ColorImage, x: isize) {
+        undoer.new_frame_at("", x as f64);
+        undoer.set_pixel(image, x, 0, Color32::WHITE);
+    }
+
+    fn stroke(
+        undoer: &mut SparseImageUndoer<Color32>,
+        image: &mut ColorImage,
+        len: isize,
+        px: Color32,
+    ) {
+        undoer.new_frame();
+        for x in 0..len {
+            undoer.set_pixel(image, x, 0, px);
+        }
+    }
+
+    #[test]
+    fn test_set_pixels_batch() {
+        let mut image = ColorImage::new([4, 1], Color32::BLACK);
+        let mut undoer = SparseImageUndoer::new();
+        undoer.new_frame();
+        // Writing the same pixel twice in one batch must still undo cleanly
+        let mut pixels = [
+            (0, 0, Color32::RED),
+            (1, 0, Color32::RED),
+            (0, 0, Color32::BLUE),
+            (2, 0, Color32::BLACK),
+        ]
+        .into_iter();
+        undoer.track(&mut image).set_pixels(&mut pixels);
+        assert_eq!(
+            image.pixels,
+            [Color32::BLUE, Color32::RED, Color32::BLACK, Color32::BLACK]
+        );
+
+        undoer.undo(&mut image);
+        assert_eq!(image, ColorImage::new([4, 1], Color32::BLACK));
+        undoer.redo(&mut image);
+        assert_eq!(image.pixels[0], Color32::BLUE);
+
+        // Nor does writing a pixel back to what it was
+        undoer.new_frame();
+        let mut pixels = [(3, 0, Color32::RED), (3, 0, Color32::BLACK)].into_iter();
+        undoer.track(&mut image).set_pixels(&mut pixels);
+        assert_eq!(image.pixels[3], Color32::BLACK);
+        undoer.undo(&mut image);
+        assert_eq!(image.pixels[3], Color32::BLACK);
+    }
+
+    #[test]
+    fn test_set_pixels_reaches_image() {
+        /// Counts the batches written to it
+        struct Batches(ColorImage, usize);
+        impl Image for Batches {
+            type Pixel = Color32;
+            fn get_pixel(&self, x: isize, y: isize) -> Color32 {
+                self.0.get_pixel(x, y)
+            }
+            fn set_pixel(&mut self, x: isize, y: isize, px: Color32) {
+                self.0.set_pixel(x, y, px);
+            }
+            fn set_pixels(&mut self, pixels: &mut dyn Iterator<Item = (isize, isize, Color32)>) {
+                self.1 += 1;
+                self.0.set_pixels(pixels);
+            }
+            fn image_boundaries(&self) -> (RangeInclusive<isize>, RangeInclusive<isize>) {
+                self.0.image_boundaries()
+            }
+        }
+
+        let mut image = Batches(ColorImage::new([4, 4], Color32::BLACK), 0);
+        let mut undoer = SparseImageUndoer::new();
+        undoer.new_frame();
+        let mut pixels = (0..4).flat_map(|y| (0..4).map(move |x| (x, y, Color32::RED)));
+        undoer.track(&mut image).set_pixels(&mut pixels);
+        assert_eq!(image.1, 1);
+        assert_eq!(image.0, ColorImage::new([4, 4], Color32::RED));
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut image = ColorImage::new([4, 1], Color32::BLACK);
+        let mut undoer = SparseImageUndoer::new();
+        stroke(&mut undoer, &mut image, 4, Color32::RED);
+        stroke(&mut undoer, &mut image, 2, Color32::BLUE);
+        undoer.undo(&mut image);
+
+        undoer.clear();
+        let before = image.clone();
+        undoer.undo(&mut image);
+        assert_eq!(image, before);
+        undoer.redo(&mut image);
+        assert_eq!(image, before);
+    }
+
+    #[test]
+    fn test_fill_rect_is_one_frame() {
+        let mut image = ColorImage::new([4, 4], Color32::BLACK);
+        let mut undoer = SparseImageUndoer::new();
+        stroke(&mut undoer, &mut image, 4, Color32::RED);
+        let before = image.clone();
+
+        undoer.new_frame();
+        undoer
+            .track(&mut image)
+            .fill_rect(2..=9, -3..=1, Color32::BLUE);
+        assert_eq!(image.pixels[3 + 4], Color32::BLUE);
+        assert_eq!(image.pixels[1 + 4], Color32::BLACK);
+
+        undoer.undo(&mut image);
+        assert_eq!(image, before);
+
+        // Pixels already of the fill color are left out of the frame
+        undoer.new_frame();
+        undoer.track(&mut image).fill_row(0, 1..=2, Color32::BLUE);
+        undoer.track(&mut image).fill_row(0, 0..=3, Color32::RED);
+        assert_eq!(undoer.history().last().map(|(_, len)| len), Some(4));
+        undoer.undo(&mut image);
+        assert_eq!(image, before);
+    }
+
+    #[test]
+    fn test_undo_n() {
+        let mut image = ColorImage::new([4, 1], Color32::BLACK);
+        let mut undoer = SparseImageUndoer::new();
+        stroke(&mut undoer, &mut image, 4, Color32::RED);
+        stroke(&mut undoer, &mut image, 3, Color32::GREEN);
+        // An empty frame doesn't count
+        undoer.new_frame();
+        stroke(&mut undoer, &mut image, 2, Color32::BLUE);
+        assert_eq!((undoer.undo_depth(), undoer.redo_depth()), (3, 0));
+
+        assert_eq!(undoer.undo_n(&mut image, 2), 2);
+        assert_eq!(image, ColorImage::new([4, 1], Color32::RED));
+        assert_eq!((undoer.undo_depth(), undoer.redo_depth()), (1, 2));
+
+        // Runs out of history after one more
+        assert_eq!(undoer.undo_n(&mut image, 5), 1);
+        assert_eq!(image, ColorImage::new([4, 1], Color32::BLACK));
+        assert_eq!((undoer.undo_depth(), undoer.redo_depth()), (0, 3));
+
+        assert_eq!(undoer.redo_n(&mut image, 2), 2);
+        assert_eq!(image.pixels[3], Color32::RED);
+        assert_eq!(image.pixels[0], Color32::GREEN);
+        assert_eq!((undoer.undo_depth(), undoer.redo_depth()), (2, 1));
+
+        assert_eq!(undoer.redo_n(&mut image, 5), 1);
+        assert_eq!(image.pixels[0], Color32::BLUE);
+        assert_eq!((undoer.undo_depth(), undoer.redo_depth()), (3, 0));
+    }
+
+    #[test]
+    fn test_clear_redo() {
+        let mut image = ColorImage::new([4, 1], Color32::BLACK);
+        let mut undoer = SparseImageUndoer::new();
+        stroke(&mut undoer, &mut image, 4, Color32::RED);
+        stroke(&mut undoer, &mut image, 2, Color32::BLUE);
+        undoer.undo(&mut image);
+
+        undoer.clear_redo();
+        undoer.redo(&mut image);
+        assert_eq!(image, ColorImage::new([4, 1], Color32::RED));
+        undoer.undo(&mut image);
+        assert_eq!(image, ColorImage::new([4, 1], Color32::BLACK));
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let entry = std::mem::size_of::<(isize, isize, Color32, Color32)>();
+        let mut image = ColorImage::new([10, 1], Color32::BLACK);
+        let mut undoer = SparseImageUndoer::new();
+        undoer.max_bytes = Some(12 * entry);
+
+        stroke(&mut undoer, &mut image, 10, Color32::RED);
+        stroke(&mut undoer, &mut image, 1, Color32::GREEN);
+        assert_eq!(undoer.history_bytes(), 11 * entry);
+
+        // Starting a new frame after going over budget evicts the oldest (largest) frame
+        stroke(&mut undoer, &mut image, 5, Color32::BLUE);
+        assert_eq!(undoer.history_bytes(), 16 * entry);
+        undoer.new_frame();
+        assert_eq!(undoer.history_bytes(), 6 * entry);
+
+        // Both caps apply at once
+        undoer.max_frames = 2;
+        undoer.new_frame();
+        assert_eq!(undoer.history_bytes(), 5 * entry);
+    }
+
+    #[test]
+    fn test_max_frames() {
+        let mut image = ColorImage::new([1, 1], Color32::BLACK);
+        let mut undoer = SparseImageUndoer::new();
+        let total = undoer.max_frames + 10;
+        for i in 1..=total {
+            undoer.new_frame();
+            undoer.set_pixel(&mut image, 0, 0, Color32::from_gray(i as u8));
+        }
+
+        // The newest frames come back in order, down to the oldest one still kept
+        for i in (10..total).rev() {
+            undoer.undo(&mut image);
+            assert_eq!(image.pixels[0], Color32::from_gray(i as u8));
+        }
+        undoer.undo(&mut image);
+        assert_eq!(image.pixels[0], Color32::from_gray(10));
+    }
+
+    #[test]
+    fn test_zero_max_frames() {
+        let mut image = ColorImage::new([1, 1], Color32::BLACK);
+        let mut undoer = SparseImageUndoer::new();
+        undoer.max_frames = 0;
+        for gray in [1, 2] {
+            undoer.new_frame();
+            undoer.set_pixel(&mut image, 0, 0, Color32::from_gray(gray));
+        }
+
+        // Only the frame being recorded is kept
+        assert_eq!(undoer.history().count(), 1);
+        undoer.undo(&mut image);
+        assert_eq!(image.pixels[0], Color32::from_gray(1));
+        assert!(!undoer.can_undo());
+    }
+
+    #[test]
+    fn test_lenient_history() {
+        let mut image = ColorImage::new([2, 1], Color32::BLACK);
+        let mut undoer = SparseImageUndoer::new();
+        undoer.strict = false;
+        stroke(&mut undoer, &mut image, 2, Color32::RED);
+
+        // Changed behind the undoer's back, which is overwritten rather than a panic
+        image.set_pixel(1, 0, Color32::BLUE);
+        undoer.undo(&mut image);
+        assert_eq!(image, ColorImage::new([2, 1], Color32::BLACK));
+        image.set_pixel(0, 0, Color32::GREEN);
+        undoer.redo(&mut image);
+        assert_eq!(image, ColorImage::new([2, 1], Color32::RED));
+
+        // The image shrank, so the pixel which is gone is skipped
+        let mut image = ColorImage::new([1, 1], Color32::RED);
+        undoer.undo(&mut image);
+        assert_eq!(image, ColorImage::new([1, 1], Color32::BLACK));
+    }
+
+    #[test]
+    fn test_transaction() {
+        let mut image = ColorImage::new([2, 1], Color32::BLACK);
+        let mut undoer = SparseImageUndoer::new();
+        stroke(&mut undoer, &mut image, 1, Color32::GREEN);
+
+        undoer.begin_transaction();
+        undoer.new_frame();
+        undoer.track(&mut image).fill_all(Color32::RED);
+        undoer.begin_transaction();
+        undoer.new_frame();
+        undoer.track(&mut image).fill_all(Color32::BLUE);
+        undoer.end_transaction();
+        stroke(&mut undoer, &mut image, 1, Color32::WHITE);
+        undoer.end_transaction();
+
+        undoer.undo(&mut image);
+        assert_eq!(image.pixels, [Color32::GREEN, Color32::BLACK]);
+        undoer.redo(&mut image);
+        assert_eq!(image.pixels, [Color32::WHITE, Color32::BLUE]);
+
+        // Frames are separate again once the transaction is over
+        stroke(&mut undoer, &mut image, 2, Color32::RED);
+        undoer.undo(&mut image);
+        assert_eq!(image.pixels, [Color32::WHITE, Color32::BLUE]);
+    }
+
+    #[test]
+    fn test_history() {
+        let mut image = ColorImage::new([4, 1], Color32::BLACK);
+        let mut undoer = SparseImageUndoer::new();
+        undoer.new_frame_named("Fill");
+        undoer.track(&mut image).fill_all(Color32::RED);
+        // Empty frames are left out
+        undoer.new_frame_named("Nothing");
+        stroke(&mut undoer, &mut image, 2, Color32::BLUE);
+
+        let history: Vec<_> = undoer.history().collect();
+        assert_eq!(history, [("Fill", 4), ("", 2)]);
+        undoer.undo(&mut image);
+        undoer.undo(&mut image);
+        undoer.redo(&mut image);
+        assert_eq!(undoer.history().collect::<Vec<_>>(), [("Fill", 4)]);
+    }
+
+    #[test]
+    fn test_clone_pixels() {
+        // Pixels only need to be `Clone`, e.g. to carry metadata on the heap
+        let mut image = Buffer2D::new(2, 1, String::from("empty"));
+        let mut undoer = SparseImageUndoer::new();
+        undoer.new_frame();
+        undoer.set_pixel(&mut image, 1, 0, String::from("grass"));
+        assert_eq!(image.data, ["empty", "grass"]);
+
+        undoer.undo(&mut image);
+        assert_eq!(image.data, ["empty", "empty"]);
+        undoer.redo(&mut image);
+        assert_eq!(image.data, ["empty", "grass"]);
+    }
+
+    #[test]
+    fn test_last_frame_changes() {
+        let mut image = ColorImage::new([4, 2], Color32::BLACK);
+        let mut undoer = SparseImageUndoer::new();
+        assert!(undoer.last_frame_changes().is_empty());
+
+        click(&mut undoer, &mut image, 3);
+        undoer.new_frame();
+        undoer.set_pixel(&mut image, 2, 1, Color32::RED);
+        undoer.set_pixel(&mut image, 1, 0, Color32::RED);
+        undoer.set_pixel(&mut image, 2, 1, Color32::GREEN);
+        undoer.new_frame();
+
+        // The empty frame is skipped, and only the last value of (2, 1) is kept
+        let expected = [(1, 0, Color32::RED), (2, 1, Color32::GREEN)];
+        assert_eq!(undoer.last_frame_changes(), expected);
+    }
+
+    #[test]
+    fn test_is_consistent_with() {
+        let mut image = ColorImage::new([3, 1], Color32::BLACK);
+        let mut undoer = SparseImageUndoer::new();
+        stroke(&mut undoer, &mut image, 2, Color32::RED);
+        stroke(&mut undoer, &mut image, 1, Color32::BLUE);
+        stroke(&mut undoer, &mut image, 3, Color32::GREEN);
+        undoer.undo(&mut image);
+        assert!(undoer.is_consistent_with(&image));
+
+        // Pixel 1 was last changed to red; pixel 2 would be changed from black by redo
+        let mut changed = image.clone();
+        changed.set_pixel(1, 0, Color32::BLUE);
+        assert!(!undoer.is_consistent_with(&changed));
+        let mut changed = image.clone();
+        changed.set_pixel(2, 0, Color32::WHITE);
+        assert!(!undoer.is_consistent_with(&changed));
+        assert!(!undoer.is_consistent_with(&ColorImage::new([1, 1], Color32::BLUE)));
+    }
+
+    #[test]
+    fn test_coalesce_clicks() {
+        let mut image = ColorImage::new([5, 1], Color32::BLACK);
+        let mut undoer = SparseImageUndoer::new();
+
+        undoer.coalesce_window = Some(Duration::from_millis(1500));
+        click(&mut undoer, &mut image, 0);
+        click(&mut undoer, &mut image, 1);
+        click(&mut undoer, &mut image, 2);
+        // Too long after the previous click
+        click(&mut undoer, &mut image, 4);
+        // Frames without a time are never merged
+        undoer.new_frame_named("");
+        undoer.set_pixel(&mut image, 3, 0, Color32::WHITE);
+
+        undoer.undo(&mut image);
+        undoer.undo(&mut image);
+        assert_eq!(image.pixels[3], Color32::BLACK);
+        assert_eq!(image.pixels[2], Color32::WHITE);
+        undoer.undo(&mut image);
+        assert_eq!(image, ColorImage::new([5, 1], Color32::BLACK));
+    }
+
+    #[test]
+    fn test_no_coalesce_without_window() {
+        let mut image = ColorImage::new([2, 1], Color32::BLACK);
+        let mut undoer = SparseImageUndoer::new();
+
+        click(&mut undoer, &mut image, 0);
+        click(&mut undoer, &mut image, 1);
+
+        undoer.undo(&mut image);
+        assert_eq!(image.pixels, [Color32::WHITE, Color32::BLACK]);
+    }
+
+    #[test]
+    fn test_coalesce_stops_at_undo() {
+        let mut image = ColorImage::new([4, 1], Color32::BLACK);
+        let mut undoer = SparseImageUndoer::new();
+
+        click(&mut undoer, &mut image, 0);
+        click(&mut undoer, &mut image, 1);
+        undoer.coalesce_window = Some(Duration::from_secs(60));
+        undoer.undo(&mut image);
+
+        // These discard the redo history, so they must not join the frame below them
+        click(&mut undoer, &mut image, 2);
+        click(&mut undoer, &mut image, 3);
+        undoer.undo(&mut image);
+        assert_eq!(
+            image.pixels,
+            [
+                Color32::WHITE,
+                Color32::BLACK,
+                Color32::BLACK,
+                Color32::BLACK
+            ]
+        );
+    }
 }